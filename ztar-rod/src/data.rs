@@ -1,3 +1,4 @@
+pub mod area;
 pub mod color;
 pub mod map;
 pub mod yay0;
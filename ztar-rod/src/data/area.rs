@@ -0,0 +1,391 @@
+use crate::rom::loc::{is_vaddr, Dma, Location};
+use crate::rom::*;
+
+// Only the USA rom's tables have been located so far; reading them from
+// other regions fails with `ReadError::UnsupportedRegion`.
+
+/// The main code segment, which holds the area table and the strings it
+/// points to.
+fn main_segment(rom: &Rom) -> Result<Dma, ReadError> {
+    match rom.region {
+        Region::America => Ok(Dma::new(0x1000, 0x759B0, 0x80025C00)),
+        region => Err(ReadError::UnsupportedRegion(region)),
+    }
+}
+
+fn area_table_vaddr(rom: &Rom) -> Result<u32, ReadError> {
+    match rom.region {
+        Region::America => Ok(0x800934F0),
+        region => Err(ReadError::UnsupportedRegion(region)),
+    }
+}
+
+/// Like `Dma::loc_at_vaddr`, but fails rather than panicking if `vaddr` lies
+/// outside of `dma`.
+fn loc_at_vaddr(dma: &Dma, vaddr: u32) -> Result<Location, ReadError> {
+    if dma.contains_vaddr(vaddr) {
+        Ok(dma.loc_at_vaddr(vaddr))
+    } else {
+        Err(ReadError::BadPointer(vaddr))
+    }
+}
+
+/// Size of a single area table entry: map count, map list, name and
+/// description.
+const AREA_SIZE: u32 = 0x10;
+
+/// Size of a single map list entry.
+const MAP_SIZE: u32 = 0x20;
+
+/// Size of a single entrance: position and yaw.
+const ENTRANCE_SIZE: u32 = 0x10;
+
+/// Every area in the game and the maps within it. Only USA roms can be read
+/// for now.
+#[derive(Debug, Clone)]
+pub struct AreaTable {
+    pub areas: Vec<Area>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Area {
+    pub name: AsciiString,
+    pub maps: Vec<Map>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Map {
+    pub name: AsciiString,
+
+    /// Where the map's code and data live, and where they are loaded to.
+    pub dma: Dma,
+
+    /// The map header, which points to the main script, entrances, etc.
+    pub header: Location,
+
+    /// Assembly run when the map is loaded, if any.
+    pub init_asm: Option<Location>,
+
+    /// Name of the background asset, if the map has one.
+    pub background: Option<AsciiString>,
+
+    pub entrances: Vec<Entrance>,
+    pub tattle: Tattle,
+    pub flags: u32,
+}
+
+/// A point at which the player can enter a map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entrance {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub yaw: f32,
+}
+
+/// What Goombario says about a map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tattle {
+    /// A message ID.
+    Message(u32),
+
+    /// A function which returns the message ID to use.
+    Function(Location),
+}
+
+impl Map {
+    /// Location of the map's main script.
+    pub fn main_fun(&self, rom: &mut Rom) -> Result<Location, ReadError> {
+        rom.file.seek(self.header.add_offset(0x10).into())?;
+        loc_at_vaddr(&self.dma, u32::read(rom)?)
+    }
+
+    /// Address of the map's entrance list, which `entrances` was read from.
+    pub fn entrances_vaddr(&self, rom: &mut Rom) -> Result<u32, ReadError> {
+        rom.file.seek(self.header.add_offset(0x14).into())?;
+        u32::read(rom)
+    }
+}
+
+impl RomRead for AreaTable {
+    fn read(rom: &mut Rom) -> Result<Self, ReadError> {
+        AreaTable::read_in(rom, &main_segment(rom)?, area_table_vaddr(rom)?)
+    }
+}
+
+impl AreaTable {
+    /// Reads the table at `vaddr` within `segment`, which must also hold the
+    /// map lists and strings that it points to.
+    fn read_in(rom: &mut Rom, segment: &Dma, vaddr: u32) -> Result<Self, ReadError> {
+        let mut areas = Vec::new();
+        let mut entry_loc = loc_at_vaddr(segment, vaddr)?;
+
+        loop {
+            // The table ends with an empty entry.
+            rom.file.seek(entry_loc.into())?;
+            if u32::read(rom)? == 0 {
+                break;
+            }
+
+            rom.file.seek(entry_loc.into())?;
+            areas.push(Area::read_in(rom, segment)?);
+
+            entry_loc = entry_loc.add_offset(AREA_SIZE);
+        }
+
+        Ok(AreaTable { areas })
+    }
+}
+
+impl RomRead for Area {
+    fn read(rom: &mut Rom) -> Result<Self, ReadError> {
+        Area::read_in(rom, &main_segment(rom)?)
+    }
+}
+
+impl Area {
+    fn read_in(rom: &mut Rom, segment: &Dma) -> Result<Self, ReadError> {
+        let map_count = u32::read(rom)?;
+        let maps_vaddr = u32::read(rom)?;
+        let name_vaddr = u32::read(rom)?;
+
+        let mut maps = Vec::with_capacity(map_count as usize);
+        for i in 0..map_count {
+            rom.file.seek(
+                loc_at_vaddr(segment, maps_vaddr)?
+                    .add_offset(i * MAP_SIZE)
+                    .into(),
+            )?;
+            maps.push(Map::read_in(rom, segment)?);
+        }
+
+        rom.file.seek(loc_at_vaddr(segment, name_vaddr)?.into())?;
+
+        Ok(Area {
+            name: AsciiString::read(rom)?,
+            maps,
+        })
+    }
+}
+
+impl RomRead for Map {
+    fn read(rom: &mut Rom) -> Result<Self, ReadError> {
+        Map::read_in(rom, &main_segment(rom)?)
+    }
+}
+
+impl Map {
+    fn read_in(rom: &mut Rom, segment: &Dma) -> Result<Self, ReadError> {
+        let name_vaddr = u32::read(rom)?;
+        let header_vaddr = u32::read(rom)?;
+        let (start, end, dest) = (u32::read(rom)?, u32::read(rom)?, u32::read(rom)?);
+        let background_vaddr = u32::read(rom)?;
+        let init_asm_vaddr = u32::read(rom)?;
+        let flags = u32::read(rom)?;
+
+        if !is_vaddr(dest) {
+            return Err(ReadError::BadPointer(dest));
+        }
+        if end < start {
+            return Err(ReadError::BadDma(start, end));
+        }
+
+        let dma = Dma::new(start, end, dest);
+        let header = loc_at_vaddr(&dma, header_vaddr)?;
+
+        rom.file.seek(loc_at_vaddr(segment, name_vaddr)?.into())?;
+        let name = AsciiString::read(rom)?;
+
+        let background = match background_vaddr {
+            0 => None,
+            vaddr => {
+                rom.file.seek(loc_at_vaddr(segment, vaddr)?.into())?;
+                Some(AsciiString::read(rom)?)
+            }
+        };
+
+        // Map header
+        rom.file.seek(header.add_offset(0x14).into())?;
+        let entrances_vaddr = u32::read(rom)?;
+        let entrance_count = u32::read(rom)?;
+
+        rom.file.seek(header.add_offset(0x3C).into())?;
+        let tattle = match u32::read(rom)? {
+            vaddr if is_vaddr(vaddr) => Tattle::Function(loc_at_vaddr(&dma, vaddr)?),
+            id => Tattle::Message(id),
+        };
+
+        let mut entrances = Vec::with_capacity(entrance_count as usize);
+        if entrance_count > 0 {
+            let entrances_loc = loc_at_vaddr(&dma, entrances_vaddr)?;
+
+            for i in 0..entrance_count {
+                rom.file
+                    .seek(entrances_loc.add_offset(i * ENTRANCE_SIZE).into())?;
+                entrances.push(Entrance {
+                    x: f32::read(rom)?,
+                    y: f32::read(rom)?,
+                    z: f32::read(rom)?,
+                    yaw: f32::read(rom)?,
+                });
+            }
+        }
+
+        Ok(Map {
+            name,
+            dma,
+            header,
+            init_asm: match init_asm_vaddr {
+                0 => None,
+                vaddr => Some(loc_at_vaddr(&dma, vaddr)?),
+            },
+            background,
+            entrances,
+            tattle,
+            flags,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A segment holding an area table with one area, `kmr`, of one map.
+    const SEGMENT: Dma = Dma {
+        start: 0x1000,
+        end: 0x1400,
+        dest: 0x8010_0000,
+    };
+
+    /// Where the map itself is loaded from and to.
+    const MAP_DMA: Dma = Dma {
+        start: 0x2000,
+        end: 0x2100,
+        dest: 0x8024_0000,
+    };
+
+    fn put_words(rom: &mut Rom, offset: u32, words: &[u32]) {
+        rom.file.seek(SeekFrom::Start(u64::from(offset))).unwrap();
+        for word in words {
+            word.write(rom).unwrap();
+        }
+    }
+
+    fn put_str(rom: &mut Rom, offset: u32, string: &str) {
+        rom.file.seek(SeekFrom::Start(u64::from(offset))).unwrap();
+        AsciiString::from_ascii(string).unwrap().write(rom).unwrap();
+    }
+
+    fn table_rom(name: &str) -> Rom {
+        let mut rom = scratch_rom(name, 0x3000);
+
+        // Area table, then its terminator: map count, map list, name and
+        // description.
+        put_words(&mut rom, 0x1000, &[1, 0x8010_0100, 0x8010_0200, 0]);
+
+        // Map list: name, header, DMA, background, init asm and flags.
+        put_words(
+            &mut rom,
+            0x1100,
+            &[
+                0x8010_0210,
+                0x8024_0000,
+                MAP_DMA.start,
+                MAP_DMA.end,
+                MAP_DMA.dest,
+                0x8010_0220,
+                0x8024_0080,
+                7,
+            ],
+        );
+
+        put_str(&mut rom, 0x1200, "kmr");
+        put_str(&mut rom, 0x1210, "kmr_00");
+        put_str(&mut rom, 0x1220, "kmr_bg");
+
+        // Map header: main script, entrances and their count, tattle.
+        put_words(&mut rom, 0x2010, &[0x8024_0040, 0x8024_0060, 2]);
+        put_words(&mut rom, 0x203C, &[0x0019_0000]);
+
+        let entrances = [1.0f32, 2.0, 3.0, 90.0, -1.0, 0.0, 0.0, 180.0];
+        let words: Vec<u32> = entrances.iter().map(|float| float.to_bits()).collect();
+        put_words(&mut rom, 0x2060, &words);
+
+        rom
+    }
+
+    #[test]
+    fn read_table() {
+        let mut rom = table_rom("read_table");
+        let table = AreaTable::read_in(&mut rom, &SEGMENT, SEGMENT.dest).unwrap();
+
+        assert_eq!(table.areas.len(), 1);
+        assert_eq!(table.areas[0].name.as_str(), "kmr");
+
+        let map = &table.areas[0].maps[0];
+        assert_eq!(map.name.as_str(), "kmr_00");
+        assert_eq!(map.dma, MAP_DMA);
+        assert_eq!(map.header, MAP_DMA.loc_at_offset(0));
+        assert_eq!(map.init_asm, Some(MAP_DMA.loc_at_offset(0x80)));
+        assert_eq!(
+            map.background.as_ref().map(|name| name.as_str()),
+            Some("kmr_bg")
+        );
+        assert_eq!(map.flags, 7);
+        assert_eq!(map.tattle, Tattle::Message(0x0019_0000));
+        assert_eq!(
+            map.entrances,
+            [
+                Entrance {
+                    x: 1.0,
+                    y: 2.0,
+                    z: 3.0,
+                    yaw: 90.0,
+                },
+                Entrance {
+                    x: -1.0,
+                    y: 0.0,
+                    z: 0.0,
+                    yaw: 180.0,
+                },
+            ]
+        );
+        assert_eq!(map.main_fun(&mut rom).unwrap(), MAP_DMA.loc_at_offset(0x40));
+    }
+
+    #[test]
+    fn bad_pointers() {
+        // The map's name lies past the end of the segment.
+        let mut rom = table_rom("bad_pointers");
+        put_words(&mut rom, 0x1100, &[0x8010_0400]);
+
+        match AreaTable::read_in(&mut rom, &SEGMENT, SEGMENT.dest) {
+            Err(ReadError::BadPointer(0x8010_0400)) => (),
+            result => panic!("expected BadPointer, got {:?}", result),
+        }
+
+        // The map's DMA is backwards.
+        let mut rom = table_rom("backwards_dma");
+        put_words(&mut rom, 0x1108, &[0x2100, 0x2000]);
+
+        match AreaTable::read_in(&mut rom, &SEGMENT, SEGMENT.dest) {
+            Err(ReadError::BadDma(0x2100, 0x2000)) => (),
+            result => panic!("expected BadDma, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn other_regions() {
+        let mut rom = table_rom("other_regions");
+
+        for region in [Region::Japan, Region::Europe].iter() {
+            rom.region = *region;
+
+            match AreaTable::read(&mut rom) {
+                Err(ReadError::UnsupportedRegion(r)) if r == *region => (),
+                result => panic!("expected UnsupportedRegion, got {:?}", result),
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use itertools::Itertools;
+use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
 pub struct Palette {
@@ -20,7 +21,7 @@ impl Palette {
         for color in self.colors {
             let short: u16 = color.into_rgba16();
 
-            packed.push(((short & 0xFF00) >> 4) as u8);
+            packed.push((short >> 8) as u8);
             packed.push((short & 0x00FF) as u8);
         }
 
@@ -41,6 +42,63 @@ impl Palette {
         Palette { colors }
     }
 
+    /// Reduces `pixels` to a palette of at most `max_colors` (<= 256) colours,
+    /// returning the palette and each pixel's index into it. Colours are
+    /// compared as they will look once stored as RGBA16, so an image that
+    /// already fits the palette is converted losslessly; otherwise median cut
+    /// is used. All transparent pixels share a single palette entry.
+    pub fn quantize(pixels: &[Color], max_colors: usize) -> (Palette, Vec<u8>) {
+        let keys: Vec<u16> = pixels
+            .iter()
+            .map(|color| match color.clone().into_rgba16() {
+                key if key & 1 == 0 => 0, // Transparent
+                key => key,
+            })
+            .collect();
+
+        let mut histogram: HashMap<u16, usize> = HashMap::new();
+        for key in &keys {
+            *histogram.entry(*key).or_insert(0) += 1;
+        }
+
+        let palette: Vec<u16> = if histogram.len() <= max_colors {
+            histogram.keys().cloned().sorted().collect()
+        } else {
+            let transparent = histogram.contains_key(&0);
+            let opaque: Vec<(u16, usize)> = histogram
+                .iter()
+                .filter(|(key, _)| **key != 0)
+                .map(|(key, count)| (*key, *count))
+                .collect();
+
+            let mut palette = median_cut(opaque, max_colors - transparent as usize);
+            if transparent {
+                palette.push(0);
+            }
+            palette
+        };
+
+        // Map each distinct colour to its closest palette entry.
+        let mut indices: HashMap<u16, u8> = HashMap::new();
+        for key in histogram.keys() {
+            let closest = palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| rgba16_distance(*key, **entry))
+                .map(|(i, _)| i as u8)
+                .unwrap();
+
+            indices.insert(*key, closest);
+        }
+
+        (
+            Palette {
+                colors: palette.into_iter().map(Color::from_rgba16).collect(),
+            },
+            keys.iter().map(|key| indices[key]).collect(),
+        )
+    }
+
     pub fn rgb(&self) -> Vec<u8> {
         let mut packed = Vec::with_capacity(self.colors.len() * 3);
 
@@ -79,9 +137,11 @@ impl Palette {
 
 impl Color {
     pub fn into_rgba16(self) -> u16 {
-        let r = (31.0 * (f32::from(self.r) / 255.0)).trunc() as u16;
-        let g = (31.0 * (f32::from(self.g) / 255.0)).trunc() as u16;
-        let b = (31.0 * (f32::from(self.b) / 255.0)).trunc() as u16;
+        // Round rather than truncate, so that from_rgba16 -> into_rgba16 is
+        // lossless.
+        let r = (31.0 * (f32::from(self.r) / 255.0)).round() as u16;
+        let g = (31.0 * (f32::from(self.g) / 255.0)).round() as u16;
+        let b = (31.0 * (f32::from(self.b) / 255.0)).round() as u16;
         let opaque = self.a > 0x80;
 
         let mut color = if opaque { 1 } else { 0 };
@@ -117,3 +177,138 @@ impl Color {
         }
     }
 }
+
+/// Splits weighted RGBA16 colours into at most `max_colors` boxes, always
+/// cutting the box with the widest channel range at its weighted median, and
+/// returns the (weighted) mean colour of each box.
+fn median_cut(colors: Vec<(u16, usize)>, max_colors: usize) -> Vec<u16> {
+    let channel = |key: u16, channel: usize| (key >> (11 - channel * 5)) & 0x1F;
+    let range = |colors: &[(u16, usize)], c: usize| {
+        let values = colors.iter().map(|(key, _)| channel(*key, c));
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+
+    let mut boxes = vec![colors];
+
+    while boxes.len() < max_colors {
+        // Find the box and channel with the widest range.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(i, colors)| (0..3).map(move |c| (i, c, range(colors, c))))
+            .max_by_key(|(_, _, range)| *range);
+
+        let (i, c) = match widest {
+            Some((i, c, _)) => (i, c),
+            None => break, // Every box is a single colour.
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_by_key(|(key, _)| channel(*key, c));
+
+        let total: usize = colors.iter().map(|(_, count)| count).sum();
+        let mut seen = 0;
+        let mut split = 1;
+        for (j, (_, count)) in colors.iter().enumerate() {
+            seen += count;
+            if seen * 2 >= total {
+                split = j + 1;
+                break;
+            }
+        }
+
+        // Both halves must be non-empty.
+        let split = split.max(1).min(colors.len() - 1);
+
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: usize = colors.iter().map(|(_, count)| count).sum();
+            let mean = |c: usize| {
+                let sum: usize = colors
+                    .iter()
+                    .map(|(key, count)| usize::from(channel(*key, c)) * count)
+                    .sum();
+                ((sum + total / 2) / total) as u16
+            };
+
+            mean(0) << 11 | mean(1) << 6 | mean(2) << 1 | 1
+        })
+        .collect()
+}
+
+fn rgba16_distance(a: u16, b: u16) -> u32 {
+    if (a & 1) != (b & 1) {
+        return u32::max_value();
+    }
+
+    (0..3)
+        .map(|c| {
+            let shift = 11 - c * 5;
+            let a = i32::from((a >> shift) & 0x1F);
+            let b = i32::from((b >> shift) & 0x1F);
+            ((a - b) * (a - b)) as u32
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba16_round_trip() {
+        for s in 0..=u16::max_value() {
+            assert_eq!(Color::from_rgba16(s).into_rgba16(), s, "{:#06X}", s);
+        }
+    }
+
+    #[test]
+    fn quantize_many_colors() {
+        // Every opaque RGBA16 colour, and some transparent pixels.
+        let mut pixels: Vec<Color> = (0..=u16::max_value())
+            .filter(|s| s & 1 == 1)
+            .map(Color::from_rgba16)
+            .collect();
+        pixels.extend((0..100).map(|_| Color::from_rgba32(0x12345600)));
+
+        let (palette, indices) = Palette::quantize(&pixels, 256);
+
+        assert!(palette.colors.len() <= 256);
+        assert_eq!(indices.len(), pixels.len());
+        assert!(indices
+            .iter()
+            .all(|i| usize::from(*i) < palette.colors.len()));
+
+        // The transparent pixels all share an entry, which is transparent.
+        let transparent = indices[pixels.len() - 1];
+        assert!(indices[pixels.len() - 100..]
+            .iter()
+            .all(|i| *i == transparent));
+        assert_eq!(palette.colors[usize::from(transparent)].a, 0);
+    }
+
+    #[test]
+    fn quantize_lossless() {
+        // Few enough colours to fit the palette as they are.
+        let pixels: Vec<Color> = (0..200u16)
+            .map(|n| Color::from_rgba16(n * 327 | 1))
+            .collect();
+
+        let (palette, indices) = Palette::quantize(&pixels, 256);
+
+        assert_eq!(palette.colors.len(), 200);
+        for (pixel, i) in pixels.iter().zip(indices.iter()) {
+            assert_eq!(
+                palette.colors[usize::from(*i)].clone().into_rgba16(),
+                pixel.clone().into_rgba16()
+            );
+        }
+    }
+}
@@ -1,2 +1,4 @@
 pub mod asset_table;
+pub mod background;
+pub mod hit;
 pub mod shape;
@@ -1,10 +1,13 @@
+use failure_derive::*;
 use itertools::Itertools;
-use png::HasParameters;
-use std::convert::TryInto;
+use std::collections::HashMap;
 use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 
-use super::shape::Shape;
-use crate::data::color::Palette;
+use super::background::{Background, PngError};
+use super::hit::Hit;
+use super::shape::{ImportError, Shape};
 use crate::data::yay0;
 use crate::mod_dir::ModDir;
 use crate::rom::*;
@@ -17,50 +20,117 @@ fn asset_table_addr(rom: &Rom) -> u32 {
     }
 }
 
+/// Name of the entry that terminates the table.
+static END_DATA: &str = "end_data";
+
+/// Size of a single table entry: name, data offset, compressed size and
+/// decompressed size.
+const ENTRY_SIZE: u32 = 28;
+
 pub struct AssetTable {
     assets: Vec<Asset>,
 }
 
+#[derive(Debug, Fail)]
+pub enum BuildError {
+    #[fail(display = "unable to read {}: {}", _0, _1)]
+    Io(String, #[fail(cause)] std::io::Error),
+
+    #[fail(display = "bad asset table entry: '{}'", _0)]
+    BadEntry(String),
+
+    #[fail(display = "bad background {}: {}", _0, _1)]
+    Background(String, #[fail(cause)] PngError),
+
+    #[fail(display = "bad model {}: {}", _0, _1)]
+    Shape(String, #[fail(cause)] ImportError),
+}
+
+#[derive(Debug, Fail)]
+pub enum DumpError {
+    #[fail(display = "{}", _0)]
+    Io(#[fail(cause)] std::io::Error),
+
+    #[fail(display = "unable to write background {}: {}", _0, _1)]
+    Background(String, #[fail(cause)] PngError),
+}
+
+impl From<std::io::Error> for DumpError {
+    fn from(error: std::io::Error) -> DumpError {
+        DumpError::Io(error)
+    }
+}
+
 impl AssetTable {
-    pub fn dump(self, mod_dir: &ModDir) -> Result<(), std::io::Error> {
+    pub fn dump(self, mod_dir: &ModDir) -> Result<(), DumpError> {
+        // One asset per line, followed by "yay0" if it should be compressed.
         fs::write(
             mod_dir.asset_table(),
             self.assets
                 .iter()
-                .map(|asset| format!("{}", asset.name))
+                .map(|asset| {
+                    if asset.is_compressed() {
+                        format!("{} yay0", asset.name)
+                    } else {
+                        format!("{}", asset.name)
+                    }
+                })
                 .join("\n"),
         )?;
 
+        // Collision is exported with the names from the map's shape.
+        let names: HashMap<String, (Vec<String>, Vec<String>)> = self
+            .assets
+            .iter()
+            .filter_map(|asset| match &asset.data {
+                AssetData::Shape { shape, .. } => Some((
+                    asset.name.as_str().trim_end_matches("_shape").to_string(),
+                    (shape.collider_names.clone(), shape.zone_names.clone()),
+                )),
+                _ => None,
+            })
+            .collect();
+
         for asset in self.assets {
             match asset.data {
-                AssetData::Background {
-                    width,
-                    height,
-                    raster,
-                    palette,
-                } => {
+                AssetData::Background { background } => {
                     println!("dumping background: {}", asset.name);
 
                     let filename = mod_dir.background(asset.name.as_str());
                     let file = File::create(filename)?;
 
-                    let mut encoder = png::Encoder::new(file, u32::from(width), u32::from(height));
-                    encoder
-                        .set(png::ColorType::Indexed)
-                        .set(png::BitDepth::Eight);
-                    let mut writer = encoder.write_header().unwrap();
-
-                    writer
-                        .write_chunk(png::chunk::PLTE, &palette.1.rgb()[..])
-                        .unwrap();
-                    writer
-                        .write_chunk(png::chunk::tRNS, &palette.1.alpha()[..])
-                        .unwrap();
-                    writer.write_image_data(&raster.1).unwrap();
+                    let name = &asset.name;
+                    background
+                        .write_png(file)
+                        .map_err(|error| DumpError::Background(name.to_string(), error))?;
                 }
 
-                AssetData::Shape { shape } => {
-                    // TODO
+                AssetData::Shape { shape, bytes } => {
+                    println!("dumping shape: {}", asset.name);
+
+                    let file = File::create(mod_dir.shape(asset.name.as_str(), "glb"))?;
+                    shape.write_glb(BufWriter::new(file))?;
+
+                    // Unless the model is edited, the shape is rebuilt from its
+                    // original data; re-encoding a dumped shape isn't lossless.
+                    fs::write(mod_dir.built_asset(asset.name.as_str()), &bytes)?;
+                }
+
+                AssetData::Hit { hit, bytes } => {
+                    println!("dumping collision: {}", asset.name);
+
+                    let map = asset.name.as_str().trim_end_matches("_hit");
+                    let (collider_names, zone_names) = match names.get(map) {
+                        Some((colliders, zones)) => (&colliders[..], &zones[..]),
+                        None => (&[][..], &[][..]),
+                    };
+
+                    let file = File::create(mod_dir.hit(asset.name.as_str()))?;
+                    hit.write_obj(BufWriter::new(file), collider_names, zone_names)?;
+
+                    // Collision is only exported, so it is rebuilt from the
+                    // original data.
+                    fs::write(mod_dir.built_asset(asset.name.as_str()), &bytes)?;
                 }
 
                 AssetData::Unknown { bytes } => {
@@ -71,16 +141,210 @@ impl AssetTable {
 
         Ok(())
     }
+
+    /// Reads back the assets listed in `mod_dir`'s asset table, re-encoding
+    /// those we understand and compressing those marked as such. Data offsets
+    /// and sizes are recalculated; write the result with `RomWrite`.
+    pub fn build(mod_dir: &ModDir) -> Result<AssetTable, BuildError> {
+        let path = mod_dir.asset_table();
+        let table = fs::read_to_string(&path)
+            .map_err(|error| BuildError::Io(path.display().to_string(), error))?;
+
+        // One entry per line: the asset name, optionally followed by "yay0".
+        let mut entries = Vec::new();
+        for line in table.lines() {
+            let mut words = line.split_whitespace();
+
+            let name = match words.next() {
+                Some(name) if name != END_DATA => name,
+                _ => continue,
+            };
+            let compress = match words.next() {
+                Some("yay0") => true,
+                None => false,
+                _ => return Err(BuildError::BadEntry(line.to_string())),
+            };
+
+            entries.push((name, compress, line));
+        }
+
+        let mut assets = Vec::with_capacity(entries.len());
+
+        // Asset data begins after the table and its terminator.
+        let mut data_offset = align((entries.len() as u32 + 1) * ENTRY_SIZE, 8);
+
+        for (name, compress, line) in entries {
+            let name = AsciiString::from_ascii(name)
+                .map_err(|_| BuildError::BadEntry(line.to_string()))?;
+
+            println!("building asset: {}", name);
+
+            // Backgrounds that couldn't be dumped as images are in `build/`.
+            let png_path = mod_dir.background(name.as_str());
+
+            let data = if name.as_str().ends_with("_bg") && png_path.exists() {
+                let png = fs::read(&png_path)
+                    .map_err(|error| BuildError::Io(png_path.display().to_string(), error))?;
+
+                AssetData::Background {
+                    background: Background::from_png(&png)
+                        .map_err(|error| BuildError::Background(name.to_string(), error))?,
+                }
+            } else if let Some(path) = edited_shape(mod_dir, name.as_str()) {
+                let shape = Shape::import(&path)
+                    .map_err(|error| BuildError::Shape(path.display().to_string(), error))?;
+
+                AssetData::Shape {
+                    bytes: shape.to_bytes(),
+                    shape,
+                }
+            } else {
+                let path = mod_dir.built_asset(name.as_str());
+
+                AssetData::Unknown {
+                    bytes: fs::read(&path)
+                        .map_err(|error| BuildError::Io(path.display().to_string(), error))?,
+                }
+            };
+
+            let bytes = data.to_bytes();
+            let packed = if compress {
+                yay0::compress(&bytes)
+            } else {
+                bytes.clone()
+            };
+
+            assets.push(Asset {
+                name,
+                data_offset,
+                compressed_size: packed.len() as u32,
+                decompressed_size: bytes.len() as u32,
+                data,
+                packed,
+            });
+
+            data_offset = align(data_offset + assets.last().unwrap().compressed_size, 8);
+        }
+
+        Ok(AssetTable { assets })
+    }
+}
+
+/// Finds the model a shape should be imported from: one that is newer than
+/// the shape's data in `build/`, if there is one.
+///
+/// Dumping writes `build/` after the models, so a model only counts as edited
+/// once it has been saved again since. Anything that rewrites modification
+/// times wholesale (copying the mod without preserving them, some version
+/// control checkouts) can make a model look edited or not when it isn't;
+/// deleting a shape's file from `build/` always forces it to be imported.
+fn edited_shape(mod_dir: &ModDir, name: &str) -> Option<PathBuf> {
+    if !name.ends_with("_shape") {
+        return None;
+    }
+
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let built = modified(&mod_dir.built_asset(name));
+
+    ["glb", "gltf", "obj"]
+        .iter()
+        .map(|extension| mod_dir.shape(name, extension))
+        .find(|path| match (modified(path), built) {
+            (Some(model), Some(built)) => model > built,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+}
+
+/// The size of the table already in the rom, data included, which is all the
+/// space a rebuilt one may use.
+fn table_capacity(rom: &mut Rom) -> Result<u32, std::io::Error> {
+    rom.file
+        .seek(SeekFrom::Start(u64::from(asset_table_addr(rom))))?;
+
+    let mut capacity = 0;
+    let mut entry = [0u8; ENTRY_SIZE as usize];
+    loop {
+        rom.file.read_exact(&mut entry)?;
+
+        let word =
+            |i: usize| u32::from_be_bytes([entry[i], entry[i + 1], entry[i + 2], entry[i + 3]]);
+        capacity = capacity.max(word(16).saturating_add(word(20)));
+
+        // The terminator's offset marks the end of the data.
+        if entry[0] == 0 || entry.starts_with(END_DATA.as_bytes()) {
+            break;
+        }
+    }
+
+    Ok(capacity)
+}
+
+impl RomWrite for AssetTable {
+    fn write(&self, rom: &mut Rom) -> Result<(), WriteError> {
+        let table_addr = asset_table_addr(rom);
+
+        // The terminator points at the end of the data.
+        let end_offset = self
+            .assets
+            .iter()
+            .map(|asset| asset.data_offset + asset.compressed_size)
+            .max()
+            .unwrap_or_else(|| align((self.assets.len() as u32 + 1) * ENTRY_SIZE, 8));
+
+        // Whatever follows the table in the rom mustn't be overwritten.
+        let capacity = table_capacity(rom)?;
+        if end_offset > capacity {
+            return Err(WriteError::TooLarge(
+                "asset table".to_string(),
+                end_offset,
+                capacity,
+            ));
+        }
+
+        rom.file.seek(SeekFrom::Start(u64::from(table_addr)))?;
+
+        for asset in &self.assets {
+            asset.name.write_len(rom, 16)?;
+            asset.data_offset.write(rom)?;
+            asset.compressed_size.write(rom)?;
+            asset.decompressed_size.write(rom)?;
+        }
+
+        AsciiString::from_ascii(END_DATA)
+            .unwrap()
+            .write_len(rom, 16)?;
+        end_offset.write(rom)?;
+        0u32.write(rom)?;
+        0u32.write(rom)?;
+
+        for asset in &self.assets {
+            rom.file
+                .seek(SeekFrom::Start(u64::from(table_addr + asset.data_offset)))?;
+            rom.file.write_all(&asset.packed)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl RomRead for AssetTable {
     fn read(rom: &mut Rom) -> Result<Self, ReadError> {
         let mut assets = Vec::new();
+        let mut entry_addr = asset_table_addr(rom);
 
-        for i in 0..1033 {
-            rom.file
-                .seek(SeekFrom::Start(u64::from(asset_table_addr(rom) + i * 28)))?;
+        loop {
+            // Stop at the terminator (or an empty entry, should it be missing).
+            rom.file.seek(SeekFrom::Start(u64::from(entry_addr)))?;
+            let name = AsciiString::read_len(rom, 16)?;
+            if name.is_empty() || name.as_str() == END_DATA {
+                break;
+            }
+
+            rom.file.seek(SeekFrom::Start(u64::from(entry_addr)))?;
             assets.push(Asset::read(rom)?);
+
+            entry_addr += ENTRY_SIZE;
         }
 
         Ok(AssetTable { assets })
@@ -93,18 +357,45 @@ pub struct Asset {
     compressed_size: u32,
     decompressed_size: u32,
     data: AssetData,
+
+    /// The data exactly as it is stored in the ROM.
+    packed: Vec<u8>,
+}
+
+impl Asset {
+    /// Whether the asset is stored compressed. Assets whose compression is
+    /// corrupt are kept as they are, so don't count.
+    pub fn is_compressed(&self) -> bool {
+        match &self.data {
+            AssetData::Unknown { bytes } if *bytes == self.packed => false,
+            _ => is_yay0(&self.packed),
+        }
+    }
+}
+
+fn is_yay0(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[0..4] == yay0::MAGIC.to_be_bytes()
 }
 
 pub enum AssetData {
     Background {
-        width: u16,
-        height: u16,
-        raster: (u32, Vec<u8>),
-        palette: (u32, Palette),
+        background: Background,
     },
 
     Shape {
         shape: Shape,
+
+        /// The shape's binary form. Re-encoding a dumped shape isn't lossless,
+        /// so the original data is kept rather than re-encoded.
+        bytes: Vec<u8>,
+    },
+
+    Hit {
+        hit: Hit,
+
+        /// Collision can't be re-encoded yet, so we hold on to the original
+        /// data.
+        bytes: Vec<u8>,
     },
 
     Unknown {
@@ -114,94 +405,214 @@ pub enum AssetData {
 
 impl RomRead for Asset {
     fn read(rom: &mut Rom) -> Result<Self, ReadError> {
-        // TODO yay0 decompression
-
         let name = AsciiString::read_len(rom, 16)?;
         let data_offset = u32::read(rom)?;
         let compressed_size = u32::read(rom)?;
         let decompressed_size = u32::read(rom)?;
 
-        let bytes = {
-            rom.file.seek(SeekFrom::Start(u64::from(
-                asset_table_addr(rom) + data_offset,
-            )))?;
+        // Make sure the entry describes data that actually exists.
+        let data_addr = u64::from(asset_table_addr(rom)) + u64::from(data_offset);
+        if data_addr + u64::from(compressed_size) > rom.file.metadata()?.len() {
+            return Err(ReadError::BadAssetOffset(name, data_offset));
+        }
+
+        let packed = {
+            rom.file.seek(SeekFrom::Start(data_addr))?;
 
-            // If the data is compressed, uncompress it.
-            if u32::read(rom)? == yay0::MAGIC {
-                println!("decompressing asset: {}", name);
-                let yay0_size = u32::read(rom)?;
-                assert_eq!(yay0_size, decompressed_size);
+            let mut packed = vec![0u8; compressed_size as usize];
+            rom.file.read_exact(&mut packed).or(Err(ReadError::Eof))?;
+            packed
+        };
 
-                rom.file.seek(SeekFrom::Start(u64::from(
-                    asset_table_addr(rom) + data_offset,
-                )))?;
-                let mut buf = vec![0u8; decompressed_size as usize];
-                rom.file.read_exact(&mut buf).or(Err(ReadError::Eof))?;
+        // If the data is compressed, uncompress it. Should that fail, the
+        // asset is carried over still compressed.
+        let bytes = if is_yay0(&packed) {
+            println!("decompressing asset: {}", name);
 
-                yay0::decompress(&buf)
-            } else {
-                rom.file.seek(SeekFrom::Start(u64::from(
-                    asset_table_addr(rom) + data_offset,
-                )))?;
-                let mut buf = vec![0u8; decompressed_size as usize];
-                rom.file.read_exact(&mut buf).or(Err(ReadError::Eof))?;
-                buf
+            match decompress(&packed, decompressed_size as usize) {
+                Ok(decoded) => decoded,
+                Err(error) => {
+                    eprintln!("warning: {}", ReadError::BadAsset(name.clone(), error));
+                    return Ok(Asset {
+                        data: AssetData::Unknown {
+                            bytes: packed.clone(),
+                        },
+                        name,
+                        data_offset,
+                        compressed_size,
+                        decompressed_size,
+                        packed,
+                    });
+                }
             }
+        } else {
+            packed.clone()
         };
 
-        Ok(Asset {
-            data: if name.as_str().ends_with("_bg") {
-                // Image header
-                let raster_addr = read_u32(&bytes[0..4]).wrapping_add(0x7FE00000);
-                let palette_addr = read_u32(&bytes[4..8]).wrapping_add(0x7FE00000);
-                // bytes[8..12]?
-                let width = read_u16(&bytes[12..14]);
-                let height = read_u16(&bytes[14..16]);
+        // Assets we fail to understand are still carried over, untouched.
+        let parsed = if name.as_str().ends_with("_bg") {
+            Background::parse(&bytes).map(|background| AssetData::Background { background })
+        } else if name.as_str().ends_with("_shape") {
+            Shape::parse(bytes.clone()).map(|shape| AssetData::Shape {
+                shape,
+                bytes: bytes.clone(),
+            })
+        } else if name.as_str().ends_with("_hit") {
+            Hit::parse(&bytes).map(|hit| AssetData::Hit {
+                hit,
+                bytes: bytes.clone(),
+            })
+        } else {
+            Ok(AssetData::Unknown {
+                bytes: bytes.clone(),
+            })
+        };
 
-                AssetData::Background {
-                    width,
-                    height,
-                    raster: (raster_addr, {
-                        // 8bpp
-                        let raster_addr = raster_addr as usize;
-                        let raster_len = width as usize * height as usize;
-                        bytes[raster_addr..raster_addr + raster_len].to_vec()
-                    }),
-                    palette: (palette_addr, {
-                        let palette_addr = palette_addr as usize;
-
-                        // 256-color RGBA16
-                        Palette::from_rgba16(&bytes[palette_addr..palette_addr + 256 * 2])
-                    }),
-                }
-            } else if name.as_str().ends_with("_shape") {
-                AssetData::Shape {
-                    shape: Shape::parse(bytes)?,
-                }
-            } else {
+        let data = match parsed {
+            Ok(data) => data,
+            Err(error) => {
+                eprintln!("warning: unable to parse asset {}: {}", name, error);
                 AssetData::Unknown { bytes }
-            },
+            }
+        };
 
+        Ok(Asset {
+            data,
             name,
             data_offset,
             compressed_size,
             decompressed_size,
+            packed,
         })
     }
 }
 
-fn read_u16(bytes: &[u8]) -> u16 {
-    u16::from_be_bytes(
-        bytes
-            .try_into()
-            .expect("unexpected end-of-data while reading u16"),
-    )
+/// Decompresses an asset, checking that its Yay0 header agrees with the size
+/// the table gives before making room for it.
+fn decompress(packed: &[u8], expected: usize) -> Result<Vec<u8>, yay0::Yay0Error> {
+    let header = yay0::decompressed_size(packed)?;
+    if header != expected {
+        return Err(yay0::Yay0Error::SizeMismatch { header, expected });
+    }
+
+    let mut decoded = vec![0u8; header];
+    let len = yay0::decompress_into(packed, &mut decoded)?;
+    decoded.truncate(len);
+    Ok(decoded)
+}
+
+/// The (decompressed) data of one of the USA rom's assets; see `test_rom`.
+#[cfg(test)]
+pub fn test_asset(name: &str) -> Vec<u8> {
+    AssetTable::read(&mut test_rom())
+        .unwrap()
+        .assets
+        .into_iter()
+        .find(|asset| asset.name.as_str() == name)
+        .expect("no such asset")
+        .data
+        .to_bytes()
+}
+
+impl AssetData {
+    /// Encodes the asset back into its (uncompressed) binary form.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            AssetData::Background { background } => background.to_bytes(),
+            AssetData::Shape { bytes, .. } => bytes.clone(),
+            AssetData::Hit { bytes, .. } => bytes.clone(),
+            AssetData::Unknown { bytes } => bytes.clone(),
+        }
+    }
+}
+
+fn align(offset: u32, alignment: u32) -> u32 {
+    (offset + alignment - 1) / alignment * alignment
 }
 
-fn read_u32(bytes: &[u8]) -> u32 {
-    u32::from_be_bytes(
-        bytes
-            .try_into()
-            .expect("unexpected end-of-data while reading u32"),
-    )
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A rom holding just an asset table of `assets`, each given as its name,
+    /// stored data and decompressed size.
+    fn table_rom(name: &str, assets: &[(&str, Vec<u8>, u32)]) -> Rom {
+        let mut rom = scratch_rom(name, 0x1E50000);
+        let table_addr = asset_table_addr(&rom);
+        let mut data_offset = align((assets.len() as u32 + 1) * ENTRY_SIZE, 8);
+
+        for (i, (name, packed, decompressed_size)) in assets.iter().enumerate() {
+            rom.file
+                .seek(SeekFrom::Start(u64::from(
+                    table_addr + i as u32 * ENTRY_SIZE,
+                )))
+                .unwrap();
+            AsciiString::from_ascii(*name)
+                .unwrap()
+                .write_len(&mut rom, 16)
+                .unwrap();
+            data_offset.write(&mut rom).unwrap();
+            (packed.len() as u32).write(&mut rom).unwrap();
+            decompressed_size.write(&mut rom).unwrap();
+
+            rom.file
+                .seek(SeekFrom::Start(u64::from(table_addr + data_offset)))
+                .unwrap();
+            rom.file.write_all(packed).unwrap();
+            data_offset = align(data_offset + packed.len() as u32, 8);
+        }
+
+        rom.file
+            .seek(SeekFrom::Start(u64::from(
+                table_addr + assets.len() as u32 * ENTRY_SIZE,
+            )))
+            .unwrap();
+        AsciiString::from_ascii(END_DATA)
+            .unwrap()
+            .write_len(&mut rom, 16)
+            .unwrap();
+
+        rom
+    }
+
+    #[test]
+    fn corrupt_assets_are_kept() {
+        let data = vec![0xAA; 64];
+        let good = yay0::compress(&data);
+
+        // The first command is a back-reference, to before the start.
+        let mut corrupt = good.clone();
+        corrupt[16] = 0;
+
+        let mut rom = table_rom(
+            "corrupt_assets_are_kept",
+            &[
+                ("good", good.clone(), 64),
+                ("corrupt", corrupt.clone(), 64),
+                ("mismatched", good.clone(), 0x7FFF_FFFF),
+            ],
+        );
+        let table = AssetTable::read(&mut rom).unwrap();
+
+        let summary: Vec<(&str, Vec<u8>, bool)> = table
+            .assets
+            .iter()
+            .map(|asset| {
+                (
+                    asset.name.as_str(),
+                    asset.data.to_bytes(),
+                    asset.is_compressed(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                ("good", data, true),
+                ("corrupt", corrupt, false),
+                ("mismatched", good, false),
+            ]
+        );
+    }
 }
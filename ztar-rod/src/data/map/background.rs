@@ -0,0 +1,289 @@
+use failure_derive::*;
+use png::HasParameters;
+use std::convert::TryInto;
+use std::io::{self, Write};
+
+use crate::data::color::{Color, Palette};
+
+/// Backgrounds are loaded to this address; the pointers in their header are
+/// relative to it.
+const BASE_ADDR: u32 = 0x80200000;
+
+const HEADER_SIZE: usize = 0x10;
+const PALETTE_LEN: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct Background {
+    /// Screen position the background is drawn at.
+    pub x: u16,
+    pub y: u16,
+
+    pub width: u16,
+    pub height: u16,
+
+    /// 8bpp colour indices into `palette`, row by row.
+    pub raster: Vec<u8>,
+
+    /// 256-colour palette, stored as RGBA16.
+    pub palette: Palette,
+}
+
+#[derive(Debug, Fail)]
+pub enum PngError {
+    #[fail(display = "{}", _0)]
+    Decoding(#[fail(cause)] png::DecodingError),
+
+    #[fail(display = "{}", _0)]
+    Encoding(#[fail(cause)] png::EncodingError),
+
+    #[fail(display = "unsupported PNG format: {:?}, {:?}", _0, _1)]
+    UnsupportedFormat(png::ColorType, png::BitDepth),
+
+    #[fail(display = "{}x{} is too large for a background", _0, _1)]
+    TooLarge(u32, u32),
+}
+
+impl From<png::DecodingError> for PngError {
+    fn from(error: png::DecodingError) -> PngError {
+        PngError::Decoding(error)
+    }
+}
+
+impl From<png::EncodingError> for PngError {
+    fn from(error: png::EncodingError) -> PngError {
+        PngError::Encoding(error)
+    }
+}
+
+impl Background {
+    pub fn parse(bytes: &[u8]) -> io::Result<Background> {
+        // Image header
+        let raster_addr = read_u32(bytes, 0)?.wrapping_sub(BASE_ADDR) as usize;
+        let palette_addr = read_u32(bytes, 4)?.wrapping_sub(BASE_ADDR) as usize;
+        let x = read_u16(bytes, 8)?;
+        let y = read_u16(bytes, 10)?;
+        let width = read_u16(bytes, 12)?;
+        let height = read_u16(bytes, 14)?;
+
+        // 8bpp
+        let raster_len = width as usize * height as usize;
+        let raster = slice(bytes, raster_addr, raster_len)?.to_vec();
+
+        // 256-color RGBA16
+        let palette = Palette::from_rgba16(slice(bytes, palette_addr, PALETTE_LEN * 2)?);
+
+        Ok(Background {
+            x,
+            y,
+            width,
+            height,
+            raster,
+            palette,
+        })
+    }
+
+    /// Encodes this background into the layout `parse` reads: the header,
+    /// followed by the raster, followed by the palette.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let raster_addr = HEADER_SIZE;
+        let palette_addr = align(raster_addr + self.raster.len(), 8);
+
+        let mut bytes = Vec::with_capacity(palette_addr + PALETTE_LEN * 2);
+        bytes.extend_from_slice(&(BASE_ADDR + raster_addr as u32).to_be_bytes());
+        bytes.extend_from_slice(&(BASE_ADDR + palette_addr as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.x.to_be_bytes());
+        bytes.extend_from_slice(&self.y.to_be_bytes());
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+
+        bytes.extend_from_slice(&self.raster);
+        bytes.resize(palette_addr, 0);
+
+        let mut palette = self.palette.clone();
+        palette.colors.resize(
+            PALETTE_LEN,
+            Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0,
+            },
+        );
+        bytes.extend_from_slice(&palette.into_rgba16());
+
+        bytes
+    }
+
+    /// Writes this background as an 8-bit indexed PNG. The screen position is
+    /// kept in an `oFFs` chunk so that `from_png` can restore it.
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), PngError> {
+        let mut encoder = png::Encoder::new(w, u32::from(self.width), u32::from(self.height));
+        encoder
+            .set(png::ColorType::Indexed)
+            .set(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+
+        let mut offs = Vec::with_capacity(9);
+        offs.extend_from_slice(&i32::from(self.x).to_be_bytes());
+        offs.extend_from_slice(&i32::from(self.y).to_be_bytes());
+        offs.push(0); // Unit: pixels
+
+        writer.write_chunk(*b"oFFs", &offs)?;
+        writer.write_chunk(png::chunk::PLTE, &self.palette.rgb()[..])?;
+        writer.write_chunk(png::chunk::tRNS, &self.palette.alpha()[..])?;
+        writer.write_image_data(&self.raster)?;
+
+        Ok(())
+    }
+
+    /// Reads a background from a PNG. 8-bit indexed PNGs (such as those written
+    /// by `write_png`) are used as-is; anything else is quantized down to a
+    /// 256-colour palette.
+    pub fn from_png(bytes: &[u8]) -> Result<Background, PngError> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info()?;
+
+        let (width, height) = match (info.width.try_into(), info.height.try_into()) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(PngError::TooLarge(info.width, info.height)),
+        };
+
+        let (raster, palette) = if info.color_type == png::ColorType::Indexed
+            && info.bit_depth == png::BitDepth::Eight
+        {
+            let mut raster = vec![0u8; info.buffer_size()];
+            reader.next_frame(&mut raster)?;
+
+            let info = reader.info();
+            let rgb = info.palette.as_ref().map(Vec::as_slice).unwrap_or(&[]);
+            let alpha = info.trns.as_ref().map(Vec::as_slice).unwrap_or(&[]);
+
+            let palette = Palette {
+                colors: rgb
+                    .chunks(3)
+                    .enumerate()
+                    .map(|(i, rgb)| Color {
+                        r: rgb[0],
+                        g: rgb[1],
+                        b: rgb[2],
+                        a: alpha.get(i).cloned().unwrap_or(0xFF),
+                    })
+                    .collect(),
+            };
+
+            (raster, palette)
+        } else {
+            let (palette, raster) = Palette::quantize(&read_truecolor(bytes)?, PALETTE_LEN);
+            (raster, palette)
+        };
+
+        let (x, y) = read_offs(bytes).unwrap_or((0, 0));
+
+        Ok(Background {
+            x,
+            y,
+            width,
+            height,
+            raster,
+            palette,
+        })
+    }
+}
+
+/// Decodes any non-indexed (or low bit-depth indexed) PNG into 8-bit RGBA.
+fn read_truecolor(bytes: &[u8]) -> Result<Vec<Color>, PngError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+
+    let mut buf = vec![0u8; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let pixels = match (info.color_type, info.bit_depth) {
+        (png::ColorType::RGBA, png::BitDepth::Eight) => buf
+            .chunks(4)
+            .map(|p| Color {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+                a: p[3],
+            })
+            .collect(),
+        (png::ColorType::RGB, png::BitDepth::Eight) => buf
+            .chunks(3)
+            .map(|p| Color {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+                a: 0xFF,
+            })
+            .collect(),
+        (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight) => buf
+            .chunks(2)
+            .map(|p| Color {
+                r: p[0],
+                g: p[0],
+                b: p[0],
+                a: p[1],
+            })
+            .collect(),
+        (png::ColorType::Grayscale, png::BitDepth::Eight) => buf
+            .iter()
+            .map(|p| Color {
+                r: *p,
+                g: *p,
+                b: *p,
+                a: 0xFF,
+            })
+            .collect(),
+        (color_type, bit_depth) => return Err(PngError::UnsupportedFormat(color_type, bit_depth)),
+    };
+
+    Ok(pixels)
+}
+
+/// Finds the `oFFs` chunk of a PNG file, if it has one. The png crate skips
+/// over chunks it doesn't know, so we walk the chunk list ourselves.
+fn read_offs(png: &[u8]) -> Option<(u16, u16)> {
+    let mut offset = 8; // Signature
+
+    while let Ok(len) = read_u32(png, offset) {
+        let kind = png.get(offset + 4..offset + 8)?;
+        let data = png.get(offset + 8..offset + 8 + len as usize)?;
+
+        match kind {
+            b"oFFs" if data.len() == 9 => {
+                let x = read_u32(data, 0).ok()? as i32;
+                let y = read_u32(data, 4).ok()? as i32;
+                return Some((x.try_into().ok()?, y.try_into().ok()?));
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => offset += 12 + len as usize, // Length, type, data, CRC
+        }
+    }
+
+    None
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    bytes
+        .get(offset..offset + len)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    Ok(u16::from_be_bytes(
+        slice(bytes, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from_be_bytes(
+        slice(bytes, offset, 4)?.try_into().unwrap(),
+    ))
+}
@@ -0,0 +1,440 @@
+use std::convert::TryInto;
+use std::io::{self, Write};
+
+/// Size of a collider in the collider list.
+const COLLIDER_SIZE: usize = 0x0C;
+
+/// Size of a vertex (`Vec3s`) in the vertex list.
+const VERTEX_SIZE: usize = 6;
+
+/// Collision geometry of a map: solid colliders, and the zones that control
+/// the camera. Each is a tree of triangle meshes, indexed in the same order as
+/// the shape's collider and zone name lists.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub colliders: Option<Section>,
+    pub zones: Option<Section>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub colliders: Vec<Collider>,
+    pub vertices: Vec<[i16; 3]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Collider {
+    pub bounding_box: Option<BoundingBox>,
+
+    /// Indices into the section's collider list.
+    pub next_sibling: Option<usize>,
+    pub first_child: Option<usize>,
+
+    pub triangles: Vec<Triangle>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BoundingBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+
+    /// Surface type and other per-collider flags.
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    /// Indices into the section's vertex list.
+    pub vertices: [usize; 3],
+
+    /// Whether the triangle only collides from the front.
+    pub one_sided: bool,
+}
+
+impl Hit {
+    pub fn parse(bytes: &[u8]) -> io::Result<Hit> {
+        // Header
+        let colliders = read_u32(bytes, 0)? as usize;
+        let zones = read_u32(bytes, 4)? as usize;
+
+        Ok(Hit {
+            colliders: match colliders {
+                0 => None,
+                offset => Some(Section::parse(bytes, offset)?),
+            },
+            zones: match zones {
+                0 => None,
+                offset => Some(Section::parse(bytes, offset)?),
+            },
+        })
+    }
+
+    /// Writes the colliders and zones as a Wavefront OBJ file, with a group per
+    /// collider or zone. Groups are named after their path in the tree, using
+    /// the names from the map's shape; flags are noted in comments.
+    pub fn write_obj<W: Write>(
+        &self,
+        mut w: W,
+        collider_names: &[String],
+        zone_names: &[String],
+    ) -> io::Result<()> {
+        let sections = [
+            (&self.colliders, collider_names, "collider"),
+            (&self.zones, zone_names, "zone"),
+        ];
+
+        // Every section's vertices first, since OBJ indices are global.
+        let mut first_vertex = Vec::new();
+        let mut vertex_count = 0;
+
+        for (section, _, kind) in &sections {
+            first_vertex.push(vertex_count);
+
+            if let Some(section) = section {
+                writeln!(w, "# {} vertices", kind)?;
+                for [x, y, z] in &section.vertices {
+                    writeln!(w, "v {} {} {}", x, y, z)?;
+                }
+
+                vertex_count += section.vertices.len();
+            }
+        }
+
+        for ((section, names, kind), first_vertex) in sections.iter().zip(first_vertex) {
+            if let Some(section) = section {
+                for (index, path) in section.paths(names, kind) {
+                    let collider = &section.colliders[index];
+
+                    writeln!(w)?;
+                    writeln!(w, "g {}", path)?;
+
+                    if let Some(bounding_box) = &collider.bounding_box {
+                        writeln!(w, "# flags {:08X}", bounding_box.flags)?;
+                    }
+
+                    for triangle in &collider.triangles {
+                        let [a, b, c] = triangle.vertices;
+
+                        // OBJ indices start at 1.
+                        let offset = first_vertex + 1;
+                        writeln!(w, "f {} {} {}", a + offset, b + offset, c + offset)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Section {
+    fn parse(bytes: &[u8], offset: usize) -> io::Result<Section> {
+        let collider_count = read_u16(bytes, offset)? as usize;
+        let collider_list = read_u32(bytes, offset + 0x04)? as usize;
+        let vertex_count = read_u16(bytes, offset + 0x08)? as usize;
+        let vertex_list = read_u32(bytes, offset + 0x0C)? as usize;
+        let bounding_box_list = read_u32(bytes, offset + 0x14)? as usize;
+
+        let vertices = (0..vertex_count)
+            .map(|i| {
+                let offset = vertex_list + i * VERTEX_SIZE;
+
+                Ok([
+                    read_u16(bytes, offset)? as i16,
+                    read_u16(bytes, offset + 2)? as i16,
+                    read_u16(bytes, offset + 4)? as i16,
+                ])
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let colliders = (0..collider_count)
+            .map(|i| {
+                let offset = collider_list + i * COLLIDER_SIZE;
+
+                let bounding_box = read_u16(bytes, offset)? as i16;
+                let next_sibling = read_u16(bytes, offset + 0x02)? as i16;
+                let first_child = read_u16(bytes, offset + 0x04)? as i16;
+                let triangle_count = read_u16(bytes, offset + 0x06)? as usize;
+                let triangle_list = read_u32(bytes, offset + 0x08)? as usize;
+
+                let index = |index: i16| match index {
+                    -1 => Ok(None),
+                    index if index >= 0 && (index as usize) < collider_count => {
+                        Ok(Some(index as usize))
+                    }
+                    _ => Err(invalid_data("collider index out of range")),
+                };
+
+                let triangles = (0..triangle_count)
+                    .map(|i| {
+                        // Three 10-bit vertex indices, then the one-sided bit.
+                        let packed = read_u32(bytes, triangle_list + i * 4)?;
+                        let vertices = [
+                            (packed & 0x3FF) as usize,
+                            (packed >> 10 & 0x3FF) as usize,
+                            (packed >> 20 & 0x3FF) as usize,
+                        ];
+
+                        if vertices.iter().any(|vertex| *vertex >= vertex_count) {
+                            return Err(invalid_data("vertex index out of range"));
+                        }
+
+                        Ok(Triangle {
+                            vertices,
+                            one_sided: packed >> 30 & 1 != 0,
+                        })
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+
+                Ok(Collider {
+                    // The bounding box offset is in words.
+                    bounding_box: match bounding_box {
+                        -1 => None,
+                        words => Some(BoundingBox::parse(
+                            bytes,
+                            bounding_box_list + words as usize * 4,
+                        )?),
+                    },
+                    next_sibling: index(next_sibling)?,
+                    first_child: index(first_child)?,
+                    triangles,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Section {
+            colliders,
+            vertices,
+        })
+    }
+
+    /// Walks the tree from its first collider, pairing each collider's index
+    /// with its path: the names of its ancestors and itself, joined with `/`.
+    fn paths(&self, names: &[String], kind: &str) -> Vec<(usize, String)> {
+        let mut paths = Vec::with_capacity(self.colliders.len());
+        let mut visited = vec![false; self.colliders.len()];
+
+        // (index, parent path)
+        let mut stack = Vec::new();
+        if !self.colliders.is_empty() {
+            stack.push((0, String::new()));
+        }
+
+        while let Some((index, parent)) = stack.pop() {
+            // Guard against cycles in corrupt trees.
+            if visited[index] {
+                continue;
+            }
+            visited[index] = true;
+
+            let name = match names.get(index) {
+                Some(name) => name.clone(),
+                None => format!("{}_{}", kind, index),
+            };
+            let path = if parent.is_empty() {
+                name
+            } else {
+                format!("{}/{}", parent, name)
+            };
+
+            let collider = &self.colliders[index];
+            if let Some(sibling) = collider.next_sibling {
+                stack.push((sibling, parent));
+            }
+            if let Some(child) = collider.first_child {
+                stack.push((child, path.clone()));
+            }
+
+            paths.push((index, path));
+        }
+
+        // Anything unreachable from the root still gets exported.
+        for (index, visited) in visited.into_iter().enumerate() {
+            if !visited {
+                let name = names.get(index).cloned();
+                paths.push((index, name.unwrap_or_else(|| format!("{}_{}", kind, index))));
+            }
+        }
+
+        paths
+    }
+}
+
+impl BoundingBox {
+    fn parse(bytes: &[u8], offset: usize) -> io::Result<BoundingBox> {
+        let float = |i: usize| read_u32(bytes, offset + i * 4).map(f32::from_bits);
+
+        Ok(BoundingBox {
+            min: [float(0)?, float(1)?, float(2)?],
+            max: [float(3)?, float(4)?, float(5)?],
+            flags: read_u32(bytes, offset + 0x18)?,
+        })
+    }
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    bytes
+        .get(offset..offset + len)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    Ok(u16::from_be_bytes(
+        slice(bytes, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from_be_bytes(
+        slice(bytes, offset, 4)?.try_into().unwrap(),
+    ))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::map::asset_table::test_asset;
+
+    /// A collider section of two colliders, the second inside the first, with
+    /// a triangle each.
+    fn fixture() -> Vec<u8> {
+        let mut bytes = vec![0u8; 0x88];
+        let mut put = |offset: usize, data: &[u8]| {
+            bytes[offset..offset + data.len()].copy_from_slice(data);
+        };
+
+        // Header: colliders, no zones.
+        put(0x00, &0x10u32.to_be_bytes());
+
+        // Section: collider count and list, vertex count and list, bounding
+        // box list.
+        put(0x10, &2u16.to_be_bytes());
+        put(0x14, &0x28u32.to_be_bytes());
+        put(0x18, &4u16.to_be_bytes());
+        put(0x1C, &0x40u32.to_be_bytes());
+        put(0x24, &0x58u32.to_be_bytes());
+
+        // Colliders: bounding box, next sibling, first child, triangle count
+        // and list.
+        for (i, fields) in [[0, -1, 1, 1], [-1, -1, -1, 1]].iter().enumerate() {
+            let offset = 0x28 + i * COLLIDER_SIZE;
+            for (j, field) in fields.iter().enumerate() {
+                put(offset + j * 2, &(*field as i16).to_be_bytes());
+            }
+            put(offset + 0x08, &(0x80 + i as u32 * 4).to_be_bytes());
+        }
+
+        for (i, vertex) in [[0, 0, 0], [100, 0, 0], [0, 0, -100], [-5, 10, 20]]
+            .iter()
+            .enumerate()
+        {
+            for (j, coord) in vertex.iter().enumerate() {
+                put(
+                    0x40 + i * VERTEX_SIZE + j * 2,
+                    &(*coord as i16).to_be_bytes(),
+                );
+            }
+        }
+
+        for (i, float) in [-1.0f32, -2.0, -3.0, 1.0, 2.0, 3.0].iter().enumerate() {
+            put(0x58 + i * 4, &float.to_bits().to_be_bytes());
+        }
+        put(0x58 + 0x18, &0x0001_0002u32.to_be_bytes());
+
+        put(0x80, &(1u32 << 10 | 2 << 20 | 1 << 30).to_be_bytes());
+        put(0x84, &(1u32 | 3 << 10 | 2 << 20).to_be_bytes());
+
+        bytes
+    }
+
+    #[test]
+    fn parse() {
+        let hit = Hit::parse(&fixture()).unwrap();
+        assert!(hit.zones.is_none());
+
+        let section = hit.colliders.unwrap();
+        assert_eq!(section.vertices[3], [-5, 10, 20]);
+        assert_eq!(section.colliders.len(), 2);
+
+        let root = &section.colliders[0];
+        let bounding_box = root.bounding_box.as_ref().unwrap();
+        assert_eq!(bounding_box.min, [-1.0, -2.0, -3.0]);
+        assert_eq!(bounding_box.max, [1.0, 2.0, 3.0]);
+        assert_eq!(bounding_box.flags, 0x0001_0002);
+        assert_eq!(root.next_sibling, None);
+        assert_eq!(root.first_child, Some(1));
+        assert_eq!(root.triangles[0].vertices, [0, 1, 2]);
+        assert!(root.triangles[0].one_sided);
+
+        let child = &section.colliders[1];
+        assert!(child.bounding_box.is_none());
+        assert_eq!(child.first_child, None);
+        assert_eq!(child.triangles[0].vertices, [1, 3, 2]);
+        assert!(!child.triangles[0].one_sided);
+    }
+
+    #[test]
+    fn bad_indices() {
+        // A triangle using a fifth vertex.
+        let mut bytes = fixture();
+        bytes[0x84..0x88].copy_from_slice(&(4u32 | 3 << 10 | 2 << 20).to_be_bytes());
+        assert!(Hit::parse(&bytes).is_err());
+
+        // A child that isn't in the list.
+        let mut bytes = fixture();
+        bytes[0x2C..0x2E].copy_from_slice(&2u16.to_be_bytes());
+        assert!(Hit::parse(&bytes).is_err());
+
+        // Truncated data.
+        assert!(Hit::parse(&fixture()[..0x60]).is_err());
+    }
+
+    #[test]
+    fn write_obj() {
+        let hit = Hit::parse(&fixture()).unwrap();
+        let names = ["floor".to_string(), "step".to_string()];
+
+        let mut obj = Vec::new();
+        hit.write_obj(&mut obj, &names, &[]).unwrap();
+
+        assert_eq!(
+            String::from_utf8(obj).unwrap(),
+            concat!(
+                "# collider vertices\n",
+                "v 0 0 0\n",
+                "v 100 0 0\n",
+                "v 0 0 -100\n",
+                "v -5 10 20\n",
+                "\n",
+                "g floor\n",
+                "# flags 00010002\n",
+                "f 1 2 3\n",
+                "\n",
+                "g floor/step\n",
+                "f 2 4 3\n",
+            )
+        );
+
+        // Colliders without a name are named by index.
+        let mut obj = Vec::new();
+        hit.write_obj(&mut obj, &[], &[]).unwrap();
+        assert!(String::from_utf8(obj)
+            .unwrap()
+            .contains("g collider_0/collider_1\n"));
+    }
+
+    #[test]
+    #[ignore]
+    fn real_hit() {
+        let hit = Hit::parse(&test_asset("kmr_00_hit")).unwrap();
+        assert!(hit.colliders.is_some());
+
+        let mut obj = Vec::new();
+        hit.write_obj(&mut obj, &[], &[]).unwrap();
+        assert!(String::from_utf8(obj).unwrap().contains("\nf "));
+    }
+}
@@ -1,44 +1,1056 @@
-// TODO
-
-use std::io::prelude::*;
-use std::io::{self, Cursor, SeekFrom};
+use failure_derive::*;
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
 
 use crate::data::color::Color;
 
+mod gltf;
+mod obj;
+
+/// Shapes are loaded to this address; every pointer within them is relative
+/// to it.
 const BASE_ADDR: u32 = 0x80210000;
 
-#[derive(Debug)]
-pub struct Shape {}
+/// Guards against reference cycles in corrupt model trees.
+const MAX_TREE_DEPTH: usize = 64;
+
+/// Guards against display lists that are missing their G_ENDDL.
+const MAX_DISPLAY_LIST_LEN: usize = 0x10000;
+
+/// Size of a vertex (`Vtx_t`) in the vertex table.
+const VERTEX_SIZE: usize = 16;
+
+/// Number of vertices the RSP's vertex buffer holds at once.
+const VERTEX_BUFFER_LEN: usize = 32;
+
+/// Texture coordinates are s10.5 fixed-point texels. Texture sizes aren't
+/// known here, so imported and exported UVs are in texels, not normalised.
+const TEXEL_SCALE: f64 = 32.0;
+
+/// Room for the five header pointers.
+const HEADER_SIZE: usize = 0x20;
+
+// Model node types.
+const NODE_MODEL: u32 = 2;
+const NODE_GROUP: u32 = 5;
+const NODE_ROOT: u32 = 7;
+const NODE_SPECIAL_GROUP: u32 = 10;
+
+// Property types.
+const PROPERTY_INT: u32 = 0;
+const PROPERTY_FLOAT: u32 = 1;
+const PROPERTY_STRING: u32 = 2;
+
+/// Property key holding the name of the texture a model uses.
+pub const PROPERTY_TEXTURE_NAME: u32 = 0x5E;
+
+// F3DEX2 display list opcodes.
+const G_VTX: u8 = 0x01;
+const G_TRI1: u8 = 0x05;
+const G_TRI2: u8 = 0x06;
+const G_ENDDL: u8 = 0xDF;
+
+/// The visual geometry of a map: a tree of models and groups, plus the name
+/// lists that the map's scripts (models) and collision (colliders and zones)
+/// refer to by index.
+#[derive(Debug, Clone)]
+pub struct Shape {
+    pub root: Node,
+
+    /// Every vertex the display lists in the tree load from.
+    pub vertices: Vec<Vertex>,
+
+    pub model_names: Vec<String>,
+    pub collider_names: Vec<String>,
+    pub zone_names: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+
+    /// Name from the model name list; empty if the list is too short.
+    pub name: String,
+
+    pub properties: Vec<Property>,
+
+    /// Only models have geometry.
+    pub display_list: Option<DisplayList>,
+
+    /// Only groups (and the root) have children.
+    pub group: Option<Group>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Model,
+    Group,
+    Root,
+    SpecialGroup,
+    Unknown(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Group {
+    /// Applied to every child of the group.
+    pub transform: Option<Matrix>,
+
+    pub lights: Option<Lights>,
+
+    pub children: Vec<Node>,
+}
+
+/// 4x4 transform matrix in the RSP's s15.16 fixed-point format, row-major.
+/// Vectors are multiplied on the left (row vectors), so the translation is in
+/// the bottom row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix(pub [[i32; 4]; 4]);
+
+#[derive(Debug, Clone)]
+pub struct Lights {
+    pub ambient: Color,
+    pub directional: Vec<Light>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Light {
+    pub color: Color,
+    pub direction: [i8; 3],
+}
 
-#[derive(Debug)]
-struct Vertex {
-    x: i16,
-    y: i16,
-    z: i16,
+#[derive(Debug, Clone)]
+pub struct Property {
+    pub key: u32,
+    pub value: PropertyValue,
+}
 
-    texture_x: i16,
-    texture_y: i16,
+#[derive(Debug, Clone)]
+pub enum PropertyValue {
+    Int(i32),
+    Float(f32),
+    String(String),
 
-    color: Color,
+    /// A property type we don't understand, kept as-is.
+    Unknown {
+        kind: u32,
+        data: u32,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DisplayList(pub Vec<Gfx>);
+
+/// A single F3DEX2 display list command. Commands we don't need to understand
+/// are kept as raw words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gfx {
+    /// Loads `count` vertices from the shape's vertex table, starting at
+    /// `first`, into the RSP's vertex buffer at `buffer_index`.
+    Vertex {
+        first: usize,
+        count: u8,
+        buffer_index: u8,
+    },
+
+    /// One triangle, as vertex buffer indices.
+    Tri1([u8; 3]),
+
+    /// Two triangles, as vertex buffer indices.
+    Tri2([u8; 3], [u8; 3]),
+
+    End,
+
+    Raw(u32, u32),
+}
+
+#[derive(Debug, Fail)]
+pub enum ImportError {
+    #[fail(display = "{}", _0)]
+    Io(#[fail(cause)] io::Error),
+
+    #[fail(display = "{}", _0)]
+    Json(#[fail(cause)] serde_json::Error),
+
+    #[fail(display = "bad glTF: {}", _0)]
+    Gltf(String),
+
+    #[fail(display = "bad OBJ, line {}: {}", _0, _1)]
+    Obj(usize, String),
+
+    #[fail(display = "unsupported model format: {}", _0)]
+    UnsupportedFormat(String),
+}
+
+impl From<io::Error> for ImportError {
+    fn from(error: io::Error) -> ImportError {
+        ImportError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(error: serde_json::Error) -> ImportError {
+        ImportError::Json(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Vertex {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+
+    pub texture_x: i16,
+    pub texture_y: i16,
+
+    pub color: Color,
 }
 
 impl Shape {
     pub fn parse(data: Vec<u8>) -> io::Result<Shape> {
-        let mut data = Cursor::new(data);
+        let mut parser = Parser {
+            data: &data,
+            vertex_table: 0,
+            vertex_count: 0,
+        };
 
         // Header
-        let mesh_tree = read_u32(&mut data) - BASE_ADDR;
-        let vertex_table = read_u32(&mut data) - BASE_ADDR;
-        let model_name_list = read_u32(&mut data) - BASE_ADDR;
-        let collider_name_list = read_u32(&mut data) - BASE_ADDR;
-        let zone_name_list = read_u32(&mut data).checked_sub(BASE_ADDR);
+        let root_addr = parser.read_u32(0x00)?;
+        parser.vertex_table = parser.offset(parser.read_u32(0x04)?)?;
+        let model_name_list = parser.read_u32(0x08)?;
+        let collider_name_list = parser.read_u32(0x0C)?;
+        let zone_name_list = parser.read_u32(0x10)?;
+
+        let mut root = parser.parse_node(root_addr, 0)?;
+
+        let vertices = (0..parser.vertex_count)
+            .map(|i| parser.parse_vertex(parser.vertex_table + i * VERTEX_SIZE))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // Nodes are named in the order the game indexes them: children before
+        // their parents.
+        let mut node_count = 0;
+        root.visit_post_order(&mut |_| node_count += 1);
+
+        let model_names = parser.parse_name_list(model_name_list, Some(node_count))?;
+
+        let mut names = model_names.iter();
+        root.visit_post_order_mut(&mut |node| {
+            node.name = names.next().cloned().unwrap_or_default();
+        });
+
+        Ok(Shape {
+            root,
+            vertices,
+            model_names,
+            collider_names: parser.parse_name_list(collider_name_list, None)?,
+            zone_names: parser.parse_name_list(zone_name_list, None)?,
+        })
+    }
+
+    /// Reads a shape from a model file, picking the format from its extension:
+    /// glTF 2.0 (`.glb` or `.gltf`) or Wavefront OBJ (`.obj`).
+    pub fn import(path: &Path) -> Result<Shape, ImportError> {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("glb") | Some("gltf") => Shape::from_gltf(path),
+            Some("obj") => Shape::from_obj(&fs::read_to_string(path)?),
+            _ => Err(ImportError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+
+    /// Builds a shape around `root`, naming the model name list after the
+    /// nodes in the tree.
+    fn from_root(
+        root: Node,
+        vertices: Vec<Vertex>,
+        collider_names: Vec<String>,
+        zone_names: Vec<String>,
+    ) -> Shape {
+        let mut model_names = Vec::new();
+        root.visit_post_order(&mut |node| model_names.push(node.name.clone()));
+
+        Shape {
+            root,
+            vertices,
+            model_names,
+            collider_names,
+            zone_names,
+        }
+    }
+
+    /// Encodes the shape into the layout `parse` reads, as it would be loaded
+    /// at `BASE_ADDR`: the header, the vertex table, then the model tree
+    /// (children before their parents), then the name lists.
+    ///
+    /// Raw display list commands are written as-is, so any that point into
+    /// the shape will no longer be correct if the layout has changed.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer {
+            bytes: vec![0; HEADER_SIZE],
+            vertex_table: 0,
+        };
+
+        writer.vertex_table = writer.addr();
+        for vertex in &self.vertices {
+            writer.write_vertex(vertex);
+        }
+
+        let root = writer.write_node(&self.root);
+        let model_names = writer.write_name_list(&self.model_names);
+        let collider_names = writer.write_name_list(&self.collider_names);
+        let zone_names = writer.write_name_list(&self.zone_names);
+
+        let vertex_table = writer.vertex_table;
+        for (i, addr) in [root, vertex_table, model_names, collider_names, zone_names]
+            .iter()
+            .enumerate()
+        {
+            writer.bytes[i * 4..i * 4 + 4].copy_from_slice(&addr.to_be_bytes());
+        }
+
+        writer.bytes
+    }
+
+    /// Names of every texture referenced by a model in the tree.
+    pub fn texture_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+
+        self.root.visit_post_order(&mut |node| {
+            if let Some(name) = node.texture_name() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        });
+
+        names
+    }
+}
+
+impl Node {
+    pub fn texture_name(&self) -> Option<&str> {
+        self.properties
+            .iter()
+            .find_map(|property| match (property.key, &property.value) {
+                (PROPERTY_TEXTURE_NAME, PropertyValue::String(name)) => Some(name.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match &self.group {
+            Some(group) => &group.children,
+            None => &[],
+        }
+    }
+
+    /// Calls `f` for this node and every node below it, children first.
+    pub fn visit_post_order<'a, F: FnMut(&'a Node)>(&'a self, f: &mut F) {
+        for child in self.children() {
+            child.visit_post_order(f);
+        }
+
+        f(self);
+    }
+
+    pub fn visit_post_order_mut<F: FnMut(&mut Node)>(&mut self, f: &mut F) {
+        if let Some(group) = &mut self.group {
+            for child in group.children.iter_mut() {
+                child.visit_post_order_mut(f);
+            }
+        }
+
+        f(self);
+    }
+}
+
+impl NodeKind {
+    fn from(kind: u32) -> NodeKind {
+        match kind {
+            NODE_MODEL => NodeKind::Model,
+            NODE_GROUP => NodeKind::Group,
+            NODE_ROOT => NodeKind::Root,
+            NODE_SPECIAL_GROUP => NodeKind::SpecialGroup,
+            kind => NodeKind::Unknown(kind),
+        }
+    }
+
+    pub fn id(self) -> u32 {
+        match self {
+            NodeKind::Model => NODE_MODEL,
+            NodeKind::Group => NODE_GROUP,
+            NodeKind::Root => NODE_ROOT,
+            NodeKind::SpecialGroup => NODE_SPECIAL_GROUP,
+            NodeKind::Unknown(kind) => kind,
+        }
+    }
+}
+
+impl Matrix {
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.0[row][col] as f32 / 65536.0
+    }
+
+    /// Converts to fixed-point, saturating elements that are out of range.
+    pub fn from_f64(elements: [[f64; 4]; 4]) -> Matrix {
+        let mut matrix = [[0i32; 4]; 4];
+
+        for (row, elements) in matrix.iter_mut().zip(elements.iter()) {
+            for (element, value) in row.iter_mut().zip(elements.iter()) {
+                *element = (value * 65536.0)
+                    .round()
+                    .max(f64::from(i32::min_value()))
+                    .min(f64::from(i32::max_value())) as i32;
+            }
+        }
+
+        Matrix(matrix)
+    }
+}
+
+impl DisplayList {
+    /// Builds a display list drawing `triangles`, which index into
+    /// `vertices`. Triangles are split into batches whose vertices fit in the
+    /// vertex buffer, and each batch's vertices are appended to `table` (the
+    /// shape's vertex table) so that a single G_VTX can load them.
+    pub fn from_triangles(
+        vertices: &[Vertex],
+        triangles: &[[usize; 3]],
+        table: &mut Vec<Vertex>,
+    ) -> DisplayList {
+        let mut commands = Vec::new();
+        let mut batch: Vec<usize> = Vec::new();
+        let mut batch_triangles: Vec<[u8; 3]> = Vec::new();
+
+        let mut flush = |batch: &mut Vec<usize>, batch_triangles: &mut Vec<[u8; 3]>| {
+            if batch_triangles.is_empty() {
+                return;
+            }
+
+            commands.push(Gfx::Vertex {
+                first: table.len(),
+                count: batch.len() as u8,
+                buffer_index: 0,
+            });
+            table.extend(batch.drain(..).map(|i| vertices[i].clone()));
+
+            for pair in batch_triangles.chunks(2) {
+                commands.push(match pair {
+                    [a, b] => Gfx::Tri2(*a, *b),
+                    [a] => Gfx::Tri1(*a),
+                    _ => unreachable!(),
+                });
+            }
+            batch_triangles.clear();
+        };
+
+        for triangle in triangles {
+            let mut new = triangle.to_vec();
+            new.sort();
+            new.dedup();
+            new.retain(|i| !batch.contains(i));
+
+            if batch.len() + new.len() > VERTEX_BUFFER_LEN {
+                flush(&mut batch, &mut batch_triangles);
+            }
+
+            let mut local = [0u8; 3];
+            for (local, index) in local.iter_mut().zip(triangle.iter()) {
+                *local = match batch.iter().position(|i| i == index) {
+                    Some(position) => position as u8,
+                    None => {
+                        batch.push(*index);
+                        (batch.len() - 1) as u8
+                    }
+                };
+            }
+
+            batch_triangles.push(local);
+        }
+
+        flush(&mut batch, &mut batch_triangles);
+        commands.push(Gfx::End);
+
+        DisplayList(commands)
+    }
+
+    /// Resolves the display list's triangles into indices into the shape's
+    /// vertex table, by tracking what each G_VTX loads into the vertex buffer.
+    pub fn triangles(&self) -> Vec<[usize; 3]> {
+        let mut buffer = [0usize; 64];
+        let mut triangles = Vec::new();
+
+        for gfx in &self.0 {
+            match *gfx {
+                Gfx::Vertex {
+                    first,
+                    count,
+                    buffer_index,
+                } => {
+                    for i in 0..count as usize {
+                        if let Some(slot) = buffer.get_mut(buffer_index as usize + i) {
+                            *slot = first + i;
+                        }
+                    }
+                }
+                Gfx::Tri1(a) => triangles.push(resolve(&buffer, a)),
+                Gfx::Tri2(a, b) => {
+                    triangles.push(resolve(&buffer, a));
+                    triangles.push(resolve(&buffer, b));
+                }
+                _ => (),
+            }
+        }
+
+        triangles
+    }
+}
+
+/// Properties for an imported model: just its texture, if it has one.
+fn texture_properties(texture: Option<String>) -> Vec<Property> {
+    texture
+        .into_iter()
+        .map(|name| Property {
+            key: PROPERTY_TEXTURE_NAME,
+            value: PropertyValue::String(name),
+        })
+        .collect()
+}
+
+/// Rounds to the nearest `i16`, saturating if out of range.
+fn quantize(value: f64) -> i16 {
+    value
+        .round()
+        .max(f64::from(i16::min_value()))
+        .min(f64::from(i16::max_value())) as i16
+}
+
+fn resolve(buffer: &[usize; 64], tri: [u8; 3]) -> [usize; 3] {
+    [
+        buffer[tri[0] as usize % 64],
+        buffer[tri[1] as usize % 64],
+        buffer[tri[2] as usize % 64],
+    ]
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+
+    /// Offset of the vertex table.
+    vertex_table: usize,
+
+    /// How much of the vertex table the display lists use.
+    vertex_count: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_node(&mut self, addr: u32, depth: usize) -> io::Result<Node> {
+        if depth > MAX_TREE_DEPTH {
+            return Err(invalid_data("model tree is too deep"));
+        }
+
+        let offset = self.offset(addr)?;
+
+        let kind = NodeKind::from(self.read_u32(offset)?);
+        let display_data = self.read_u32(offset + 0x04)?;
+        let property_count = self.read_u32(offset + 0x08)? as usize;
+        let property_list = self.read_u32(offset + 0x0C)?;
+        let group_data = self.read_u32(offset + 0x10)?;
+
+        let display_list = match display_data {
+            0 => None,
+            addr => match self.read_u32(self.offset(addr)?)? {
+                0 => None,
+                addr => Some(self.parse_display_list(addr)?),
+            },
+        };
+
+        let properties = match property_list {
+            0 => Vec::new(),
+            addr => {
+                let offset = self.offset(addr)?;
+
+                (0..property_count)
+                    .map(|i| self.parse_property(offset + i * 0xC))
+                    .collect::<io::Result<Vec<_>>>()?
+            }
+        };
+
+        let group = match group_data {
+            0 => None,
+            addr => Some(self.parse_group(addr, depth)?),
+        };
+
+        Ok(Node {
+            kind,
+            name: String::new(),
+            properties,
+            display_list,
+            group,
+        })
+    }
+
+    fn parse_group(&mut self, addr: u32, depth: usize) -> io::Result<Group> {
+        let offset = self.offset(addr)?;
+
+        let transform = self.read_u32(offset)?;
+        let lights = self.read_u32(offset + 0x04)?;
+        let light_count = self.read_u32(offset + 0x08)? as usize;
+        let child_count = self.read_u32(offset + 0x0C)? as usize;
+        let child_list = self.read_u32(offset + 0x10)?;
+
+        let transform = match transform {
+            0 => None,
+            addr => Some(self.parse_matrix(addr)?),
+        };
+
+        let lights = match lights {
+            0 => None,
+            addr => Some(self.parse_lights(addr, light_count)?),
+        };
+
+        let mut children = Vec::with_capacity(child_count.min(0x100));
+        if child_list != 0 {
+            let offset = self.offset(child_list)?;
+
+            for i in 0..child_count {
+                let addr = self.read_u32(offset + i * 4)?;
+                children.push(self.parse_node(addr, depth + 1)?);
+            }
+        }
+
+        Ok(Group {
+            transform,
+            lights,
+            children,
+        })
+    }
+
+    fn parse_matrix(&self, addr: u32) -> io::Result<Matrix> {
+        let offset = self.offset(addr)?;
+        let mut matrix = [[0i32; 4]; 4];
+
+        // Integer parts first, then fractional parts.
+        for (i, element) in matrix.iter_mut().flatten().enumerate() {
+            let integer = self.read_u16(offset + i * 2)?;
+            let fraction = self.read_u16(offset + 0x20 + i * 2)?;
+
+            *element = (u32::from(integer) << 16 | u32::from(fraction)) as i32;
+        }
+
+        Ok(Matrix(matrix))
+    }
+
+    fn parse_lights(&self, addr: u32, count: usize) -> io::Result<Lights> {
+        let offset = self.offset(addr)?;
+
+        let color = |offset: usize| -> io::Result<Color> {
+            Ok(Color {
+                r: self.read_u8(offset)?,
+                g: self.read_u8(offset + 1)?,
+                b: self.read_u8(offset + 2)?,
+                a: 0xFF,
+            })
+        };
+
+        // Ambient_t, followed by count * Light_t.
+        Ok(Lights {
+            ambient: color(offset)?,
+            directional: (0..count)
+                .map(|i| {
+                    let offset = offset + 0x08 + i * 0x10;
+
+                    Ok(Light {
+                        color: color(offset)?,
+                        direction: [
+                            self.read_u8(offset + 0x08)? as i8,
+                            self.read_u8(offset + 0x09)? as i8,
+                            self.read_u8(offset + 0x0A)? as i8,
+                        ],
+                    })
+                })
+                .collect::<io::Result<Vec<_>>>()?,
+        })
+    }
+
+    fn parse_property(&self, offset: usize) -> io::Result<Property> {
+        let key = self.read_u32(offset)?;
+        let kind = self.read_u32(offset + 0x04)?;
+        let data = self.read_u32(offset + 0x08)?;
+
+        Ok(Property {
+            key,
+            value: match kind {
+                PROPERTY_INT => PropertyValue::Int(data as i32),
+                PROPERTY_FLOAT => PropertyValue::Float(f32::from_bits(data)),
+                PROPERTY_STRING if data != 0 => PropertyValue::String(self.read_string(data)?),
+                kind => PropertyValue::Unknown { kind, data },
+            },
+        })
+    }
+
+    fn parse_display_list(&mut self, addr: u32) -> io::Result<DisplayList> {
+        let mut offset = self.offset(addr)?;
+        let mut commands = Vec::new();
+
+        loop {
+            if commands.len() > MAX_DISPLAY_LIST_LEN {
+                return Err(invalid_data("display list is missing G_ENDDL"));
+            }
+
+            let w0 = self.read_u32(offset)?;
+            let w1 = self.read_u32(offset + 4)?;
+            offset += 8;
+
+            let tri = |w: u32| {
+                [
+                    (w >> 17) as u8,
+                    (w >> 9) as u8 & 0x7F,
+                    (w >> 1) as u8 & 0x7F,
+                ]
+            };
+
+            commands.push(match (w0 >> 24) as u8 {
+                G_VTX => {
+                    let count = (w0 >> 12) as u8;
+                    let end = (w0 >> 1) as u8 & 0x7F;
+
+                    let vertex_offset = self.offset(w1)?;
+                    if vertex_offset < self.vertex_table
+                        || (vertex_offset - self.vertex_table) % VERTEX_SIZE != 0
+                        || end < count
+                    {
+                        return Err(invalid_data("G_VTX outside of the vertex table"));
+                    }
+
+                    let first = (vertex_offset - self.vertex_table) / VERTEX_SIZE;
+                    self.vertex_count = self.vertex_count.max(first + count as usize);
+
+                    Gfx::Vertex {
+                        first,
+                        count,
+                        buffer_index: end - count,
+                    }
+                }
+                G_TRI1 => Gfx::Tri1(tri(w0 & 0xFFFFFF)),
+                G_TRI2 => Gfx::Tri2(tri(w0 & 0xFFFFFF), tri(w1)),
+                G_ENDDL => Gfx::End,
+                _ => Gfx::Raw(w0, w1),
+            });
+
+            if let Some(Gfx::End) = commands.last() {
+                return Ok(DisplayList(commands));
+            }
+        }
+    }
+
+    fn parse_vertex(&self, offset: usize) -> io::Result<Vertex> {
+        Ok(Vertex {
+            x: self.read_u16(offset)? as i16,
+            y: self.read_u16(offset + 0x02)? as i16,
+            z: self.read_u16(offset + 0x04)? as i16,
+            // 0x06: flag, unused
+            texture_x: self.read_u16(offset + 0x08)? as i16,
+            texture_y: self.read_u16(offset + 0x0A)? as i16,
+            color: Color {
+                r: self.read_u8(offset + 0x0C)?,
+                g: self.read_u8(offset + 0x0D)?,
+                b: self.read_u8(offset + 0x0E)?,
+                a: self.read_u8(offset + 0x0F)?,
+            },
+        })
+    }
+
+    /// Reads a list of string pointers. Lists without a known length end at a
+    /// null pointer or the game's "db" sentinel.
+    fn parse_name_list(&self, addr: u32, len: Option<usize>) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        if addr == 0 {
+            return Ok(names);
+        }
+
+        let offset = self.offset(addr)?;
+
+        loop {
+            if Some(names.len()) == len {
+                break;
+            }
+
+            let name = match self.read_u32(offset + names.len() * 4) {
+                Ok(0) | Err(_) => break,
+                Ok(addr) => self.read_string(addr)?,
+            };
 
-        Ok(Shape {})
+            if len.is_none() && name == "db" {
+                break;
+            }
+
+            names.push(name);
+        }
+
+        Ok(names)
+    }
+
+    fn offset(&self, addr: u32) -> io::Result<usize> {
+        match addr.checked_sub(BASE_ADDR) {
+            Some(offset) if (offset as usize) < self.data.len() => Ok(offset as usize),
+            _ => Err(invalid_data("pointer outside of the shape")),
+        }
+    }
+
+    fn read_string(&self, addr: u32) -> io::Result<String> {
+        let offset = self.offset(addr)?;
+        let bytes = &self.data[offset..];
+        let len = bytes
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| invalid_data("unterminated string"))?;
+
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| invalid_data("bad string"))
+    }
+
+    fn read_u8(&self, offset: usize) -> io::Result<u8> {
+        self.data.get(offset).cloned().ok_or_else(eof)
+    }
+
+    fn read_u16(&self, offset: usize) -> io::Result<u16> {
+        let bytes = self.data.get(offset..offset + 2).ok_or_else(eof)?;
+        Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_u32(&self, offset: usize) -> io::Result<u32> {
+        let bytes = self.data.get(offset..offset + 4).ok_or_else(eof)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+
+    /// Address of the vertex table.
+    vertex_table: u32,
+}
+
+impl Writer {
+    fn addr(&self) -> u32 {
+        BASE_ADDR + self.bytes.len() as u32
+    }
+
+    fn align(&mut self, alignment: usize) {
+        let len = (self.bytes.len() + alignment - 1) / alignment * alignment;
+        self.bytes.resize(len, 0);
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn write_node(&mut self, node: &Node) -> u32 {
+        let display_data = match &node.display_list {
+            None => 0,
+            Some(display_list) => {
+                let display_list = self.write_display_list(display_list);
+
+                self.align(8);
+                let addr = self.addr();
+                self.write_u32(display_list);
+                self.write_u32(0);
+                addr
+            }
+        };
+
+        let property_list = if node.properties.is_empty() {
+            0
+        } else {
+            // Strings first, so that the list itself is contiguous.
+            let properties: Vec<(u32, u32, u32)> = node
+                .properties
+                .iter()
+                .map(|property| match &property.value {
+                    PropertyValue::Int(value) => (property.key, PROPERTY_INT, *value as u32),
+                    PropertyValue::Float(value) => (property.key, PROPERTY_FLOAT, value.to_bits()),
+                    PropertyValue::String(value) => {
+                        (property.key, PROPERTY_STRING, self.write_string(value))
+                    }
+                    PropertyValue::Unknown { kind, data } => (property.key, *kind, *data),
+                })
+                .collect();
+
+            self.align(4);
+            let addr = self.addr();
+            for (key, kind, data) in properties {
+                self.write_u32(key);
+                self.write_u32(kind);
+                self.write_u32(data);
+            }
+            addr
+        };
+
+        let group = match &node.group {
+            None => 0,
+            Some(group) => self.write_group(group),
+        };
+
+        self.align(4);
+        let addr = self.addr();
+        self.write_u32(node.kind.id());
+        self.write_u32(display_data);
+        self.write_u32(node.properties.len() as u32);
+        self.write_u32(property_list);
+        self.write_u32(group);
+        addr
     }
+
+    fn write_group(&mut self, group: &Group) -> u32 {
+        let transform = match &group.transform {
+            None => 0,
+            Some(matrix) => {
+                self.align(8);
+                let addr = self.addr();
+
+                // Integer parts first, then fractional parts.
+                for element in matrix.0.iter().flatten() {
+                    self.write_u16((*element >> 16) as u16);
+                }
+                for element in matrix.0.iter().flatten() {
+                    self.write_u16(*element as u16);
+                }
+
+                addr
+            }
+        };
+
+        let (lights, light_count) = match &group.lights {
+            None => (0, 0),
+            Some(lights) => {
+                self.align(8);
+                let addr = self.addr();
+
+                // Ambient_t, followed by Light_t for each directional light.
+                let color = |color: &Color| [color.r, color.g, color.b, 0];
+                let ambient = color(&lights.ambient);
+                self.bytes.extend_from_slice(&ambient);
+                self.bytes.extend_from_slice(&ambient);
+
+                for light in &lights.directional {
+                    let light_color = color(&light.color);
+                    self.bytes.extend_from_slice(&light_color);
+                    self.bytes.extend_from_slice(&light_color);
+                    for direction in &light.direction {
+                        self.bytes.push(*direction as u8);
+                    }
+                    self.bytes.extend_from_slice(&[0; 5]);
+                }
+
+                (addr, lights.directional.len() as u32)
+            }
+        };
+
+        let children: Vec<u32> = group
+            .children
+            .iter()
+            .map(|child| self.write_node(child))
+            .collect();
+
+        let child_list = if children.is_empty() {
+            0
+        } else {
+            let addr = self.addr();
+            for child in &children {
+                self.write_u32(*child);
+            }
+            addr
+        };
+
+        let addr = self.addr();
+        self.write_u32(transform);
+        self.write_u32(lights);
+        self.write_u32(light_count);
+        self.write_u32(children.len() as u32);
+        self.write_u32(child_list);
+        addr
+    }
+
+    fn write_display_list(&mut self, display_list: &DisplayList) -> u32 {
+        self.align(8);
+        let addr = self.addr();
+
+        let tri = |tri: [u8; 3]| {
+            u32::from(tri[0]) << 17 | u32::from(tri[1]) << 9 | u32::from(tri[2]) << 1
+        };
+
+        for gfx in &display_list.0 {
+            let (w0, w1) = match *gfx {
+                Gfx::Vertex {
+                    first,
+                    count,
+                    buffer_index,
+                } => (
+                    u32::from(G_VTX) << 24
+                        | u32::from(count) << 12
+                        | u32::from(buffer_index + count) << 1,
+                    self.vertex_table + (first * VERTEX_SIZE) as u32,
+                ),
+                Gfx::Tri1(a) => (u32::from(G_TRI1) << 24 | tri(a), 0),
+                Gfx::Tri2(a, b) => (u32::from(G_TRI2) << 24 | tri(a), tri(b)),
+                Gfx::End => (u32::from(G_ENDDL) << 24, 0),
+                Gfx::Raw(w0, w1) => (w0, w1),
+            };
+
+            self.write_u32(w0);
+            self.write_u32(w1);
+        }
+
+        addr
+    }
+
+    fn write_vertex(&mut self, vertex: &Vertex) {
+        self.write_u16(vertex.x as u16);
+        self.write_u16(vertex.y as u16);
+        self.write_u16(vertex.z as u16);
+        self.write_u16(0); // flag
+        self.write_u16(vertex.texture_x as u16);
+        self.write_u16(vertex.texture_y as u16);
+        self.bytes.extend_from_slice(&[
+            vertex.color.r,
+            vertex.color.g,
+            vertex.color.b,
+            vertex.color.a,
+        ]);
+    }
+
+    /// Writes a null-terminated list of string pointers.
+    fn write_name_list(&mut self, names: &[String]) -> u32 {
+        let names: Vec<u32> = names.iter().map(|name| self.write_string(name)).collect();
+
+        self.align(4);
+        let addr = self.addr();
+        for name in names {
+            self.write_u32(name);
+        }
+        self.write_u32(0);
+        addr
+    }
+
+    fn write_string(&mut self, string: &str) -> u32 {
+        self.align(4);
+        let addr = self.addr();
+        self.bytes.extend_from_slice(string.as_bytes());
+        self.bytes.push(0);
+        addr
+    }
+}
+
+fn eof() -> io::Error {
+    io::Error::from(io::ErrorKind::UnexpectedEof)
 }
 
-fn read_u32(data: &mut Cursor<Vec<u8>>) -> u32 {
-    let mut buf = [0u8; 4];
-    data.read_exact(&mut buf).unwrap();
-    u32::from_be_bytes(buf)
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
 }
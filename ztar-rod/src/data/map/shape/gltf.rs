@@ -0,0 +1,1119 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::*;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: &[u8; 4] = b"JSON";
+const CHUNK_BIN: &[u8; 4] = b"BIN\0";
+
+// Accessor component types.
+const BYTE: u32 = 5120;
+const UNSIGNED_BYTE: u32 = 5121;
+const SHORT: u32 = 5122;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+// Buffer view targets.
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+const MODE_TRIANGLES: u32 = 4;
+
+impl Shape {
+    /// Writes this shape as a binary glTF 2.0 file. Each node of the model
+    /// tree becomes a glTF node of the same name; models become meshes with a
+    /// material per texture name.
+    ///
+    /// Everything glTF has no place for (node kinds, properties, group
+    /// lights, display list commands other than geometry, and the collider
+    /// and zone name lists) is kept in `extras`.
+    pub fn write_glb<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let mut builder = Builder::default();
+        let root = builder.add_node(self, &self.root);
+
+        let mut gltf = Map::new();
+        gltf.insert(
+            "asset".to_string(),
+            json!({ "version": "2.0", "generator": "ztar-rod" }),
+        );
+        gltf.insert("scene".to_string(), json!(0));
+        gltf.insert(
+            "scenes".to_string(),
+            json!([{
+                "nodes": [root],
+                "extras": {
+                    "colliders": self.collider_names,
+                    "zones": self.zone_names,
+                },
+            }]),
+        );
+
+        // glTF doesn't allow empty arrays, so leave out anything unused.
+        let materials = builder
+            .materials
+            .iter()
+            .map(|name| json!({ "name": name }))
+            .collect();
+
+        for (key, list) in vec![
+            ("nodes", builder.nodes),
+            ("meshes", builder.meshes),
+            ("materials", materials),
+            ("accessors", builder.accessors),
+            ("bufferViews", builder.buffer_views),
+        ] {
+            if !list.is_empty() {
+                gltf.insert(key.to_string(), Value::Array(list));
+            }
+        }
+
+        let mut bin = builder.buffer;
+        if !bin.is_empty() {
+            gltf.insert("buffers".to_string(), json!([{ "byteLength": bin.len() }]));
+        }
+
+        // Chunks must be 4-byte aligned.
+        let mut json = serde_json::to_vec(&Value::Object(gltf))?;
+        json.resize(align(json.len(), 4), b' ');
+        bin.resize(align(bin.len(), 4), 0);
+
+        let mut len = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            len += 8 + bin.len();
+        }
+
+        w.write_all(GLB_MAGIC)?;
+        w.write_all(&GLB_VERSION.to_le_bytes())?;
+        w.write_all(&(len as u32).to_le_bytes())?;
+
+        w.write_all(&(json.len() as u32).to_le_bytes())?;
+        w.write_all(CHUNK_JSON)?;
+        w.write_all(&json)?;
+
+        if !bin.is_empty() {
+            w.write_all(&(bin.len() as u32).to_le_bytes())?;
+            w.write_all(CHUNK_BIN)?;
+            w.write_all(&bin)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a shape from a glTF 2.0 file, binary (`.glb`) or not (`.gltf`).
+    /// The extras written by `write_glb` restore node kinds, properties,
+    /// lights, non-geometry display list commands and name lists. Files
+    /// without them get defaults: nodes with meshes become models textured by
+    /// their material's name, and the rest become groups.
+    ///
+    /// Models can't have transforms of their own, so a mesh node's transform
+    /// is applied to its vertices. Mesh nodes with children are split into a
+    /// group and a model.
+    pub fn from_gltf(path: &Path) -> Result<Shape, ImportError> {
+        let file = fs::read(path)?;
+
+        let (json, bin) = if file.starts_with(GLB_MAGIC) {
+            read_glb(&file)?
+        } else {
+            (&file[..], None)
+        };
+
+        let gltf: Value = serde_json::from_slice(json)?;
+
+        let buffers = array(&gltf["buffers"])
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| match buffer["uri"].as_str() {
+                // Only the first buffer can refer to the BIN chunk.
+                None if i == 0 => bin
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| gltf_error("missing BIN chunk")),
+                None => Err(gltf_error("buffer has no data")),
+                Some(uri) if uri.starts_with("data:") => uri
+                    .splitn(2, ',')
+                    .nth(1)
+                    .and_then(|data| base64::decode(data).ok())
+                    .ok_or_else(|| gltf_error("bad data URI")),
+                Some(uri) => Ok(fs::read(path.with_file_name(uri))?),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut importer = Importer {
+            gltf: &gltf,
+            buffers,
+            vertices: Vec::new(),
+        };
+
+        let scene = &gltf["scenes"][gltf["scene"].as_u64().unwrap_or(0) as usize];
+        let mut nodes = array(&scene["nodes"])
+            .iter()
+            .map(|index| importer.node(index, 0))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The root must be a group; wrap the scene's nodes in one if needed.
+        let root = match nodes.first() {
+            Some(node) if nodes.len() == 1 && node.group.is_some() => {
+                let mut root = nodes.remove(0);
+                if root.kind == NodeKind::Group {
+                    root.kind = NodeKind::Root;
+                }
+                root
+            }
+            _ => Node {
+                kind: NodeKind::Root,
+                name: "Root".to_string(),
+                properties: Vec::new(),
+                display_list: None,
+                group: Some(Group {
+                    transform: None,
+                    lights: None,
+                    children: nodes,
+                }),
+            },
+        };
+
+        let names = |key| {
+            array(&scene["extras"][key])
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        };
+
+        Ok(Shape::from_root(
+            root,
+            importer.vertices,
+            names("colliders"),
+            names("zones"),
+        ))
+    }
+}
+
+/// Splits a binary glTF file into its JSON and (optional) BIN chunks.
+fn read_glb(file: &[u8]) -> Result<(&[u8], Option<&[u8]>), ImportError> {
+    if file.get(4..8) != Some(&GLB_VERSION.to_le_bytes()[..]) {
+        return Err(gltf_error("unsupported glTF version"));
+    }
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+
+    while let Some(header) = file.get(offset..offset + 8) {
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let data = file
+            .get(offset + 8..offset + 8 + len)
+            .ok_or_else(|| gltf_error("truncated chunk"))?;
+
+        match &header[4..8] {
+            kind if kind == CHUNK_JSON => json = json.or(Some(data)),
+            kind if kind == CHUNK_BIN => bin = bin.or(Some(data)),
+            _ => (),
+        }
+
+        offset += 8 + len;
+    }
+
+    Ok((json.ok_or_else(|| gltf_error("missing JSON chunk"))?, bin))
+}
+
+/// Row-major 4x4 matrix, laid out like `Matrix`.
+type Transform = [[f64; 4]; 4];
+
+/// Vertices, the triangles between them, and the texture they use.
+type Mesh = (Vec<Vertex>, Vec<[usize; 3]>, Option<String>);
+
+struct Importer<'a> {
+    gltf: &'a Value,
+    buffers: Vec<Vec<u8>>,
+
+    /// The shape's vertex table.
+    vertices: Vec<Vertex>,
+}
+
+impl<'a> Importer<'a> {
+    fn node(&mut self, index: &Value, depth: usize) -> Result<Node, ImportError> {
+        if depth > MAX_TREE_DEPTH {
+            return Err(gltf_error("node tree is too deep"));
+        }
+
+        let node = self.get("nodes", index)?;
+        let name = node["name"].as_str().unwrap_or_default().to_string();
+        let transform = node_transform(node)?;
+
+        let extras = &node["extras"];
+        let kind = extras["kind"]
+            .as_u64()
+            .map(|kind| NodeKind::from(kind as u32));
+        let properties = match extras["properties"].as_array() {
+            Some(properties) => Some(
+                properties
+                    .iter()
+                    .map(property_from_json)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+
+        let lights = match &extras["lights"] {
+            Value::Null => None,
+            lights => Some(lights_from_json(lights)?),
+        };
+        let raw = match extras["gfx"].as_array() {
+            Some(gfx) => Some(raw_gfx_from_json(gfx)?),
+            None => None,
+        };
+
+        let mut children = array(&node["children"])
+            .iter()
+            .map(|child| self.node(child, depth + 1))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mesh = match &node["mesh"] {
+            Value::Null => None,
+            mesh => Some(self.mesh(mesh)?),
+        };
+
+        let is_model = match kind {
+            Some(kind) => kind == NodeKind::Model,
+            None => mesh.is_some(),
+        };
+
+        if children.is_empty() && is_model {
+            let (properties, mut display_list) = match mesh {
+                // A model without geometry may still have other commands.
+                None => (
+                    properties.unwrap_or_default(),
+                    raw.as_ref().map(|_| DisplayList(vec![Gfx::End])),
+                ),
+                Some((mut vertices, triangles, texture)) => {
+                    if let Some(transform) = &transform {
+                        for vertex in &mut vertices {
+                            apply_transform(transform, vertex);
+                        }
+                    }
+
+                    (
+                        properties.unwrap_or_else(|| texture_properties(texture)),
+                        Some(DisplayList::from_triangles(
+                            &vertices,
+                            &triangles,
+                            &mut self.vertices,
+                        )),
+                    )
+                }
+            };
+
+            if let (Some(display_list), Some(raw)) = (&mut display_list, &raw) {
+                insert_raw_gfx(display_list, raw);
+            }
+
+            return Ok(Node {
+                kind: NodeKind::Model,
+                name,
+                properties,
+                display_list,
+                group: None,
+            });
+        }
+
+        if let Some((vertices, triangles, texture)) = mesh {
+            children.insert(
+                0,
+                Node {
+                    kind: NodeKind::Model,
+                    name: name.clone(),
+                    properties: texture_properties(texture),
+                    display_list: Some(DisplayList::from_triangles(
+                        &vertices,
+                        &triangles,
+                        &mut self.vertices,
+                    )),
+                    group: None,
+                },
+            );
+        }
+
+        Ok(Node {
+            kind: kind.unwrap_or(NodeKind::Group),
+            name,
+            properties: properties.unwrap_or_default(),
+            display_list: None,
+            group: Some(Group {
+                transform: transform.map(Matrix::from_f64),
+                lights,
+                children,
+            }),
+        })
+    }
+
+    /// Merges every primitive of a mesh. Models only have one texture, so
+    /// only the first material is kept.
+    fn mesh(&self, index: &Value) -> Result<Mesh, ImportError> {
+        let mesh = self.get("meshes", index)?;
+
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        let mut texture = None;
+
+        for primitive in array(&mesh["primitives"]) {
+            if primitive["mode"]
+                .as_u64()
+                .unwrap_or(u64::from(MODE_TRIANGLES))
+                != u64::from(MODE_TRIANGLES)
+            {
+                return Err(gltf_error("only triangle primitives are supported"));
+            }
+
+            let attributes = &primitive["attributes"];
+            let positions = self.accessor(&attributes["POSITION"])?;
+            let texcoords = match &attributes["TEXCOORD_0"] {
+                Value::Null => Vec::new(),
+                texcoords => self.accessor(texcoords)?,
+            };
+            let colors = match &attributes["COLOR_0"] {
+                Value::Null => Vec::new(),
+                colors => self.accessor(colors)?,
+            };
+
+            let component = |elements: &[Vec<f64>], i: usize, n: usize| {
+                elements.get(i).and_then(|element| element.get(n)).cloned()
+            };
+            let channel = |i, n| match component(&colors, i, n) {
+                Some(value) => (value * 255.0).round().max(0.0).min(255.0) as u8,
+                None => 0xFF,
+            };
+            let texcoord =
+                |i, n| quantize(component(&texcoords, i, n).unwrap_or(0.0) * TEXEL_SCALE);
+            let position = |i, n| quantize(component(&positions, i, n).unwrap_or(0.0));
+
+            let base = vertices.len();
+            for i in 0..positions.len() {
+                vertices.push(Vertex {
+                    x: position(i, 0),
+                    y: position(i, 1),
+                    z: position(i, 2),
+                    texture_x: texcoord(i, 0),
+                    texture_y: texcoord(i, 1),
+                    color: Color {
+                        r: channel(i, 0),
+                        g: channel(i, 1),
+                        b: channel(i, 2),
+                        a: channel(i, 3),
+                    },
+                });
+            }
+
+            let indices: Vec<usize> = match &primitive["indices"] {
+                Value::Null => (0..positions.len()).collect(),
+                indices => self
+                    .accessor(indices)?
+                    .iter()
+                    .map(|index| index[0] as usize)
+                    .collect(),
+            };
+
+            for triangle in indices.chunks(3).filter(|triangle| triangle.len() == 3) {
+                if triangle.iter().any(|index| *index >= positions.len()) {
+                    return Err(gltf_error("index out of range"));
+                }
+
+                triangles.push([base + triangle[0], base + triangle[1], base + triangle[2]]);
+            }
+
+            if texture.is_none() {
+                texture = primitive["material"]
+                    .as_u64()
+                    .and_then(|material| self.gltf["materials"][material as usize]["name"].as_str())
+                    .map(str::to_string);
+            }
+        }
+
+        Ok((vertices, triangles, texture))
+    }
+
+    /// Reads every element of an accessor, converting components to floats
+    /// (and normalising them, if the accessor says to).
+    fn accessor(&self, index: &Value) -> Result<Vec<Vec<f64>>, ImportError> {
+        let accessor = self.get("accessors", index)?;
+
+        if !accessor["sparse"].is_null() {
+            return Err(gltf_error("sparse accessors are not supported"));
+        }
+
+        let count = accessor["count"]
+            .as_u64()
+            .ok_or_else(|| gltf_error("accessor has no count"))? as usize;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            _ => return Err(gltf_error("unsupported accessor type")),
+        };
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0) as u32;
+        let size = match component_type {
+            BYTE | UNSIGNED_BYTE => 1,
+            SHORT | UNSIGNED_SHORT => 2,
+            UNSIGNED_INT | FLOAT => 4,
+            _ => return Err(gltf_error("unsupported component type")),
+        };
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+
+        let view = self.get("bufferViews", &accessor["bufferView"])?;
+        let view_offset = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let view_len = view["byteLength"].as_u64().unwrap_or(0) as usize;
+        let data = view["buffer"]
+            .as_u64()
+            .and_then(|buffer| self.buffers.get(buffer as usize))
+            .and_then(|buffer| buffer.get(view_offset..view_offset + view_len))
+            .ok_or_else(|| gltf_error("buffer view out of range"))?;
+
+        let offset = accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let stride = match view["byteStride"].as_u64() {
+            Some(stride) => stride as usize,
+            None => components * size,
+        };
+
+        (0..count)
+            .map(|i| {
+                (0..components)
+                    .map(|n| {
+                        let offset = offset + i * stride + n * size;
+
+                        data.get(offset..offset + size)
+                            .map(|bytes| read_component(component_type, bytes, normalized))
+                            .ok_or_else(|| gltf_error("accessor out of range"))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn get(&self, key: &str, index: &Value) -> Result<&'a Value, ImportError> {
+        let gltf = self.gltf;
+
+        index
+            .as_u64()
+            .and_then(|index| gltf[key].get(index as usize))
+            .ok_or_else(|| ImportError::Gltf(format!("bad index into {}", key)))
+    }
+}
+
+fn read_component(component_type: u32, bytes: &[u8], normalized: bool) -> f64 {
+    let (value, max) = match component_type {
+        BYTE => (f64::from(bytes[0] as i8), 127.0),
+        UNSIGNED_BYTE => (f64::from(bytes[0]), 255.0),
+        SHORT => (
+            f64::from(i16::from_le_bytes(bytes.try_into().unwrap())),
+            32767.0,
+        ),
+        UNSIGNED_SHORT => (
+            f64::from(u16::from_le_bytes(bytes.try_into().unwrap())),
+            65535.0,
+        ),
+        UNSIGNED_INT => (
+            f64::from(u32::from_le_bytes(bytes.try_into().unwrap())),
+            1.0,
+        ),
+        _ => (
+            f64::from(f32::from_bits(u32::from_le_bytes(
+                bytes.try_into().unwrap(),
+            ))),
+            1.0,
+        ),
+    };
+
+    if normalized {
+        (value / max).max(-1.0)
+    } else {
+        value
+    }
+}
+
+/// A node's local transform, if it has one.
+fn node_transform(node: &Value) -> Result<Option<Transform>, ImportError> {
+    let floats = |value: &Value| -> Option<Vec<f64>> {
+        value
+            .as_array()
+            .map(|array| array.iter().filter_map(Value::as_f64).collect())
+    };
+
+    // glTF's column-major order is the RSP's row-major order.
+    if let Some(elements) = floats(&node["matrix"]) {
+        if elements.len() != 16 {
+            return Err(gltf_error("bad matrix"));
+        }
+
+        let mut matrix = [[0.0; 4]; 4];
+        for (i, element) in elements.into_iter().enumerate() {
+            matrix[i / 4][i % 4] = element;
+        }
+
+        return Ok(Some(matrix));
+    }
+
+    let (t, r, s) = match (
+        floats(&node["translation"]),
+        floats(&node["rotation"]),
+        floats(&node["scale"]),
+    ) {
+        (None, None, None) => return Ok(None),
+        (t, r, s) => (
+            t.unwrap_or_else(|| vec![0.0, 0.0, 0.0]),
+            r.unwrap_or_else(|| vec![0.0, 0.0, 0.0, 1.0]),
+            s.unwrap_or_else(|| vec![1.0, 1.0, 1.0]),
+        ),
+    };
+
+    if t.len() != 3 || r.len() != 4 || s.len() != 3 {
+        return Err(gltf_error("bad transform"));
+    }
+
+    let (x, y, z, w) = (r[0], r[1], r[2], r[3]);
+    let rotation = [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - z * w),
+            2.0 * (x * z + y * w),
+        ],
+        [
+            2.0 * (x * y + z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - x * w),
+        ],
+        [
+            2.0 * (x * z - y * w),
+            2.0 * (y * z + x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ],
+    ];
+
+    // T * R * S, transposed for row vectors.
+    let mut matrix = [[0.0; 4]; 4];
+    for row in 0..3 {
+        for col in 0..3 {
+            matrix[col][row] = rotation[row][col] * s[col];
+        }
+    }
+    matrix[3] = [t[0], t[1], t[2], 1.0];
+
+    Ok(Some(matrix))
+}
+
+fn apply_transform(transform: &Transform, vertex: &mut Vertex) {
+    let position = [
+        f64::from(vertex.x),
+        f64::from(vertex.y),
+        f64::from(vertex.z),
+        1.0,
+    ];
+
+    let component = |col: usize| {
+        quantize(
+            position
+                .iter()
+                .zip(transform.iter())
+                .map(|(value, row)| value * row[col])
+                .sum(),
+        )
+    };
+
+    vertex.x = component(0);
+    vertex.y = component(1);
+    vertex.z = component(2);
+}
+
+fn property_from_json(property: &Value) -> Result<Property, ImportError> {
+    let key = property["key"]
+        .as_u64()
+        .ok_or_else(|| gltf_error("property has no key"))? as u32;
+
+    let value = if let Some(value) = property["int"].as_i64() {
+        PropertyValue::Int(value as i32)
+    } else if let Some(value) = property["float"].as_f64() {
+        PropertyValue::Float(value as f32)
+    } else if let Some(value) = property["string"].as_str() {
+        PropertyValue::String(value.to_string())
+    } else {
+        PropertyValue::Unknown {
+            kind: property["kind"].as_u64().unwrap_or(0) as u32,
+            data: property["data"].as_u64().unwrap_or(0) as u32,
+        }
+    };
+
+    Ok(Property { key, value })
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or(&[])
+}
+
+fn gltf_error(message: &str) -> ImportError {
+    ImportError::Gltf(message.to_string())
+}
+
+#[derive(Default)]
+struct Builder {
+    nodes: Vec<Value>,
+    meshes: Vec<Value>,
+    accessors: Vec<Value>,
+    buffer_views: Vec<Value>,
+    buffer: Vec<u8>,
+
+    /// Texture names, by material index.
+    materials: Vec<String>,
+}
+
+impl Builder {
+    /// Adds `node` and its children, returning its index. Children are added
+    /// first, so nodes end up in the same order as the model name list.
+    fn add_node(&mut self, shape: &Shape, node: &Node) -> usize {
+        let children: Vec<usize> = node
+            .children()
+            .iter()
+            .map(|child| self.add_node(shape, child))
+            .collect();
+
+        let mut value = Map::new();
+        value.insert("name".to_string(), json!(node.name));
+
+        if !children.is_empty() {
+            value.insert("children".to_string(), json!(children));
+        }
+
+        // The RSP multiplies row vectors, so its row-major matrices are
+        // already in glTF's column-major order.
+        if let Some(Group {
+            transform: Some(matrix),
+            ..
+        }) = &node.group
+        {
+            let elements: Vec<f32> = (0..16).map(|i| matrix.get(i / 4, i % 4)).collect();
+            value.insert("matrix".to_string(), json!(elements));
+        }
+
+        if let Some(mesh) = self.add_mesh(shape, node) {
+            value.insert("mesh".to_string(), json!(mesh));
+        }
+
+        let mut extras = json!({
+            "kind": node.kind.id(),
+            "properties": node.properties.iter().map(property_to_json).collect::<Vec<_>>(),
+        });
+
+        if let Some(Group {
+            lights: Some(lights),
+            ..
+        }) = &node.group
+        {
+            extras["lights"] = lights_to_json(lights);
+        }
+
+        if let Some(display_list) = &node.display_list {
+            extras["gfx"] = json!(raw_gfx(display_list)
+                .into_iter()
+                .map(|(after, w0, w1)| json!({ "after": after, "w0": w0, "w1": w1 }))
+                .collect::<Vec<_>>());
+        }
+
+        value.insert("extras".to_string(), extras);
+
+        self.nodes.push(Value::Object(value));
+        self.nodes.len() - 1
+    }
+
+    fn add_mesh(&mut self, shape: &Shape, node: &Node) -> Option<usize> {
+        let triangles: Vec<[usize; 3]> = node
+            .display_list
+            .as_ref()?
+            .triangles()
+            .into_iter()
+            .filter(|tri| tri.iter().all(|i| *i < shape.vertices.len()))
+            .collect();
+
+        if triangles.is_empty() {
+            return None;
+        }
+
+        // Only include the vertices this mesh uses.
+        let mut remap = HashMap::new();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::with_capacity(triangles.len() * 3);
+
+        for index in triangles.iter().flatten() {
+            let next = vertices.len() as u32;
+            let mapped = *remap.entry(*index).or_insert_with(|| {
+                vertices.push(&shape.vertices[*index]);
+                next
+            });
+
+            indices.push(mapped);
+        }
+
+        let mut positions = Vec::with_capacity(vertices.len() * 12);
+        let mut texcoords = Vec::with_capacity(vertices.len() * 8);
+        let mut colors = Vec::with_capacity(vertices.len() * 4);
+        let mut min = [i16::max_value(); 3];
+        let mut max = [i16::min_value(); 3];
+
+        for vertex in &vertices {
+            for (i, component) in [vertex.x, vertex.y, vertex.z].iter().enumerate() {
+                positions.extend_from_slice(&f32::from(*component).to_bits().to_le_bytes());
+                min[i] = min[i].min(*component);
+                max[i] = max[i].max(*component);
+            }
+
+            for component in &[vertex.texture_x, vertex.texture_y] {
+                let uv = (f64::from(*component) / TEXEL_SCALE) as f32;
+                texcoords.extend_from_slice(&uv.to_bits().to_le_bytes());
+            }
+
+            colors.extend_from_slice(&[
+                vertex.color.r,
+                vertex.color.g,
+                vertex.color.b,
+                vertex.color.a,
+            ]);
+        }
+
+        let count = vertices.len();
+
+        let position = self.add_accessor(
+            &positions,
+            ARRAY_BUFFER,
+            json!({
+                "componentType": FLOAT,
+                "count": count,
+                "type": "VEC3",
+                "min": min,
+                "max": max,
+            }),
+        );
+        let texcoord = self.add_accessor(
+            &texcoords,
+            ARRAY_BUFFER,
+            json!({ "componentType": FLOAT, "count": count, "type": "VEC2" }),
+        );
+        let color = self.add_accessor(
+            &colors,
+            ARRAY_BUFFER,
+            json!({
+                "componentType": UNSIGNED_BYTE,
+                "normalized": true,
+                "count": count,
+                "type": "VEC4",
+            }),
+        );
+
+        let index_bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|i| i.to_le_bytes().to_vec())
+            .collect();
+        let indices = self.add_accessor(
+            &index_bytes,
+            ELEMENT_ARRAY_BUFFER,
+            json!({ "componentType": UNSIGNED_INT, "count": indices.len(), "type": "SCALAR" }),
+        );
+
+        let mut primitive = json!({
+            "attributes": {
+                "POSITION": position,
+                "TEXCOORD_0": texcoord,
+                "COLOR_0": color,
+            },
+            "indices": indices,
+            "mode": MODE_TRIANGLES,
+        });
+
+        if let Some(texture) = node.texture_name() {
+            primitive["material"] = json!(self.material(texture));
+        }
+
+        self.meshes.push(json!({
+            "name": node.name,
+            "primitives": [primitive],
+        }));
+        Some(self.meshes.len() - 1)
+    }
+
+    /// Appends `bytes` to the buffer under a new buffer view, and adds an
+    /// accessor for it.
+    fn add_accessor(&mut self, bytes: &[u8], target: u32, mut accessor: Value) -> usize {
+        let offset = align(self.buffer.len(), 4);
+        self.buffer.resize(offset, 0);
+        self.buffer.extend_from_slice(bytes);
+
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+
+        accessor["bufferView"] = json!(self.buffer_views.len() - 1);
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn material(&mut self, texture: &str) -> usize {
+        match self.materials.iter().position(|name| name == texture) {
+            Some(index) => index,
+            None => {
+                self.materials.push(texture.to_string());
+                self.materials.len() - 1
+            }
+        }
+    }
+}
+
+fn property_to_json(property: &Property) -> Value {
+    match &property.value {
+        PropertyValue::Int(value) => json!({ "key": property.key, "int": value }),
+        PropertyValue::Float(value) => json!({ "key": property.key, "float": value }),
+        PropertyValue::String(value) => json!({ "key": property.key, "string": value }),
+        PropertyValue::Unknown { kind, data } => {
+            json!({ "key": property.key, "kind": kind, "data": data })
+        }
+    }
+}
+
+fn lights_to_json(lights: &Lights) -> Value {
+    let color = |color: &Color| json!([color.r, color.g, color.b]);
+
+    json!({
+        "ambient": color(&lights.ambient),
+        "directional": lights
+            .directional
+            .iter()
+            .map(|light| json!({ "color": color(&light.color), "direction": light.direction }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn lights_from_json(lights: &Value) -> Result<Lights, ImportError> {
+    let color = |color: &Value| match color.as_array().map(Vec::as_slice) {
+        Some([r, g, b]) => match (r.as_u64(), g.as_u64(), b.as_u64()) {
+            (Some(r), Some(g), Some(b)) if r.max(g).max(b) <= 0xFF => Ok(Color {
+                r: r as u8,
+                g: g as u8,
+                b: b as u8,
+                a: 0xFF,
+            }),
+            _ => Err(gltf_error("bad light color")),
+        },
+        _ => Err(gltf_error("bad light color")),
+    };
+
+    Ok(Lights {
+        ambient: color(&lights["ambient"])?,
+        directional: array(&lights["directional"])
+            .iter()
+            .map(|light| {
+                let direction = array(&light["direction"])
+                    .iter()
+                    .filter_map(Value::as_i64)
+                    .filter(|component| *component >= -128 && *component <= 127)
+                    .map(|component| component as i8)
+                    .collect::<Vec<_>>();
+
+                match direction.as_slice() {
+                    [x, y, z] => Ok(Light {
+                        color: color(&light["color"])?,
+                        direction: [*x, *y, *z],
+                    }),
+                    _ => Err(gltf_error("bad light direction")),
+                }
+            })
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// The commands of a display list that aren't geometry, each with the number
+/// of triangles drawn before it.
+fn raw_gfx(display_list: &DisplayList) -> Vec<(usize, u32, u32)> {
+    let mut triangles = 0;
+    let mut raw = Vec::new();
+
+    for gfx in &display_list.0 {
+        match *gfx {
+            Gfx::Tri1(_) => triangles += 1,
+            Gfx::Tri2(_, _) => triangles += 2,
+            Gfx::Raw(w0, w1) => raw.push((triangles, w0, w1)),
+            _ => (),
+        }
+    }
+
+    raw
+}
+
+fn raw_gfx_from_json(gfx: &[Value]) -> Result<Vec<(usize, u32, u32)>, ImportError> {
+    gfx.iter()
+        .map(|command| {
+            match (
+                command["after"].as_u64(),
+                command["w0"].as_u64(),
+                command["w1"].as_u64(),
+            ) {
+                (Some(after), Some(w0), Some(w1)) if w0.max(w1) <= u64::from(u32::max_value()) => {
+                    Ok((after as usize, w0 as u32, w1 as u32))
+                }
+                _ => Err(gltf_error("bad display list command")),
+            }
+        })
+        .collect()
+}
+
+/// Puts the commands `raw_gfx` took out of a display list back into one
+/// rebuilt from its triangles, each after the same number of triangles as
+/// before.
+fn insert_raw_gfx(display_list: &mut DisplayList, raw: &[(usize, u32, u32)]) {
+    let mut commands = Vec::with_capacity(display_list.0.len() + raw.len());
+    let mut raw = raw.iter().peekable();
+    let mut triangles = 0;
+
+    for gfx in display_list.0.drain(..) {
+        // Anything left over goes before the G_ENDDL.
+        let drawn = match gfx {
+            Gfx::End => usize::max_value(),
+            _ => triangles,
+        };
+
+        while let Some((_, w0, w1)) = raw.peek().filter(|(after, _, _)| *after <= drawn) {
+            commands.push(Gfx::Raw(*w0, *w1));
+            raw.next();
+        }
+
+        match gfx {
+            Gfx::Tri1(_) => triangles += 1,
+            Gfx::Tri2(_, _) => triangles += 2,
+            _ => (),
+        }
+
+        commands.push(gfx);
+    }
+
+    display_list.0 = commands;
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::map::asset_table::test_asset;
+    use std::env;
+
+    fn round_trip(shape: &Shape, name: &str) -> Shape {
+        let path = env::temp_dir().join(format!("ztar-rod-{}.glb", name));
+
+        let mut glb = Vec::new();
+        shape.write_glb(&mut glb).unwrap();
+        fs::write(&path, &glb).unwrap();
+
+        let imported = Shape::from_gltf(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        imported
+    }
+
+    /// Every node's lights and non-geometry display list commands, in order.
+    fn extras(shape: &Shape) -> Vec<(String, String, Vec<(usize, u32, u32)>)> {
+        let mut extras = Vec::new();
+        shape.root.visit_post_order(&mut |node| {
+            let lights = match &node.group {
+                Some(group) => format!("{:?}", group.lights),
+                None => String::new(),
+            };
+            let raw = node.display_list.as_ref().map(raw_gfx).unwrap_or_default();
+
+            extras.push((node.name.clone(), lights, raw));
+        });
+        extras
+    }
+
+    #[test]
+    fn lights_and_raw_gfx() {
+        let color = |r, g, b| Color { r, g, b, a: 0xFF };
+        let vertex = |x, y| Vertex {
+            x,
+            y,
+            z: 0,
+            texture_x: 0,
+            texture_y: 0,
+            color: color(0xFF, 0xFF, 0xFF),
+        };
+
+        let mut vertices = Vec::new();
+        let mut display_list = DisplayList::from_triangles(
+            &[vertex(0, 0), vertex(10, 0), vertex(0, 10), vertex(10, 10)],
+            &[[0, 1, 2], [1, 3, 2]],
+            &mut vertices,
+        );
+        insert_raw_gfx(
+            &mut display_list,
+            &[
+                (0, 0xE700_0000, 0),
+                (1, 0xD9FF_FFFF, 0x0020_0005),
+                (2, 0xE200_001C, 0),
+            ],
+        );
+
+        let model = Node {
+            kind: NodeKind::Model,
+            name: "model".to_string(),
+            properties: Vec::new(),
+            display_list: Some(display_list),
+            group: None,
+        };
+        let empty_model = Node {
+            name: "empty".to_string(),
+            display_list: Some(DisplayList(vec![Gfx::Raw(0xE700_0000, 0), Gfx::End])),
+            ..model.clone()
+        };
+        let root = Node {
+            kind: NodeKind::Root,
+            name: "root".to_string(),
+            properties: Vec::new(),
+            display_list: None,
+            group: Some(Group {
+                transform: None,
+                lights: Some(Lights {
+                    ambient: color(0x40, 0x40, 0x40),
+                    directional: vec![Light {
+                        color: color(0xFF, 0xC0, 0x80),
+                        direction: [0, -127, 20],
+                    }],
+                }),
+                children: vec![model, empty_model],
+            }),
+        };
+
+        let shape = Shape::from_root(root, vertices, Vec::new(), Vec::new());
+        let imported = round_trip(&shape, "lights-and-raw-gfx");
+
+        assert_eq!(extras(&imported), extras(&shape));
+        assert_eq!(imported.to_bytes(), shape.to_bytes());
+    }
+
+    /// Needs the USA rom; see `test_rom`.
+    #[test]
+    #[ignore]
+    fn real_shape() {
+        let shape = Shape::parse(test_asset("kmr_00_shape")).unwrap();
+        let imported = round_trip(&shape, "kmr_00_shape");
+
+        assert_eq!(extras(&imported), extras(&shape));
+
+        // Geometry is rebatched on import, but from then on is stable.
+        let bytes = imported.to_bytes();
+        assert_eq!(Shape::parse(bytes.clone()).unwrap().to_bytes(), bytes);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use super::*;
+
+/// An object (or group) in an OBJ file, which becomes a model.
+struct Object {
+    name: String,
+    texture: Option<String>,
+    vertices: Vec<Vertex>,
+    triangles: Vec<[usize; 3]>,
+
+    /// Maps each (position, texture coordinate) pair to its index in
+    /// `vertices`.
+    indices: HashMap<(usize, Option<usize>), usize>,
+}
+
+impl Object {
+    fn new(name: String, texture: Option<String>) -> Object {
+        Object {
+            name,
+            texture,
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl Shape {
+    /// Reads a shape from a Wavefront OBJ file. Each object or group becomes
+    /// a model under the root, textured by the name of its material; changing
+    /// material part-way through an object starts a new model. Vertex colours
+    /// are read from the common `v x y z r g b` extension.
+    ///
+    /// OBJ has nowhere to keep collider and zone names, so the shape has none.
+    pub fn from_obj(source: &str) -> Result<Shape, ImportError> {
+        let mut positions: Vec<(Vec<f64>, Option<Vec<f64>>)> = Vec::new();
+        let mut texcoords: Vec<Vec<f64>> = Vec::new();
+        let mut objects: Vec<Object> = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let error = |message: &str| ImportError::Obj(i + 1, message.to_string());
+
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+
+            match words.next() {
+                Some("v") => {
+                    let values = parse_floats(words).ok_or_else(|| error("bad vertex"))?;
+
+                    positions.push(match values.len() {
+                        3 | 4 => (values[0..3].to_vec(), None),
+                        6 => (values[0..3].to_vec(), Some(values[3..6].to_vec())),
+                        _ => return Err(error("bad vertex")),
+                    });
+                }
+
+                Some("vt") => {
+                    let values =
+                        parse_floats(words).ok_or_else(|| error("bad texture coordinate"))?;
+
+                    match values.len() {
+                        1 => texcoords.push(vec![values[0], 0.0]),
+                        2 | 3 => texcoords.push(values[0..2].to_vec()),
+                        _ => return Err(error("bad texture coordinate")),
+                    }
+                }
+
+                Some("o") | Some("g") => {
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    let texture = objects.last().and_then(|object| object.texture.clone());
+
+                    objects.push(Object::new(name, texture));
+                }
+
+                Some("usemtl") => {
+                    let material = words.collect::<Vec<_>>().join(" ");
+
+                    match objects.last_mut() {
+                        Some(object) if object.triangles.is_empty() => {
+                            object.texture = Some(material)
+                        }
+                        Some(object) if object.texture.as_ref() == Some(&material) => (),
+                        _ => {
+                            let name = match objects.last() {
+                                Some(object) => format!("{}_{}", object.name, material),
+                                None => material.clone(),
+                            };
+
+                            objects.push(Object::new(name, Some(material)));
+                        }
+                    }
+                }
+
+                Some("f") => {
+                    let corners = words
+                        .map(|corner| {
+                            let mut indices = corner.split('/');
+
+                            let position = indices
+                                .next()
+                                .and_then(|index| resolve_index(index, positions.len()))
+                                .ok_or_else(|| error("bad vertex index"))?;
+                            let texcoord = match indices.next() {
+                                None | Some("") => None,
+                                Some(index) => Some(
+                                    resolve_index(index, texcoords.len())
+                                        .ok_or_else(|| error("bad texture coordinate index"))?,
+                                ),
+                            };
+
+                            Ok::<_, ImportError>((position, texcoord))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    if corners.len() < 3 {
+                        return Err(error("face has fewer than three vertices"));
+                    }
+
+                    if objects.is_empty() {
+                        objects.push(Object::new(String::new(), None));
+                    }
+                    let object = objects.last_mut().unwrap();
+
+                    let mut indices = Vec::with_capacity(corners.len());
+                    for (position, texcoord) in corners {
+                        let next = object.vertices.len();
+                        let index = *object.indices.entry((position, texcoord)).or_insert(next);
+
+                        if index == next {
+                            let texcoord = texcoord.map(|texcoord| &texcoords[texcoord][..]);
+                            object.vertices.push(vertex(&positions[position], texcoord));
+                        }
+
+                        indices.push(index);
+                    }
+
+                    // Faces are convex, so a fan will do.
+                    for i in 1..indices.len() - 1 {
+                        object
+                            .triangles
+                            .push([indices[0], indices[i], indices[i + 1]]);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+
+        let mut vertices = Vec::new();
+        let children = objects
+            .into_iter()
+            .filter(|object| !object.triangles.is_empty())
+            .enumerate()
+            .map(|(i, object)| Node {
+                kind: NodeKind::Model,
+                name: if object.name.is_empty() {
+                    format!("model_{}", i)
+                } else {
+                    object.name
+                },
+                properties: texture_properties(object.texture),
+                display_list: Some(DisplayList::from_triangles(
+                    &object.vertices,
+                    &object.triangles,
+                    &mut vertices,
+                )),
+                group: None,
+            })
+            .collect();
+
+        let root = Node {
+            kind: NodeKind::Root,
+            name: "Root".to_string(),
+            properties: Vec::new(),
+            display_list: None,
+            group: Some(Group {
+                transform: None,
+                lights: None,
+                children,
+            }),
+        };
+
+        Ok(Shape::from_root(root, vertices, Vec::new(), Vec::new()))
+    }
+}
+
+fn vertex((position, color): &(Vec<f64>, Option<Vec<f64>>), texcoord: Option<&[f64]>) -> Vertex {
+    let channel = |n: usize| match color {
+        Some(color) => (color[n] * 255.0).round().max(0.0).min(255.0) as u8,
+        None => 0xFF,
+    };
+
+    // OBJ puts the origin of texture space at the bottom.
+    let (u, v) = texcoord.map_or((0.0, 0.0), |uv| (uv[0], 1.0 - uv[1]));
+
+    Vertex {
+        x: quantize(position[0]),
+        y: quantize(position[1]),
+        z: quantize(position[2]),
+        texture_x: quantize(u * TEXEL_SCALE),
+        texture_y: quantize(v * TEXEL_SCALE),
+        color: Color {
+            r: channel(0),
+            g: channel(1),
+            b: channel(2),
+            a: 0xFF,
+        },
+    }
+}
+
+fn parse_floats<'a, I: Iterator<Item = &'a str>>(words: I) -> Option<Vec<f64>> {
+    words.map(|word| word.parse().ok()).collect()
+}
+
+/// Converts a one-based (or, if negative, relative) index into a zero-based
+/// one.
+fn resolve_index(index: &str, len: usize) -> Option<usize> {
+    match index.parse::<isize>().ok()? {
+        0 => None,
+        index if index > 0 && index as usize <= len => Some(index as usize - 1),
+        index if index < 0 && index.abs() as usize <= len => Some(len - index.abs() as usize),
+        _ => None,
+    }
+}
@@ -1,52 +1,125 @@
+use failure_derive::*;
 use std::convert::TryInto;
 
 pub static MAGIC: u32 = 0x59617930; // "yay0"
 
-pub fn decompress(source: &[u8]) -> Vec<u8> {
+#[derive(Debug, Fail)]
+pub enum Yay0Error {
+    #[fail(display = "bad Yay0 magic: {:#010X}", _0)]
+    BadMagic(u32),
+
+    #[fail(display = "Yay0 data is truncated at offset {:#X}", _0)]
+    Truncated(usize),
+
+    #[fail(
+        display = "back-reference at output offset {:#X} points {} bytes before the start of the output",
+        position, distance
+    )]
+    BadBackReference { position: usize, distance: usize },
+
+    #[fail(
+        display = "{} bytes decoded at output offset {:#X} overrun the decompressed size of {:#X}",
+        length, position, size
+    )]
+    OutputOverrun {
+        position: usize,
+        length: usize,
+        size: usize,
+    },
+
+    #[fail(
+        display = "output buffer holds {:#X} bytes but {:#X} are needed",
+        available, needed
+    )]
+    BufferTooSmall { available: usize, needed: usize },
+
+    #[fail(
+        display = "header gives a decompressed size of {:#X}, but {:#X} was expected",
+        header, expected
+    )]
+    SizeMismatch { header: usize, expected: usize },
+}
+
+/// Reads the decompressed size from the header of a Yay0 stream.
+pub fn decompressed_size(source: &[u8]) -> Result<usize, Yay0Error> {
+    let magic = read_u32(source, 0x00)?;
+    if magic != MAGIC {
+        return Err(Yay0Error::BadMagic(magic));
+    }
+
+    Ok(read_u32(source, 0x04)? as usize)
+}
+
+pub fn decompress(source: &[u8]) -> Result<Vec<u8>, Yay0Error> {
+    let mut decoded = vec![0u8; decompressed_size(source)?];
+    decompress_into(source, &mut decoded)?;
+    Ok(decoded)
+}
+
+/// Decompresses `source` into the start of `decoded`, which must be at least
+/// `decompressed_size(source)` bytes long. Returns the number of bytes written.
+pub fn decompress_into(source: &[u8], decoded: &mut [u8]) -> Result<usize, Yay0Error> {
     // Header (16 bytes)
-    assert_eq!(
-        MAGIC,
-        u32::from_be_bytes(source[0x00..0x04].try_into().unwrap())
-    );
-    let decompressed_size = u32::from_be_bytes(source[0x04..0x08].try_into().unwrap()) as usize;
-    let mut link_offset = u32::from_be_bytes(source[0x08..0x0C].try_into().unwrap()) as usize;
-    let mut source_offset = u32::from_be_bytes(source[0x0C..0x10].try_into().unwrap()) as usize;
+    let decompressed_size = decompressed_size(source)?;
+    let mut link_offset = read_u32(source, 0x08)? as usize;
+    let mut source_offset = read_u32(source, 0x0C)? as usize;
+
+    if decoded.len() < decompressed_size {
+        return Err(Yay0Error::BufferTooSmall {
+            available: decoded.len(),
+            needed: decompressed_size,
+        });
+    }
 
     let mut current_command = 0u8;
     let mut command_offset = 16;
     let mut remaining_bits = 0;
 
-    let mut decoded = vec![0u8; decompressed_size];
     let mut decoded_bytes = 0;
 
     while decoded_bytes < decompressed_size {
         if remaining_bits == 0 {
-            current_command = source[command_offset];
+            current_command = read_u8(source, command_offset)?;
             command_offset += 1;
             remaining_bits = 8;
         }
 
         if (current_command & 0x80) != 0 {
-            decoded[decoded_bytes] = source[source_offset];
+            decoded[decoded_bytes] = read_u8(source, source_offset)?;
             source_offset += 1;
             decoded_bytes += 1;
         } else {
-            let link = u16::from_be_bytes(source[link_offset..link_offset + 2].try_into().unwrap());
+            let link = read_u16(source, link_offset)?;
             link_offset += 2;
 
-            let dist = link & 0xFFF;
-            let copy_src = decoded_bytes - usize::from(dist + 1);
+            let dist = usize::from(link & 0xFFF) + 1;
+            if dist > decoded_bytes {
+                return Err(Yay0Error::BadBackReference {
+                    position: decoded_bytes,
+                    distance: dist,
+                });
+            }
+            let copy_src = decoded_bytes - dist;
 
-            let mut length = link >> 12 & 0xF;
+            let mut length = usize::from(link >> 12 & 0xF);
             if length == 0 {
-                length = u16::from(source[source_offset]);
+                length = usize::from(read_u8(source, source_offset)?);
                 length += 16;
                 source_offset += 1;
             }
             length += 2;
 
+            if decoded_bytes + length > decompressed_size {
+                return Err(Yay0Error::OutputOverrun {
+                    position: decoded_bytes,
+                    length,
+                    size: decompressed_size,
+                });
+            }
+
+            // Byte-by-byte, as the source and destination may overlap.
             for i in 0..length {
-                decoded[decoded_bytes] = decoded[copy_src + usize::from(i)];
+                decoded[decoded_bytes] = decoded[copy_src + i];
                 decoded_bytes += 1;
             }
         }
@@ -55,5 +128,281 @@ pub fn decompress(source: &[u8]) -> Vec<u8> {
         remaining_bits -= 1;
     }
 
-    decoded
+    Ok(decoded_bytes)
+}
+
+fn read_u8(source: &[u8], offset: usize) -> Result<u8, Yay0Error> {
+    source
+        .get(offset)
+        .cloned()
+        .ok_or(Yay0Error::Truncated(offset))
+}
+
+fn read_u16(source: &[u8], offset: usize) -> Result<u16, Yay0Error> {
+    source
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or(Yay0Error::Truncated(offset))
+}
+
+fn read_u32(source: &[u8], offset: usize) -> Result<u32, Yay0Error> {
+    source
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or(Yay0Error::Truncated(offset))
+}
+
+/// Largest back-reference distance a link can encode (12 bits).
+const WINDOW_SIZE: usize = 0x1000;
+
+/// Shortest run worth encoding as a link rather than as literal bytes.
+const MIN_MATCH: usize = 3;
+
+/// Longest run a link can encode: an extra length byte on top of the 4-bit
+/// length nibble gives 0xFF + 16 + 2.
+const MAX_MATCH: usize = 0xFF + 0x12;
+
+/// How many earlier positions we are willing to examine when looking for the
+/// longest match. Higher values compress (very) slightly better but slower.
+const MAX_CHAIN: usize = 512;
+
+const HASH_BITS: u32 = 15;
+
+/// Compresses `source` into a Yay0 stream that `decompress` can read back.
+///
+/// Matches are found with a hash chain over the preceding 4 KiB window, and
+/// the choice between literals, short links and long links is made with an
+/// optimal (shortest-output) parse rather than a greedy one, which keeps us
+/// on par with - and usually a little smaller than - the vanilla assets.
+pub fn compress(source: &[u8]) -> Vec<u8> {
+    let longest = longest_matches(source);
+
+    // cost[i] is the smallest number of bits needed to encode source[i..];
+    // step[i] is the length of the token that achieves it (1 = literal).
+    let mut cost = vec![0usize; source.len() + 1];
+    let mut step = vec![1usize; source.len()];
+
+    for i in (0..source.len()).rev() {
+        cost[i] = cost[i + 1] + 9;
+
+        let (_, max_len) = longest[i];
+        for len in MIN_MATCH..=max_len {
+            let link_cost = if len < 0x12 { 17 } else { 25 };
+
+            if cost[i + len] + link_cost < cost[i] {
+                cost[i] = cost[i + len] + link_cost;
+                step[i] = len;
+            }
+        }
+    }
+
+    let mut commands: Vec<u32> = Vec::new();
+    let mut links: Vec<u8> = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    let mut bit = 0;
+
+    let mut i = 0;
+    while i < source.len() {
+        if bit == 0 {
+            commands.push(0);
+            bit = 32;
+        }
+        bit -= 1;
+
+        let len = step[i];
+        if len == 1 {
+            *commands.last_mut().unwrap() |= 1 << bit;
+            data.push(source[i]);
+        } else {
+            let (dist, _) = longest[i];
+            let dist = (dist - 1) as u16;
+
+            if len < 0x12 {
+                links.extend_from_slice(&((((len - 2) as u16) << 12) | dist).to_be_bytes());
+            } else {
+                links.extend_from_slice(&dist.to_be_bytes());
+                data.push((len - 0x12) as u8);
+            }
+        }
+
+        i += len;
+    }
+
+    let link_offset = 16 + commands.len() * 4;
+    let data_offset = link_offset + links.len();
+
+    let mut compressed = Vec::with_capacity(data_offset + data.len());
+    compressed.extend_from_slice(&MAGIC.to_be_bytes());
+    compressed.extend_from_slice(&(source.len() as u32).to_be_bytes());
+    compressed.extend_from_slice(&(link_offset as u32).to_be_bytes());
+    compressed.extend_from_slice(&(data_offset as u32).to_be_bytes());
+    for command in commands {
+        compressed.extend_from_slice(&command.to_be_bytes());
+    }
+    compressed.extend_from_slice(&links);
+    compressed.extend_from_slice(&data);
+    compressed
+}
+
+/// Finds, for every position in `source`, the distance and length of the
+/// longest match in the preceding window. A length of 0 means no usable match.
+///
+/// The cost of a link doesn't depend on its distance, so the longest match is
+/// all the parser needs: every shorter length is available at the same
+/// distance.
+fn longest_matches(source: &[u8]) -> Vec<(usize, usize)> {
+    let mut longest = vec![(0, 0); source.len()];
+
+    // Hash chains; `head` holds the most recent position + 1 for each hash,
+    // `prev` links each position to the previous one with the same hash.
+    let mut head = vec![0usize; 1 << HASH_BITS];
+    let mut prev = vec![0usize; source.len()];
+
+    let hash = |i: usize| {
+        let key =
+            u32::from(source[i]) << 16 | u32::from(source[i + 1]) << 8 | u32::from(source[i + 2]);
+        (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+
+    for i in 0..source.len().saturating_sub(MIN_MATCH - 1) {
+        let h = hash(i);
+        let max_len = MAX_MATCH.min(source.len() - i);
+
+        let mut best = (0, 0);
+        let mut candidate = head[h];
+        let mut chain = 0;
+
+        while candidate != 0 && chain < MAX_CHAIN {
+            let j = candidate - 1;
+            if i - j > WINDOW_SIZE {
+                break;
+            }
+
+            // Quick reject: a longer match must also agree at best.1.
+            if best.1 == 0 || source[j + best.1] == source[i + best.1] {
+                let len = source[j..]
+                    .iter()
+                    .zip(&source[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if len > best.1 {
+                    best = (i - j, len);
+
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+
+            candidate = prev[j];
+            chain += 1;
+        }
+
+        if best.1 >= MIN_MATCH {
+            longest[i] = best;
+        }
+
+        prev[i] = head[h];
+        head[h] = i + 1;
+    }
+
+    longest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::{test_rom, RomRead};
+    use std::io::{Read, Seek, SeekFrom};
+
+    fn round_trip(source: &[u8]) -> Vec<u8> {
+        let compressed = compress(source);
+        assert_eq!(decompress(&compressed).unwrap(), source);
+        compressed
+    }
+
+    /// Deterministic bytes with (almost) no repeats worth linking to.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        round_trip(&[]);
+    }
+
+    #[test]
+    fn no_repeats() {
+        let source: Vec<u8> = (0..=255).collect();
+        let compressed = round_trip(&source);
+
+        // Every byte is a literal: 16-byte header, 8 commands, no links.
+        assert_eq!(compressed.len(), 16 + 8 * 4 + 256);
+    }
+
+    #[test]
+    fn long_run() {
+        let compressed = round_trip(&vec![0xAA; 10_000]);
+        assert!(compressed.len() < 200);
+    }
+
+    #[test]
+    fn max_match() {
+        // A block of noise followed by its start again: the repeat can only
+        // be found at exactly the largest distance a link can encode.
+        let mut source = noise(WINDOW_SIZE);
+        source.extend_from_slice(&source[..MAX_MATCH + 10].to_vec());
+
+        assert_eq!(
+            longest_matches(&source)[WINDOW_SIZE],
+            (WINDOW_SIZE, MAX_MATCH)
+        );
+        round_trip(&source);
+    }
+
+    #[test]
+    fn beyond_window() {
+        // One byte further back than a link can reach.
+        let mut source = noise(WINDOW_SIZE + 1);
+        source.extend_from_slice(&source[..64].to_vec());
+
+        assert_eq!(longest_matches(&source)[WINDOW_SIZE + 1].0, 0);
+        round_trip(&source);
+    }
+
+    #[test]
+    #[ignore]
+    fn real_asset() {
+        let mut rom = test_rom();
+
+        // The first compressed asset in the USA asset table.
+        let table = 0x1E40020;
+        let mut entry = table;
+        let packed = loop {
+            rom.file.seek(SeekFrom::Start(entry + 16)).unwrap();
+            let offset = u32::read(&mut rom).unwrap();
+            let compressed_size = u32::read(&mut rom).unwrap();
+
+            let mut packed = vec![0u8; compressed_size as usize];
+            rom.file
+                .seek(SeekFrom::Start(table + u64::from(offset)))
+                .unwrap();
+            rom.file.read_exact(&mut packed).unwrap();
+
+            if packed.starts_with(b"Yay0") {
+                break packed;
+            }
+
+            entry += 28;
+        };
+
+        round_trip(&decompress(&packed).unwrap());
+    }
 }
@@ -3,4 +3,4 @@
 pub mod data;
 pub mod mod_dir;
 pub mod rom;
-// pub mod script;
+pub mod script;
@@ -1,18 +1,55 @@
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::path::Path;
 
+use ztar_rod::data::area::AreaTable;
 use ztar_rod::data::map::asset_table::AssetTable;
 use ztar_rod::mod_dir::ModDir;
 use ztar_rod::rom::*;
+use ztar_rod::script::api::Api;
+use ztar_rod::script::check::check_script;
+use ztar_rod::script::diagnostic::Diagnostic;
+use ztar_rod::script::parse::parse_script;
+use ztar_rod::script::{decompile_map, disassemble_map};
+
+/// Read from the working directory if present, like the rom; otherwise the
+/// database bundled with ztar-rod is used.
+static API_DATABASE: &str = "api.json";
 
 fn main() {
-    static ROM_JAPAN: &'static str = "Mario Story (J) [!].z64";
-    static ROM_AMERICA: &'static str = "Paper Mario (U) [!].z64";
-    static ROM_EUROPE: &'static str = "Paper Mario (Europe) (En,Fr,De,Es).z64";
+    static ROM_JAPAN: &str = "Mario Story (J) [!].z64";
+    static ROM_AMERICA: &str = "Paper Mario (U) [!].z64";
+    static ROM_EUROPE: &str = "Paper Mario (Europe) (En,Fr,De,Es).z64";
+
+    let command = std::env::args().nth(1);
+
+    // Checking a script doesn't need the rom, only which one it's for.
+    if let Some("check") = command.as_ref().map(String::as_str) {
+        let region = match std::env::args().nth(3).as_ref().map(String::as_str) {
+            None | Some("us") => Some(Region::America),
+            Some("jp") => Some(Region::Japan),
+            Some("pal") => Some(Region::Europe),
+            Some(_) => None,
+        };
+
+        match (std::env::args().nth(2), region) {
+            (Some(path), Some(region)) => {
+                if let Err(error) = check(&path, region) {
+                    println!("{}", error);
+                }
+            }
+            _ => println!("usage: check <script> [jp|us|pal]"),
+        }
+        return;
+    }
 
     match File::open(ROM_AMERICA) {
         Err(_) => println!("unable to open rom"),
-        Ok(rom) => match dump(rom) {
+        Ok(rom) => match match command.as_ref().map(String::as_str) {
+            Some("build") => build(rom),
+            Some("maps") => list_maps(rom),
+            Some("decompile") => decompile(rom),
+            _ => dump(rom),
+        } {
             Err(error) => println!("{}", error),
             Ok(()) => (),
         },
@@ -29,3 +66,102 @@ fn dump(rom: File) -> Result<(), failure::Error> {
 
     Ok(())
 }
+
+fn build(rom: File) -> Result<(), failure::Error> {
+    static ROM_OUTPUT: &str = "mod.z64";
+
+    let mut rom = Rom::from(rom)?;
+    let mod_dir = ModDir::open(Path::new("./mod"));
+
+    let asset_table = AssetTable::build(&mod_dir)?;
+
+    let output = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(ROM_OUTPUT)?;
+    let mut output = rom.build(output, &[])?;
+
+    asset_table.write(&mut output)?;
+
+    Ok(())
+}
+
+fn list_maps(rom: File) -> Result<(), failure::Error> {
+    let mut rom = Rom::from(rom)?;
+
+    for area in AreaTable::read(&mut rom)?.areas {
+        println!("{}", area.name);
+
+        for map in area.maps {
+            println!(
+                "  {:<8} {:#010X}..{:#010X} -> {:#010X}",
+                map.name, map.dma.start, map.dma.end, map.dma.dest
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn decompile(rom: File) -> Result<(), failure::Error> {
+    let mut rom = Rom::from(rom)?;
+    let mod_dir = ModDir::open(Path::new("./mod"));
+    let api = Api::open(Path::new(API_DATABASE))?;
+
+    for area in AreaTable::read(&mut rom)?.areas {
+        for map in area.maps {
+            // Scripts that can't be decompiled are disassembled instead.
+            let (source, extension) = match decompile_map(&map, &mut rom, &api) {
+                Ok(source) => (source, "txt"),
+                Err(error) => {
+                    println!("unable to decompile map {}: {}", map.name, error);
+
+                    match disassemble_map(&map, &mut rom) {
+                        Ok(source) => (source, "asm"),
+                        Err(error) => {
+                            println!("skipping map {}: {}", map.name, error);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            println!("dumping script: {}", map.name);
+
+            let path = mod_dir.script(map.name.as_str(), extension);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            fs::write(path, source)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn check(path: &str, region: Region) -> Result<(), failure::Error> {
+    let source = fs::read_to_string(path)?;
+
+    let script = match parse_script(&source) {
+        Ok(script) => script,
+        Err(error) => {
+            print!("{}", Diagnostic::from(&error).render(path, &source));
+            return Ok(());
+        }
+    };
+
+    let api = Api::open(Path::new(API_DATABASE))?;
+
+    match check_script(&script, &api, region) {
+        Ok(()) => println!("{}: ok", path),
+        Err(errors) => {
+            for error in errors.iter() {
+                print!("{}", Diagnostic::from(error).render(path, &source));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -17,6 +17,9 @@ impl<'a> ModDir<'a> {
         fs::create_dir(self.root)?;
         fs::create_dir(self.root.join("./build/"))?;
         fs::create_dir(self.root.join("./map/"))?;
+        fs::create_dir(self.root.join("./map/shape/"))?;
+        fs::create_dir(self.root.join("./map/hit/"))?;
+        fs::create_dir(self.root.join("./map/script/"))?;
         fs::create_dir(self.root.join("./img/"))?;
         fs::create_dir(self.root.join("./img/bg/"))?;
 
@@ -31,6 +34,20 @@ impl<'a> ModDir<'a> {
         self.root.join("./map/AssetTable.txt")
     }
 
+    pub fn shape(&self, asset_name: &str, extension: &str) -> PathBuf {
+        self.root
+            .join(format!("./map/shape/{}.{}", asset_name, extension))
+    }
+
+    pub fn hit(&self, asset_name: &str) -> PathBuf {
+        self.root.join(format!("./map/hit/{}.obj", asset_name))
+    }
+
+    pub fn script(&self, map_name: &str, extension: &str) -> PathBuf {
+        self.root
+            .join(format!("./map/script/{}.{}", map_name, extension))
+    }
+
     pub fn background(&self, filename: &str) -> PathBuf {
         self.root.join(format!("./img/bg/{}.png", filename))
     }
@@ -65,11 +65,15 @@ pub struct Dma {
 }
 
 impl Dma {
-    /// Panics if `dest` is not a virtual address (see `is_vaddr`).
+    /// Panics if `dest` is not a virtual address (see `is_vaddr`), or if `end`
+    /// comes before `start`.
     pub fn new(start: u32, end: u32, dest: u32) -> Dma {
         if !is_vaddr(dest) {
             panic!("Dma::new called with non-vaddr destination");
         }
+        if end < start {
+            panic!("Dma::new called with end before start");
+        }
 
         Dma { start, end, dest }
     }
@@ -81,6 +85,11 @@ impl Dma {
         }
     }
 
+    /// Returns true if `vaddr` lies within the destination of this DMA.
+    pub fn contains_vaddr(&self, vaddr: u32) -> bool {
+        vaddr >= self.dest && vaddr - self.dest < self.len()
+    }
+
     pub fn loc_at_offset(&self, offset: u32) -> Location {
         Location {
             base: self.start,
@@ -92,3 +101,35 @@ impl Dma {
         self.end - self.start
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dma() {
+        let dma = Dma::new(0x1000, 0x1400, 0x8010_0000);
+
+        assert_eq!(dma.len(), 0x400);
+        assert!(!dma.contains_vaddr(0x800F_FFFF));
+        assert!(dma.contains_vaddr(0x8010_0000));
+        assert!(dma.contains_vaddr(0x8010_03FF));
+        assert!(!dma.contains_vaddr(0x8010_0400));
+
+        let loc = dma.loc_at_vaddr(0x8010_0123);
+        assert_eq!(loc, dma.loc_at_offset(0x123));
+        assert_eq!(Into::<u32>::into(loc), 0x1123);
+    }
+
+    #[test]
+    fn empty_dma() {
+        let dma = Dma::new(0x1000, 0x1000, 0x8010_0000);
+        assert!(!dma.contains_vaddr(0x8010_0000));
+    }
+
+    #[test]
+    #[should_panic]
+    fn backwards_dma() {
+        Dma::new(0x1400, 0x1000, 0x8010_0000);
+    }
+}
@@ -5,15 +5,17 @@ use std::fmt;
 use std::fs::File;
 pub use std::io::{prelude::*, SeekFrom};
 
-//pub mod loc;
-//use loc::*;
+use crate::data::yay0::Yay0Error;
 
-/// Wrapper struct for reading (and, soon, writing) a ROM file.
+pub mod loc;
+
+/// Wrapper struct for reading and writing a ROM file.
 pub struct Rom {
     pub file: File,
     pub region: Region,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Region {
     Japan,
     America,
@@ -60,82 +62,113 @@ impl Rom {
         })
     }
 
-    /*
-    /// Reads an `Area`. Panics if data is missing or malformed.
-    pub fn read_area(&mut self) -> Area {
-        let map_count = self.read_u32();
-        let maps_addr = self.read_ptr().expect("Area has null maplist");
-        let name_addr = self.read_ptr().expect("Area has null name");
-
-        Area {
-            name: self.seek(Location::new(name_addr)).read_string(),
-            maps: {
-                let maps_loc = Location::new(maps_addr);
-                let mut maps = Vec::with_capacity(map_count as usize);
-
-                for i in 0..map_count {
-                    self.seek(maps_loc.add_offset(i * 32));
-                    maps.push(self.read_map())
-                }
-
-                maps
-            },
+    /// Copies this ROM into `output`, applies each of `patches` in order and
+    /// fixes up the header checksum so the result will boot. `output` must be
+    /// opened for both reading and writing.
+    pub fn build(&mut self, output: File, patches: &[Patch]) -> Result<Rom, WriteError> {
+        let mut rom = Rom {
+            file: output,
+            region: self.region,
+        };
+
+        self.file.seek(SeekFrom::Start(0))?;
+        rom.file.set_len(0)?;
+        rom.file.seek(SeekFrom::Start(0))?;
+        std::io::copy(&mut self.file, &mut rom.file)?;
+
+        for patch in patches {
+            rom.apply(patch)?;
         }
+
+        rom.update_checksum()?;
+
+        Ok(rom)
     }
 
-    /// Reads `Map`. Panics if data is missing or malformed.
-    pub fn read_map(&mut self) -> Map {
-        let name_addr = self.read_ptr().expect("Map has null name");
-        let header_vaddr = self.read_u32();
-        let dma = Dma::new(self.read_u32(), self.read_u32(), self.read_u32());
-        let bg_name_addr = self.read_ptr();
-        let init_asm_vaddr = self.read_u32(); // might be null
-        let flags = self.read_u32();
-
-        let header_loc = dma.loc_at_vaddr(header_vaddr);
-
-        Map {
-            name: self.seek(Location::new(name_addr)).read_string(),
-            dma,
-            init_asm: match init_asm_vaddr {
-                0 => None,
-                _ => Some(dma.loc_at_vaddr(init_asm_vaddr)),
-            },
-            main_fun: {
-                self.seek(header_loc.add_offset(0x10));
-                let loc = dma.loc_at_vaddr(self.read_u32());
-                self.seek(loc);
-                (loc, Bytecode::read(self))
-            },
-            entrances: {
-                self.seek(header_loc.add_offset(0x14));
-
-                let vaddr = self.read_u32();
-                let count = self.read_u32();
-
-                self.seek(dma.loc_at_vaddr(vaddr));
-                let mut entrances = Vec::new();
-
-                for _ in 0..count {
-                    entrances.push(Entrance {
-                        x: self.read_f32(),
-                        y: self.read_f32(),
-                        z: self.read_f32(),
-                        yaw: self.read_f32(),
-                    })
-                }
+    /// Writes a single `Patch` over the ROM. The ROM is extended if the patch
+    /// runs past its end.
+    pub fn apply(&mut self, patch: &Patch) -> Result<(), WriteError> {
+        self.file.seek(SeekFrom::Start(u64::from(patch.offset)))?;
+        self.file.write_all(&patch.bytes)?;
+        Ok(())
+    }
 
-                entrances
-            },
-            tattle: MapTattle::from(Location::new(self.seek(header_loc.add_offset(0x3C)).read_u32())),
-            background: match bg_name_addr {
-                Some(addr) => Some(self.seek(Location::new(addr)).read_string()),
-                None => None,
-            },
-            flags,
+    /// Recalculates the boot checksum (CRC1 and CRC2) in the ROM header. This
+    /// must be done after any write to the first 1MiB of game code, otherwise
+    /// the CIC will refuse to boot the ROM.
+    pub fn update_checksum(&mut self) -> Result<(), WriteError> {
+        // Paper Mario uses the CIC-NUS-6103 bootcode.
+        const SEED: u32 = 0xA3886759;
+        const START: u64 = 0x1000;
+        const LENGTH: usize = 0x100000;
+
+        let mut data = vec![0u8; LENGTH];
+        self.file.seek(SeekFrom::Start(START))?;
+        self.file.read_exact(&mut data)?;
+
+        let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (SEED, SEED, SEED, SEED, SEED, SEED);
+
+        for word in data.chunks(4) {
+            let d = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+
+            if t6.wrapping_add(d) < t6 {
+                t4 = t4.wrapping_add(1);
+            }
+            t6 = t6.wrapping_add(d);
+            t3 ^= d;
+
+            let r = d.rotate_left(d & 0x1F);
+            t5 = t5.wrapping_add(r);
+
+            if t2 > d {
+                t2 ^= r;
+            } else {
+                t2 ^= t6 ^ d;
+            }
+
+            t1 = t1.wrapping_add(t5 ^ d);
         }
+
+        self.file.seek(SeekFrom::Start(0x10))?;
+        ((t6 ^ t4).wrapping_add(t3)).write(self)?;
+        ((t5 ^ t2).wrapping_add(t1)).write(self)?;
+
+        Ok(())
     }
-    */
+}
+
+/// The USA rom, for tests that need real game data. Such tests are ignored by
+/// default; put the rom in the repository root and run them with
+/// `cargo test -- --ignored`.
+#[cfg(test)]
+pub fn test_rom() -> Rom {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../Paper Mario (U) [!].z64");
+    let file = File::open(path).expect("tests marked #[ignore] need the USA rom");
+    Rom::from(file).unwrap()
+}
+
+/// An empty USA rom of `len` bytes, for tests that write. Its file is deleted
+/// once the rom is dropped.
+#[cfg(test)]
+pub fn scratch_rom(name: &str, len: u64) -> Rom {
+    let path = std::env::temp_dir().join(format!("ztar-rod-{}.z64", name));
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+
+    file.set_len(len).unwrap();
+    file.write_all(&[0x80]).unwrap();
+    file.seek(SeekFrom::Start(0x3E)).unwrap();
+    file.write_all(b"E").unwrap();
+
+    // Unix keeps the file around until it is closed.
+    let _ = std::fs::remove_file(&path);
+
+    Rom::from(file).unwrap()
 }
 
 pub trait RomRead {
@@ -151,6 +184,22 @@ pub trait RomReadLen {
         Self: Sized;
 }
 
+pub trait RomWrite {
+    fn write(&self, rom: &mut Rom) -> Result<(), WriteError>;
+}
+
+pub trait RomWriteLen {
+    /// Should always write exactly `len` bytes.
+    fn write_len(&self, rom: &mut Rom, len: usize) -> Result<(), WriteError>;
+}
+
+/// Bytes to be written over the original ROM at `offset`; see `Rom::build`.
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
 #[derive(Debug, Fail)]
 pub enum ReadError {
     #[fail(display = "unexpected end of file")]
@@ -159,6 +208,21 @@ pub enum ReadError {
     #[fail(display = "bad ASCII string: {}", _0)]
     BadAscii(#[fail(cause)] ToAsciiCharError),
 
+    #[fail(display = "asset {} is corrupt: {}", _0, _1)]
+    BadAsset(AsciiString, #[fail(cause)] Yay0Error),
+
+    #[fail(display = "asset {} lies outside of the rom (offset {:#X})", _0, _1)]
+    BadAssetOffset(AsciiString, u32),
+
+    #[fail(display = "pointer {:#010X} lies outside of its segment", _0)]
+    BadPointer(u32),
+
+    #[fail(display = "DMA {:#X}..{:#X} ends before it starts", _0, _1)]
+    BadDma(u32, u32),
+
+    #[fail(display = "{:?} roms are not supported yet", _0)]
+    UnsupportedRegion(Region),
+
     #[fail(display = "{}", _0)]
     Io(#[fail(cause)] std::io::Error),
 }
@@ -175,6 +239,27 @@ impl From<std::io::Error> for ReadError {
     }
 }
 
+#[derive(Debug, Fail)]
+pub enum WriteError {
+    #[fail(display = "string '{}' does not fit in {} bytes", _0, _1)]
+    StringTooLong(AsciiString, usize),
+
+    #[fail(
+        display = "{} is {:#X} bytes, but only {:#X} fit in the rom",
+        _0, _1, _2
+    )]
+    TooLarge(String, u32, u32),
+
+    #[fail(display = "{}", _0)]
+    Io(#[fail(cause)] std::io::Error),
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(error: std::io::Error) -> WriteError {
+        WriteError::Io(error)
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum Pointer {
     Address(u32),
@@ -269,3 +354,168 @@ impl RomReadLen for AsciiString {
         Ok(string)
     }
 }
+
+impl RomWrite for u32 {
+    fn write(&self, rom: &mut Rom) -> Result<(), WriteError> {
+        rom.file.write_all(&self.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+impl RomWrite for f32 {
+    fn write(&self, rom: &mut Rom) -> Result<(), WriteError> {
+        self.to_bits().write(rom)
+    }
+}
+
+impl RomWrite for Pointer {
+    fn write(&self, rom: &mut Rom) -> Result<(), WriteError> {
+        match self {
+            // Undo the overflow performed by `Pointer::read`.
+            Pointer::Address(addr) => addr.wrapping_sub(2_147_333_120).write(rom),
+            Pointer::NullPtr => 0u32.write(rom),
+        }
+    }
+}
+
+impl RomWrite for AsciiString {
+    fn write(&self, rom: &mut Rom) -> Result<(), WriteError> {
+        rom.file.write_all(self.as_bytes())?;
+        rom.file.write_all(&[0])?; // Null-terminator.
+        Ok(())
+    }
+}
+
+impl RomWriteLen for AsciiString {
+    fn write_len(&self, rom: &mut Rom, len: usize) -> Result<(), WriteError> {
+        // Strings that fill the field exactly aren't null-terminated, which
+        // `read_len` handles.
+        if self.len() > len {
+            return Err(WriteError::StringTooLong(self.clone(), len));
+        }
+
+        let mut buf = vec![0u8; len];
+        buf[..self.len()].copy_from_slice(self.as_bytes());
+        rom.file.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_bytes(rom: &mut Rom, offset: u64, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        rom.file.seek(SeekFrom::Start(offset)).unwrap();
+        rom.file.read_exact(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn write_then_read() {
+        let mut rom = scratch_rom("write_then_read", 0x100);
+        let name = AsciiString::from_ascii("kmr_00_shape").unwrap();
+
+        rom.file.seek(SeekFrom::Start(0x40)).unwrap();
+        0x1234_5678u32.write(&mut rom).unwrap();
+        (-1.5f32).write(&mut rom).unwrap();
+        Pointer::Address(0x8024_0000).write(&mut rom).unwrap();
+        Pointer::NullPtr.write(&mut rom).unwrap();
+        name.write(&mut rom).unwrap();
+        name.write_len(&mut rom, 16).unwrap();
+        name.write_len(&mut rom, 12).unwrap();
+
+        rom.file.seek(SeekFrom::Start(0x40)).unwrap();
+        assert_eq!(u32::read(&mut rom).unwrap(), 0x1234_5678);
+        assert_eq!(f32::read(&mut rom).unwrap(), -1.5);
+        match Pointer::read(&mut rom).unwrap() {
+            Pointer::Address(0x8024_0000) => (),
+            pointer => panic!("expected Address(0x80240000), got {:?}", pointer),
+        }
+        match Pointer::read(&mut rom).unwrap() {
+            Pointer::NullPtr => (),
+            pointer => panic!("expected NullPtr, got {:?}", pointer),
+        }
+        assert_eq!(AsciiString::read(&mut rom).unwrap(), name);
+        assert_eq!(AsciiString::read_len(&mut rom, 16).unwrap(), name);
+        assert_eq!(AsciiString::read_len(&mut rom, 12).unwrap(), name);
+
+        match name.write_len(&mut rom, 11) {
+            Err(WriteError::StringTooLong(_, 11)) => (),
+            result => panic!("expected StringTooLong, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn apply_extends() {
+        let mut rom = scratch_rom("apply_extends", 0x100);
+        rom.apply(&Patch {
+            offset: 0xFE,
+            bytes: vec![1, 2, 3, 4],
+        })
+        .unwrap();
+
+        assert_eq!(rom.file.metadata().unwrap().len(), 0x102);
+        assert_eq!(read_bytes(&mut rom, 0xFC, 6), [0, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn blank_checksum() {
+        // With no code at all, CRC1 works out as the seed and CRC2 as the seed
+        // times one more than the number of words checksummed.
+        let mut rom = scratch_rom("blank_checksum", 0x101000);
+        rom.update_checksum().unwrap();
+
+        assert_eq!(
+            read_bytes(&mut rom, 0x10, 8),
+            [0xA3, 0x88, 0x67, 0x59, 0x40, 0xEC, 0x67, 0x59]
+        );
+    }
+
+    #[test]
+    fn build() {
+        let mut original = scratch_rom("build_original", 0x101000);
+        let output = scratch_rom("build_output", 0).file;
+
+        // Later patches are written over earlier ones.
+        let patches = [
+            Patch {
+                offset: 0x2000,
+                bytes: vec![0xAA; 8],
+            },
+            Patch {
+                offset: 0x2004,
+                bytes: vec![0xBB; 8],
+            },
+        ];
+        let mut rom = original.build(output, &patches).unwrap();
+
+        assert_eq!(rom.region, Region::America);
+        assert_eq!(rom.file.metadata().unwrap().len(), 0x101000);
+        assert_eq!(
+            read_bytes(&mut rom, 0x2000, 12),
+            [0xAA, 0xAA, 0xAA, 0xAA, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB]
+        );
+
+        // The patches changed the code, so the checksum did too.
+        assert_ne!(
+            read_bytes(&mut rom, 0x10, 8),
+            read_bytes(&mut original, 0x10, 8)
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn real_checksum() {
+        // The header of Paper Mario (U) [!].
+        let mut rom = test_rom();
+        let output = scratch_rom("real_checksum", 0).file;
+        let mut rebuilt = rom.build(output, &[]).unwrap();
+
+        assert_eq!(
+            read_bytes(&mut rebuilt, 0x10, 8),
+            [0x65, 0xEE, 0xE5, 0x3A, 0xED, 0x7D, 0x73, 0x3C]
+        );
+    }
+}
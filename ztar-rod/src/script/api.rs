@@ -0,0 +1,306 @@
+use super::datatype::{DataType, TypeDefinition};
+use super::parse::parse_datatype;
+use super::Scope;
+use crate::rom::Region;
+use failure_derive::*;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The database that ships with ztar-rod, used when there isn't one in the
+/// working directory.
+static BUNDLED: &str = include_str!("../../api.json");
+
+/// Only databases of this version can be read.
+const VERSION: u64 = 1;
+
+/// Signatures of the game's functions that scripts call, so that calls to them
+/// can be decompiled by name and type-checked. These live in a JSON file
+/// rather than in ztar-rod itself, so can be added to without recompiling:
+///
+/// ```json
+/// {
+///     "version": 1,
+///     "types": [
+///         {
+///             "name": "Camera",
+///             "kind": "enum",
+///             "variants": [ { "name": "World", "value": 0 } ]
+///         }
+///     ],
+///     "methods": [
+///         {
+///             "name": "set_sprite_shading",
+///             "kind": "asm",
+///             "addresses": { "us": "0x802D9700" },
+///             "args": [ { "name": "profile", "type": "int" } ],
+///             "doc": "..."
+///         }
+///     ]
+/// }
+/// ```
+///
+/// Types are enums, with `variants`, or structs, with typed `fields`. Method
+/// `kind` is `fun` for script functions and `asm` for native ones. Addresses
+/// are given per region (`jp`, `us` or `pal`); a method need not be known in
+/// every region, nor at all, to be called by name.
+///
+/// The bundled database only has USA addresses so far, so with Japanese and
+/// European roms its methods are declared by name alone.
+#[derive(Debug, Clone)]
+pub struct Api {
+    pub types: Vec<(String, TypeDefinition)>,
+    pub methods: Vec<Method>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub name: String,
+    pub kind: MethodKind,
+    pub addresses: Vec<(Region, u32)>,
+    pub arguments: Vec<(String, DataType)>,
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodKind {
+    Fun,
+    Asm,
+}
+
+#[derive(Debug, Fail)]
+pub enum ApiError {
+    #[fail(display = "{}", _0)]
+    Io(#[fail(cause)] io::Error),
+
+    #[fail(display = "{}", _0)]
+    Json(#[fail(cause)] serde_json::Error),
+
+    #[fail(display = "unsupported API database version {}", _0)]
+    Version(u64),
+
+    #[fail(display = "bad API type '{}': {}", _0, _1)]
+    BadType(String, String),
+
+    #[fail(display = "bad API method '{}': {}", _0, _1)]
+    BadMethod(String, String),
+}
+
+impl From<io::Error> for ApiError {
+    fn from(error: io::Error) -> ApiError {
+        ApiError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(error: serde_json::Error) -> ApiError {
+        ApiError::Json(error)
+    }
+}
+
+impl Api {
+    /// The database bundled with ztar-rod.
+    pub fn bundled() -> Api {
+        Api::from_json(BUNDLED).expect("bundled API database is invalid")
+    }
+
+    /// Reads the database at `path`, or the bundled one if there's no such
+    /// file.
+    pub fn open(path: &Path) -> Result<Api, ApiError> {
+        match fs::read_to_string(path) {
+            Ok(json) => Api::from_json(&json),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(Api::bundled()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Api, ApiError> {
+        let api: Value = serde_json::from_str(json)?;
+
+        match api["version"].as_u64() {
+            Some(VERSION) => (),
+            version => return Err(ApiError::Version(version.unwrap_or(0))),
+        }
+
+        let types = array(&api["types"])
+            .iter()
+            .map(type_from_json)
+            .collect::<Result<_, _>>()?;
+
+        let methods = array(&api["methods"])
+            .iter()
+            .map(Method::from_json)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Api { types, methods })
+    }
+
+    /// Brings every type into scope, and every method by name, and by address
+    /// if it has one in the given region.
+    pub fn declare(&self, scope: &mut Scope, region: Region) {
+        for (name, definition) in self.types.iter() {
+            scope.insert_type(name.clone(), definition.clone());
+        }
+
+        for method in self.methods.iter() {
+            match method.address(region) {
+                Some(ptr) => scope.insert_ptr(ptr, method.name.clone(), method.datatype()),
+                None => scope.insert_name(method.name.clone(), method.datatype()),
+            };
+        }
+    }
+}
+
+impl Method {
+    fn from_json(method: &Value) -> Result<Method, ApiError> {
+        let name = method["name"]
+            .as_str()
+            .ok_or_else(|| ApiError::BadMethod("?".to_string(), "missing name".to_string()))?
+            .to_string();
+
+        let bad = |message: &str| ApiError::BadMethod(name.clone(), message.to_string());
+
+        let kind = match method["kind"].as_str() {
+            Some("fun") => MethodKind::Fun,
+            Some("asm") => MethodKind::Asm,
+            _ => return Err(bad("kind must be 'fun' or 'asm'")),
+        };
+
+        let mut addresses = Vec::new();
+        if let Some(map) = method["addresses"].as_object() {
+            for (region, address) in map.iter() {
+                let region = match region.as_str() {
+                    "jp" => Region::Japan,
+                    "us" => Region::America,
+                    "pal" => Region::Europe,
+                    _ => return Err(bad(&format!("unknown region '{}'", region))),
+                };
+
+                let address = address
+                    .as_str()
+                    .filter(|address| address.starts_with("0x"))
+                    .and_then(|address| u32::from_str_radix(&address[2..], 16).ok())
+                    .ok_or_else(|| bad("addresses must be hex strings, e.g. \"0x802D9700\""))?;
+
+                addresses.push((region, address));
+            }
+        }
+
+        let arguments = array(&method["args"])
+            .iter()
+            .enumerate()
+            .map(|(n, arg)| {
+                let name = arg["name"]
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("arg{}", n));
+
+                let datatype = match arg["type"].as_str() {
+                    Some(ty) => {
+                        parse_datatype(ty).map_err(|_| bad(&format!("bad type '{}'", ty)))?
+                    }
+                    None => DataType::Any,
+                };
+
+                Ok((name, datatype))
+            })
+            .collect::<Result<_, ApiError>>()?;
+
+        Ok(Method {
+            kind,
+            addresses,
+            arguments,
+            doc: method["doc"].as_str().map(str::to_string),
+            name,
+        })
+    }
+
+    pub fn address(&self, region: Region) -> Option<u32> {
+        self.addresses
+            .iter()
+            .find(|(r, _)| *r == region)
+            .map(|(_, address)| *address)
+    }
+
+    /// The type of the method itself, e.g. `asm(int, bool)`.
+    pub fn datatype(&self) -> DataType {
+        let arguments = self.arguments.iter().map(|(_, ty)| ty.clone()).collect();
+
+        match self.kind {
+            MethodKind::Fun => DataType::Fun(arguments),
+            MethodKind::Asm => DataType::Asm(arguments),
+        }
+    }
+}
+
+fn type_from_json(ty: &Value) -> Result<(String, TypeDefinition), ApiError> {
+    let name = ty["name"]
+        .as_str()
+        .ok_or_else(|| ApiError::BadType("?".to_string(), "missing name".to_string()))?
+        .to_string();
+
+    let bad = |message: &str| ApiError::BadType(name.clone(), message.to_string());
+
+    let definition = match ty["kind"].as_str() {
+        Some("enum") => TypeDefinition::Enum(
+            array(&ty["variants"])
+                .iter()
+                .map(
+                    |variant| match (variant["name"].as_str(), variant["value"].as_i64()) {
+                        (Some(name), Some(value)) => Ok((name.to_string(), value as u32)),
+                        _ => Err(bad("variants must have a name and an integer value")),
+                    },
+                )
+                .collect::<Result<_, _>>()?,
+        ),
+
+        Some("struct") => TypeDefinition::Struct(
+            array(&ty["fields"])
+                .iter()
+                .map(
+                    |field| match (field["name"].as_str(), field["type"].as_str()) {
+                        (Some(name), Some(field_ty)) => parse_datatype(field_ty)
+                            .map(|datatype| (name.to_string(), datatype))
+                            .map_err(|_| bad(&format!("bad type '{}'", field_ty))),
+                        _ => Err(bad("fields must have a name and a type")),
+                    },
+                )
+                .collect::<Result<_, _>>()?,
+        ),
+
+        _ => return Err(bad("kind must be 'enum' or 'struct'")),
+    };
+
+    Ok((name, definition))
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled() {
+        let api = Api::bundled();
+        assert!(!api.methods.is_empty());
+
+        for region in [Region::Japan, Region::America, Region::Europe].iter() {
+            let mut scope = Scope::new();
+            api.declare(&mut scope, *region);
+
+            for method in api.methods.iter() {
+                assert_eq!(scope.lookup_name(&method.name), Some(&method.datatype()));
+
+                // Some methods share a name, so are looked up by address.
+                match method.address(*region) {
+                    Some(ptr) => assert_eq!(scope.lookup_ptr(ptr), Some(method.name.as_str())),
+                    None => assert_eq!(scope.lookup_name_ptr(&method.name), None),
+                }
+            }
+        }
+    }
+}
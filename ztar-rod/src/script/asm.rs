@@ -0,0 +1,380 @@
+use super::bc::{Arg, ArgKind, Bytecode, Opcode};
+use super::globals::*;
+use failure_derive::*;
+use itertools::Itertools;
+use std::convert::TryFrom;
+
+/// The disassembler and assembler: a textual form of bytecode that, unlike
+/// decompiled scripts, maps one-to-one with the bytes it came from. Each line
+/// holds an opcode followed by its arguments, decoded by kind:
+///
+/// ```text
+/// SetInt             word_0, 5
+/// SetFloat           mapword_3, 1.5
+/// Call               0x802D9700, flags[2]
+/// ```
+///
+/// Blocks are indented for readability; whitespace is otherwise ignored, as
+/// are `//` comments.
+impl Bytecode {
+    pub fn disassemble(&self) -> String {
+        let mut lines = Vec::new();
+        let mut depth: usize = 0;
+
+        for (opcode, args) in self.operations().iter() {
+            let (outdent, indent) = nesting(*opcode);
+
+            if outdent {
+                depth = depth.saturating_sub(1);
+            }
+
+            let line = format!(
+                "{}{:<18} {}",
+                "    ".repeat(depth),
+                format!("{:?}", opcode),
+                args.iter().map(|arg| disassemble_arg(*arg)).join(", "),
+            );
+            lines.push(line.trim_end().to_string());
+
+            if indent {
+                depth += 1;
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn assemble(source: &str) -> Result<Bytecode, AsmError> {
+        let mut data = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+
+            let line = line.split("//").next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some((Opcode::End, _)) = data.last() {
+                return Err(AsmError::AfterEnd(line_number));
+            }
+
+            let (name, args) = match line.find(char::is_whitespace) {
+                Some(split) => (&line[..split], line[split..].trim()),
+                None => (line, ""),
+            };
+
+            let opcode = opcode_from_name(name)
+                .ok_or_else(|| AsmError::UnknownOpcode(line_number, name.to_string()))?;
+
+            let args = match args {
+                "" => Vec::new(),
+                args => args
+                    .split(',')
+                    .map(str::trim)
+                    .map(|arg| {
+                        assemble_arg(arg)
+                            .ok_or_else(|| AsmError::BadArg(line_number, arg.to_string()))
+                    })
+                    .collect::<Result<_, _>>()?,
+            };
+
+            data.push((opcode, args));
+        }
+
+        match data.last() {
+            Some((Opcode::End, _)) => Ok(Bytecode::new(data.into_iter().collect())),
+            _ => Err(AsmError::MissingEnd),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum AsmError {
+    #[fail(display = "line {}: unknown opcode '{}'", _0, _1)]
+    UnknownOpcode(usize, String),
+
+    #[fail(display = "line {}: bad arg '{}'", _0, _1)]
+    BadArg(usize, String),
+
+    #[fail(display = "line {}: operation after End", _0)]
+    AfterEnd(usize),
+
+    #[fail(display = "missing End opcode")]
+    MissingEnd,
+}
+
+/// Whether an opcode closes a block (so goes back out a level), and whether it
+/// opens one.
+fn nesting(opcode: Opcode) -> (bool, bool) {
+    match opcode {
+        Opcode::Loop
+        | Opcode::IfEq
+        | Opcode::IfNe
+        | Opcode::IfLt
+        | Opcode::IfGt
+        | Opcode::IfLte
+        | Opcode::IfGte
+        | Opcode::IfAndNz
+        | Opcode::IfAndZ
+        | Opcode::Switch
+        | Opcode::SwitchConst
+        | Opcode::Thread
+        | Opcode::ChildThread => (false, true),
+
+        Opcode::Else
+        | Opcode::CaseEq
+        | Opcode::CaseNe
+        | Opcode::CaseLt
+        | Opcode::CaseGt
+        | Opcode::CaseLte
+        | Opcode::CaseGte
+        | Opcode::CaseOrEq
+        | Opcode::CaseAndEq
+        | Opcode::CaseDefault
+        | Opcode::CaseAndZ
+        | Opcode::CaseRange => (true, true),
+
+        Opcode::EndLoop
+        | Opcode::EndIf
+        | Opcode::EndSwitch
+        | Opcode::EndThread
+        | Opcode::EndChildThread => (true, false),
+
+        _ => (false, false),
+    }
+}
+
+fn opcode_from_name(name: &str) -> Option<Opcode> {
+    (1..)
+        .map(Opcode::try_from)
+        .take_while(Result::is_ok)
+        .filter_map(Result::ok)
+        .find(|opcode| format!("{:?}", opcode) == name)
+}
+
+/// Each kind of variable's name, and what is added to its number to encode it.
+fn variable(kind: &ArgKind) -> Option<(&'static str, i32)> {
+    match kind {
+        ArgKind::GameByte => Some((GAMEBYTE_STR, -170000000)),
+        ArgKind::AreaByte => Some((AREABYTE_STR, -150000000)),
+        ArgKind::MapWord => Some((MAPWORD_STR, -50000000)),
+        ArgKind::FunWord => Some((FUNWORD_STR, -30000000)),
+
+        ArgKind::GameFlag => Some((GAMEFLAG_STR, -130000000)),
+        ArgKind::AreaFlag => Some((AREAFLAG_STR, -110000000)),
+        ArgKind::MapFlag => Some((MAPFLAG_STR, -90000000)),
+        ArgKind::FunFlag => Some((FUNFLAG_STR, -70000000)),
+
+        _ => None,
+    }
+}
+
+fn disassemble_arg(arg: Arg) -> String {
+    let s = arg.0 as i32;
+
+    match arg.kind() {
+        // Pointers are easier to read in hex.
+        ArgKind::Int if arg.0 & 0xFF00_0000 == 0x8000_0000 => format!("0x{:08X}", arg.0),
+        ArgKind::Int => format!("{}", s),
+
+        // f64 holds every fixed-point value exactly, and Debug always
+        // includes the decimal point.
+        ArgKind::Float => format!("{:?}", f64::from(s + 230000000) / 1024.0),
+
+        ArgKind::FlagArrayIndex => format!("{}[{}]", FLAGARRAY_STR, s + 210000000),
+        ArgKind::ArrayIndex => format!("{}[{}]", ARRAY_STR, s + 190000000),
+
+        kind => {
+            let (name, offset) = variable(&kind).unwrap();
+            format!("{}_{:X}", name, s.wrapping_sub(offset) as u32)
+        }
+    }
+}
+
+/// Parses a single argument as written by the disassembler.
+pub fn assemble_arg(text: &str) -> Option<Arg> {
+    let (arg, expected_kind) = if text.starts_with("0x") {
+        (Arg(u32::from_str_radix(&text[2..], 16).ok()?), ArgKind::Int)
+    } else if text.contains('.') {
+        let value: f64 = text.parse().ok()?;
+        let fixed = (value * 1024.0).round() as i64 - 230000000;
+        (Arg(i32::try_from(fixed).ok()? as u32), ArgKind::Float)
+    } else if let Ok(int) = text.parse::<i32>() {
+        (Arg(int as u32), ArgKind::Int)
+    } else if text.ends_with(']') {
+        let open = text.find('[')?;
+        let index = text[open + 1..text.len() - 1].parse::<i32>().ok()?;
+
+        match &text[..open] {
+            name if name == FLAGARRAY_STR => (
+                Arg(index.wrapping_sub(210000000) as u32),
+                ArgKind::FlagArrayIndex,
+            ),
+            name if name == ARRAY_STR => (
+                Arg(index.wrapping_sub(190000000) as u32),
+                ArgKind::ArrayIndex,
+            ),
+            _ => return None,
+        }
+    } else {
+        let split = text.rfind('_')?;
+        let number = u32::from_str_radix(&text[split + 1..], 16).ok()? as i32;
+
+        let kinds = [
+            ArgKind::GameByte,
+            ArgKind::AreaByte,
+            ArgKind::MapWord,
+            ArgKind::FunWord,
+            ArgKind::GameFlag,
+            ArgKind::AreaFlag,
+            ArgKind::MapFlag,
+            ArgKind::FunFlag,
+        ];
+
+        kinds
+            .iter()
+            .filter_map(|kind| {
+                let (name, offset) = variable(kind)?;
+                if name == &text[..split] {
+                    Some((Arg(number.wrapping_add(offset) as u32), kind.clone()))
+                } else {
+                    None
+                }
+            })
+            .next()?
+    };
+
+    // Out-of-range values would be read back as some other kind of arg.
+    if arg.kind() == expected_kind {
+        Some(arg)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::area::AreaTable;
+    use crate::rom::{test_rom, RomRead};
+
+    /// Encodes an operation the way it appears in the rom.
+    fn encode(opcode: Opcode, args: &[u32]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&u32::from(opcode).to_be_bytes());
+        bytes.extend_from_slice(&(args.len() as u32).to_be_bytes());
+        for arg in args {
+            bytes.extend_from_slice(&arg.to_be_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let operations: Vec<(Opcode, Vec<u32>)> = vec![
+            // Ints, including ones that fall in the unknown and pointer ranges.
+            (Opcode::SetInt, vec![0xFE36_3C80, 5]),
+            (Opcode::SetInt, vec![0xFE36_3C81, -5i32 as u32]),
+            (Opcode::SetInt, vec![0xFE36_3C82, -260000000i32 as u32]),
+            (Opcode::SetInt, vec![0xFE36_3C83, -300000000i32 as u32]),
+            (Opcode::Call, vec![0x802D_9700, 0x8024_0000]),
+            // Floats, either side of zero.
+            (
+                Opcode::SetFloat,
+                vec![0xFD05_0F80, (-230000000i32 + 1536) as u32],
+            ),
+            (
+                Opcode::SetFloat,
+                vec![0xFD05_0F80, (-230000000i32 - 1536) as u32],
+            ),
+            (
+                Opcode::SetFloat,
+                vec![0xFD05_0F80, (-230000000i32 + 1) as u32],
+            ),
+            // Every kind of variable, at both positive and negative numbers.
+            (Opcode::SetInt, vec![(-170000000i32 + 3) as u32, 1]),
+            (Opcode::SetInt, vec![(-150000000i32 - 3) as u32, 1]),
+            (Opcode::SetInt, vec![(-50000000i32 + 0x10) as u32, 1]),
+            (Opcode::SetInt, vec![(-30000000i32 - 1) as u32, 1]),
+            (Opcode::SetInt, vec![(-130000000i32 + 0x1F0) as u32, 1]),
+            (Opcode::SetInt, vec![(-110000000i32 - 2) as u32, 1]),
+            (Opcode::SetInt, vec![(-90000000i32 + 7) as u32, 1]),
+            (Opcode::SetInt, vec![(-70000000i32 - 7) as u32, 1]),
+            // Array indices.
+            (Opcode::SetInt, vec![(-210000000i32 + 2) as u32, 1]),
+            (Opcode::SetInt, vec![(-190000000i32 - 2) as u32, 1]),
+            (Opcode::Return, vec![]),
+            (Opcode::End, vec![]),
+        ];
+
+        let bytes: Vec<u8> = operations
+            .iter()
+            .flat_map(|(opcode, args)| encode(*opcode, args))
+            .collect();
+
+        let bc = Bytecode::new(
+            operations
+                .into_iter()
+                .map(|(opcode, args)| (opcode, args.into_iter().map(Arg).collect()))
+                .collect(),
+        );
+
+        let kinds: Vec<ArgKind> = bc
+            .operations()
+            .iter()
+            .flat_map(|(_, args)| args.iter().map(|arg| arg.kind()))
+            .collect();
+        for kind in [
+            ArgKind::Int,
+            ArgKind::Float,
+            ArgKind::GameByte,
+            ArgKind::AreaByte,
+            ArgKind::MapWord,
+            ArgKind::FunWord,
+            ArgKind::GameFlag,
+            ArgKind::AreaFlag,
+            ArgKind::MapFlag,
+            ArgKind::FunFlag,
+            ArgKind::FlagArrayIndex,
+            ArgKind::ArrayIndex,
+        ]
+        .iter()
+        {
+            assert!(kinds.contains(kind), "no {:?} arg", kind);
+        }
+
+        let text = bc.disassemble();
+        assert!(text.contains("Call               0x802D9700, 0x80240000"));
+        assert!(text.contains("word_FFFFFFFF, 1"));
+        assert!(text.contains("-1.5"));
+
+        let reassembled = Bytecode::assemble(&text).unwrap();
+        assert_eq!(reassembled.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn out_of_range_args() {
+        // Numbers this large would be encoded as some other kind of arg.
+        assert!(assemble_arg("-200000000").is_none());
+        assert!(assemble_arg("word_20000000").is_none());
+        assert!(assemble_arg("flags[20000000]").is_none());
+        assert!(assemble_arg("nonsense_1").is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn real_scripts() {
+        let mut rom = test_rom();
+
+        for area in AreaTable::read(&mut rom).unwrap().areas {
+            for map in area.maps {
+                let loc = map.main_fun(&mut rom).unwrap();
+                let bc = Bytecode::read(&mut rom, loc).unwrap();
+
+                let reassembled = Bytecode::assemble(&bc.disassemble()).unwrap();
+                assert_eq!(reassembled.to_bytes(), bc.to_bytes(), "{}", map.name);
+            }
+        }
+    }
+}
@@ -1,48 +1,108 @@
-use std::convert::TryInto;
-use std::collections::{VecDeque, HashSet};
-use std::cell::RefCell;
-use failure_derive::*;
-use num_enum::{TryFromPrimitive, IntoPrimitive};
 use super::datatype::DataType;
-use super::parse::ast::*;
 use super::globals::*;
+use super::parse::ast::*;
 use super::Scope;
+use crate::rom::loc::Location;
+use crate::rom::{ReadError, Rom, RomRead, Seek};
+use failure_derive::*;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
 
 #[derive(Debug, Clone)]
 pub struct Bytecode {
     data: VecDeque<Operation>,
     seen_identifiers: HashSet<Arg>,
+
+    /// The datatypes that the decompiled source has declared variables with,
+    /// which the compiler goes by when choosing between int and float opcodes.
+    declared: HashMap<Arg, DataType>,
+
+    /// Scripts that are run with Exec, ExecWait or ExecRet.
+    exec_targets: HashSet<u32>,
 }
 
 pub type Operation = (Opcode, Vec<Arg>);
 
+/// Reading gives up after this many operations, in case it isn't actually a
+/// script that is being read.
+const MAX_LEN: usize = 0x2000;
+
+/// No opcode takes anywhere near this many arguments.
+const MAX_ARGS: u32 = 32;
+
 impl Bytecode {
-    pub fn read(rom: &mut crate::rom::Rom) -> Bytecode {
+    /// Reads the script at `loc`, up to and including its End opcode.
+    pub fn read(rom: &mut Rom, loc: Location) -> Result<Bytecode, DecodeError> {
+        rom.file.seek(loc.into())?;
+
         let mut data = VecDeque::new();
+        let mut offset = 0;
         loop {
-            let opcode = rom.read_u32();
+            let op_loc = loc.add_offset(offset);
+
+            if data.len() >= MAX_LEN {
+                return Err(DecodeError::TooLong(loc));
+            }
 
-            let mut args = Vec::new();
-            for _ in 0..rom.read_u32() {
-                args.push(Arg(rom.read_u32()))
+            let opcode = u32::read(rom)?;
+            let arg_count = u32::read(rom)?;
+
+            if arg_count > MAX_ARGS {
+                return Err(DecodeError::TooManyArgs(arg_count, op_loc));
+            }
+
+            let mut args = Vec::with_capacity(arg_count as usize);
+            for _ in 0..arg_count {
+                args.push(Arg(u32::read(rom)?))
             }
 
+            offset += 8 + arg_count * 4;
+
             match opcode.try_into().ok() {
                 Some(op) => {
                     data.push_back((op, args));
 
                     if let Opcode::End = op {
-                        return Bytecode {
-                            data,
-                            seen_identifiers: HashSet::new(),
-                        };
+                        return Ok(Bytecode::new(data));
                     }
-                },
-                None => panic!("unknown opcode: {:02X}", opcode),
+                }
+                None => return Err(DecodeError::UnknownOpcode(opcode, op_loc)),
             }
         }
     }
 
+    /// Wraps a list of operations, which should end with an End opcode.
+    pub fn new(data: VecDeque<Operation>) -> Bytecode {
+        Bytecode {
+            data,
+            seen_identifiers: HashSet::new(),
+            declared: HashMap::new(),
+            exec_targets: HashSet::new(),
+        }
+    }
+
+    pub fn operations(&self) -> &VecDeque<Operation> {
+        &self.data
+    }
+
+    /// Encodes the bytecode as `read` expects to find it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        for (opcode, args) in self.data.iter() {
+            bytes.extend_from_slice(&u32::from(*opcode).to_be_bytes());
+            bytes.extend_from_slice(&(args.len() as u32).to_be_bytes());
+
+            for Arg(word) in args.iter() {
+                bytes.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+
+        bytes
+    }
+
     pub fn decompile(mut self, scope: &mut Scope) -> Result<Vec<Statement>, Error> {
         let mut stmts = Vec::new();
         loop {
@@ -53,7 +113,11 @@ impl Bytecode {
                     Err(Error::UnexpectedEnd)
                 } else {
                     // Remove pointless trailing return statement, if there is one.
-                    if let Statement::Return = stmts[stmts.len() - 1] {
+                    if let Some(Statement {
+                        kind: StatementKind::Return,
+                        ..
+                    }) = stmts.last()
+                    {
                         stmts.pop();
                     }
 
@@ -63,15 +127,24 @@ impl Bytecode {
                             ArgKind::FunWord => {
                                 let name = oparg.into_identifier().unwrap().0;
                                 scope.insert_name(name, DataType::Any);
-                            },
+                            }
                             ArgKind::FunFlag => {
                                 let name = oparg.into_identifier().unwrap().0;
                                 scope.insert_name(name, DataType::Bool);
-                            },
+                            }
                             _ => (),
                         }
                     }
 
+                    // Scripts that are executed are funs, rather than asm.
+                    // Unnamed ones are named by their address, so that they
+                    // still unparse as one.
+                    for ptr in self.exec_targets.into_iter() {
+                        if scope.lookup_ptr(ptr).is_none() {
+                            scope.insert_ptr(ptr, format!("0x{:X}", ptr), DataType::Fun(vec![]));
+                        }
+                    }
+
                     Ok(stmts)
                 };
             }
@@ -86,24 +159,66 @@ impl Bytecode {
     }
 
     fn peek_op(&self) -> Result<&Operation, Error> {
-        self.data.get(0).ok_or(Error::MissingEnd)
+        self.data.front().ok_or(Error::MissingEnd)
+    }
+
+    /// The datatype the compiler will give `arg` when it is used as a value.
+    fn value_datatype(&self, arg: Arg) -> DataType {
+        match arg.kind() {
+            ArgKind::Int => DataType::Int,
+            ArgKind::Float => DataType::Float,
+            _ => self.declared.get(&arg).cloned().unwrap_or(DataType::Any),
+        }
+    }
+
+    /// Whether the compiler will choose an int or float opcode to set or
+    /// operate on `var` with `value`: float if either is a float, or Any if
+    /// neither has a known type.
+    fn operation_datatype(&self, var: Arg, value: Arg) -> DataType {
+        let var_datatype = self.declared.get(&var).cloned().unwrap_or(DataType::Any);
+
+        match (var_datatype, self.value_datatype(value)) {
+            (DataType::Float, _) | (_, DataType::Float) => DataType::Float,
+            (DataType::Any, DataType::Any) => DataType::Any,
+            _ => DataType::Int,
+        }
     }
 
     fn decompile_op(&mut self) -> Result<Vec<Statement>, Error> {
+        Ok(self
+            .decompile_op_kinds()?
+            .into_iter()
+            .map(Statement::from)
+            .collect())
+    }
+
+    fn decompile_op_kinds(&mut self) -> Result<Vec<StatementKind>, Error> {
         let (opcode, opargs) = self.consume_op()?;
         match opcode {
-            Opcode::IfEq  | Opcode::IfNe  | Opcode::IfLt    | Opcode::IfGt |
-            Opcode::IfLte | Opcode::IfGte | Opcode::IfAndNz | Opcode::IfAndZ
-            => Ok(vec![Statement::If {
-                condition: Expression::Operation {
-                    lhs: Box::new(opargs.get(0)
-                        .ok_or_else(|| Error::MissingArg(opcode, 0))?
-                        .into_expression()),
-                    op:  opcode.into_operator().unwrap(),
-                    rhs: Box::new(opargs.get(1)
-                        .ok_or_else(|| Error::MissingArg(opcode, 1))?
-                        .into_expression()),
-                },
+            Opcode::IfEq
+            | Opcode::IfNe
+            | Opcode::IfLt
+            | Opcode::IfGt
+            | Opcode::IfLte
+            | Opcode::IfGte
+            | Opcode::IfAndNz
+            | Opcode::IfAndZ => Ok(vec![StatementKind::If {
+                condition: ExpressionKind::Operation {
+                    lhs: Box::new(
+                        opargs
+                            .first()
+                            .ok_or(Error::MissingArg(opcode, 0))?
+                            .into_expression(),
+                    ),
+                    op: opcode.into_operator().unwrap(),
+                    rhs: Box::new(
+                        opargs
+                            .get(1)
+                            .ok_or(Error::MissingArg(opcode, 1))?
+                            .into_expression(),
+                    ),
+                }
+                .into(),
                 block_true: {
                     let mut stmts = Vec::new();
                     loop {
@@ -111,8 +226,8 @@ impl Bytecode {
                             // Consume Else; block_false does NOT expect it
                             (Opcode::Else, _) => {
                                 self.consume_op()?;
-                                break
-                            },
+                                break;
+                            }
 
                             // Don't consume EndIf; block_false needs it
                             (Opcode::EndIf, _) => break,
@@ -129,8 +244,8 @@ impl Bytecode {
                         match self.peek_op()? {
                             (Opcode::EndIf, _) => {
                                 self.consume_op()?;
-                                break
-                            },
+                                break;
+                            }
                             _ => stmts.append(&mut self.decompile_op()?),
                         };
                     }
@@ -138,66 +253,85 @@ impl Bytecode {
                 },
             }]),
 
-            Opcode::Switch | Opcode::SwitchConst => Ok(vec![Statement::Switch {
-                expression: opargs.get(0)
-                    .ok_or_else(|| Error::MissingArg(opcode, 0))?
-                    .into_expression(),
+            Opcode::Switch | Opcode::SwitchConst => Ok(vec![StatementKind::Switch {
+                expression: {
+                    let expression = opargs
+                        .first()
+                        .ok_or(Error::MissingArg(opcode, 0))?
+                        .into_expression();
+
+                    match opcode {
+                        Opcode::SwitchConst => ExpressionKind::Const(Box::new(expression)).into(),
+                        _ => expression,
+                    }
+                },
                 cases: {
                     let mut cases = Vec::new();
                     loop {
-                        match self.peek_op()? {
-                            // Consume Case ops:
-                            (Opcode::CaseEq, _)      |
-                            (Opcode::CaseOrEq, _)    |
-                            (Opcode::CaseNe, _)      |
-                            (Opcode::CaseLt, _)      |
-                            (Opcode::CaseGt, _)      |
-                            (Opcode::CaseLte, _)     |
-                            (Opcode::CaseGte, _)     |
-                            (Opcode::CaseAndZ, _)    |
-                            (Opcode::CaseDefault, _) => {
+                        let case = match self.peek_op()?.0 {
+                            Opcode::CaseDefault => {
+                                self.consume_op()?;
+                                Case::Default
+                            }
+
+                            Opcode::CaseEq
+                            | Opcode::CaseNe
+                            | Opcode::CaseLt
+                            | Opcode::CaseGt
+                            | Opcode::CaseLte
+                            | Opcode::CaseGte
+                            | Opcode::CaseAndZ => {
+                                let (case_opcode, case_opargs) = self.consume_op()?;
+
+                                Case::Test {
+                                    operator: case_opcode.into_operator().unwrap(),
+                                    against: case_opargs
+                                        .first()
+                                        .ok_or(Error::MissingArg(case_opcode, 0))?
+                                        .into_expression(),
+                                }
+                            }
+
+                            Opcode::CaseRange => {
                                 let (case_opcode, case_opargs) = self.consume_op()?;
+                                let arg = |n: u8| {
+                                    case_opargs
+                                        .get(n as usize)
+                                        .ok_or(Error::MissingArg(case_opcode, n))
+                                        .map(|arg| arg.into_expression())
+                                };
 
-                                let mut stmts = Vec::new();
-                                loop {
-                                    match self.peek_op()? {
-                                        (Opcode::CaseEq, _)      |
-                                        (Opcode::CaseAndEq, _)   |
-                                        (Opcode::CaseOrEq, _)    |
-                                        (Opcode::CaseNe, _)      |
-                                        (Opcode::CaseLt, _)      |
-                                        (Opcode::CaseGt, _)      |
-                                        (Opcode::CaseLte, _)     |
-                                        (Opcode::CaseGte, _)     |
-                                        (Opcode::CaseAndZ, _)    |
-                                        (Opcode::CaseDefault, _) |
-                                        (Opcode::EndSwitch, _)   => break,
-
-                                        _ => stmts.append(&mut self.decompile_op()?),
-                                    };
+                                Case::Range {
+                                    low: arg(0)?,
+                                    high: arg(1)?,
                                 }
+                            }
 
-                                cases.push((if let Opcode::CaseDefault = case_opcode {
-                                    Case::Default
-                                } else {
-                                    Case::Test {
-                                        operator: case_opcode.into_operator().unwrap(),
-                                        against: case_opargs.get(0)
-                                            .ok_or_else(|| Error::MissingArg(case_opcode, 0))?
+                            // A run of CaseOrEq (or CaseAndEq) sharing a
+                            // single block, which ends with EndCaseGroup.
+                            group_opcode @ Opcode::CaseOrEq | group_opcode @ Opcode::CaseAndEq => {
+                                let mut values = Vec::new();
+                                while self.peek_op()?.0 == group_opcode {
+                                    let (_, case_opargs) = self.consume_op()?;
+                                    values.push(
+                                        case_opargs
+                                            .first()
+                                            .ok_or(Error::MissingArg(group_opcode, 0))?
                                             .into_expression(),
-                                    }
-                                }, stmts));
-                            },
+                                    );
+                                }
 
-                            // This goes unused in vanilla, and is... useless.
-                            (Opcode::CaseAndEq, _) =>
-                                return Err(Error::UnimplementedOpcode(Opcode::CaseAndEq)),
+                                match group_opcode {
+                                    Opcode::CaseAndEq => Case::AllOf(values),
+                                    _ => Case::AnyOf(values),
+                                }
+                            }
 
                             // Close the switch, consuming the EndSwitch op.
-                            (Opcode::EndSwitch, _) => {
+                            Opcode::EndSwitch => {
                                 self.consume_op()?;
-                                break
-                            },
+                                break;
+                            }
 
                             // A couple vanilla functions have weird, malformed
                             // switches that are not followed by any cases (and
@@ -205,85 +339,475 @@ impl Bytecode {
                             // if we see an unexpected opcode inside it.
                             _ => break,
                         };
+
+                        let mut stmts = Vec::new();
+                        loop {
+                            match self.peek_op()?.0 {
+                                Opcode::CaseEq
+                                | Opcode::CaseAndEq
+                                | Opcode::CaseOrEq
+                                | Opcode::CaseNe
+                                | Opcode::CaseLt
+                                | Opcode::CaseGt
+                                | Opcode::CaseLte
+                                | Opcode::CaseGte
+                                | Opcode::CaseAndZ
+                                | Opcode::CaseRange
+                                | Opcode::CaseDefault
+                                | Opcode::EndCaseGroup
+                                | Opcode::EndSwitch => break,
+
+                                _ => stmts.append(&mut self.decompile_op()?),
+                            };
+                        }
+
+                        // Groups, and only groups, end with EndCaseGroup.
+                        // Statements between the cases of a group (so that
+                        // some values run more of it than others) have no
+                        // equivalent in source.
+                        let grouped = match case {
+                            Case::AnyOf(_) | Case::AllOf(_) => true,
+                            _ => false,
+                        };
+                        match self.peek_op()?.0 {
+                            Opcode::EndCaseGroup if grouped => {
+                                self.consume_op()?;
+                            }
+                            Opcode::EndCaseGroup => return Err(Error::BadCaseGroup),
+                            _ if grouped => return Err(Error::BadCaseGroup),
+                            _ => (),
+                        }
+
+                        cases.push((case, stmts));
                     }
                     cases
                 },
             }]),
 
+            Opcode::Loop => Ok(vec![StatementKind::Loop {
+                count: match opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression()
+                {
+                    // A count of zero loops forever.
+                    Expression {
+                        kind: ExpressionKind::LiteralInt(0),
+                        ..
+                    } => None,
+                    count => Some(count),
+                },
+                block: {
+                    let mut stmts = Vec::new();
+                    loop {
+                        match self.peek_op()? {
+                            (Opcode::EndLoop, _) => {
+                                self.consume_op()?;
+                                break;
+                            }
+                            _ => stmts.append(&mut self.decompile_op()?),
+                        };
+                    }
+                    stmts
+                },
+            }]),
+            Opcode::BreakLoop => Ok(vec![StatementKind::BreakLoop]),
+            Opcode::BreakCase => Ok(vec![StatementKind::BreakCase]),
+
+            Opcode::Bind | Opcode::BindLock => {
+                let arg = |n: u8| opargs.get(n as usize).ok_or(Error::MissingArg(opcode, n));
+
+                // Padlocks take a list of items before the handle and prompt.
+                let (items, handle, prompt) = match opcode {
+                    Opcode::Bind => (None, arg(4)?, arg(3)?),
+                    _ => (Some(arg(3)?.into_expression()), arg(4)?, arg(5)?),
+                };
+
+                Ok(vec![StatementKind::Bind {
+                    script: arg(0)?.into_expression(),
+                    trigger: arg(1)?.into_expression(),
+                    target: arg(2)?.into_expression(),
+                    items,
+                    prompt: prompt.into_expression(),
+                    handle: handle.into_identifier(),
+                }])
+            }
+            Opcode::Unbind => Ok(vec![StatementKind::Unbind]),
+
+            Opcode::Kill => Ok(vec![StatementKind::Kill {
+                script: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression(),
+            }]),
+            Opcode::Jump => Ok(vec![StatementKind::Jump {
+                script: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression(),
+            }]),
+
+            Opcode::SetPriority | Opcode::SetTimescale | Opcode::SetSuspensionGroup => {
+                let value = opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression();
+
+                Ok(vec![match opcode {
+                    Opcode::SetPriority => StatementKind::Priority { priority: value },
+                    Opcode::SetTimescale => StatementKind::Timescale { timescale: value },
+                    _ => StatementKind::Group { group: value },
+                }])
+            }
+
+            Opcode::SuspendAll
+            | Opcode::SuspendOthers
+            | Opcode::Suspend
+            | Opcode::ResumeAll
+            | Opcode::ResumeOthers
+            | Opcode::Resume => {
+                let value = opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression();
+
+                let target = match opcode {
+                    Opcode::SuspendAll | Opcode::ResumeAll => ScriptTarget::Group(value),
+                    Opcode::SuspendOthers | Opcode::ResumeOthers => ScriptTarget::Others(value),
+                    _ => ScriptTarget::Script(value),
+                };
+
+                Ok(vec![match opcode {
+                    Opcode::SuspendAll | Opcode::SuspendOthers | Opcode::Suspend => {
+                        StatementKind::Suspend { target }
+                    }
+                    _ => StatementKind::Resume { target },
+                }])
+            }
+
+            Opcode::DoesScriptExist => Ok(vec![StatementKind::ScriptExists {
+                identifier: opargs
+                    .get(1)
+                    .ok_or(Error::MissingArg(opcode, 1))?
+                    .into_identifier()
+                    .ok_or(Error::BadArg(opcode, 1))?,
+                script: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression(),
+            }]),
+
+            Opcode::Thread | Opcode::ChildThread => Ok(vec![StatementKind::Thread {
+                kind: match opcode {
+                    Opcode::Thread => ThreadKind::Detached,
+                    _ => ThreadKind::Child,
+                },
+                block: {
+                    let end_opcode = match opcode {
+                        Opcode::Thread => Opcode::EndThread,
+                        _ => Opcode::EndChildThread,
+                    };
+
+                    let mut stmts = Vec::new();
+                    loop {
+                        match self.peek_op()? {
+                            (op, _) if *op == end_opcode => {
+                                self.consume_op()?;
+                                break;
+                            }
+                            _ => stmts.append(&mut self.decompile_op()?),
+                        };
+                    }
+                    stmts
+                },
+            }]),
+
             Opcode::SetInt | Opcode::SetRef | Opcode::SetFloat => {
-                let identifier_arg = opargs.get(0)
-                    .ok_or_else(|| Error::MissingArg(opcode, 0))?;
-                let identifier = identifier_arg.into_identifier()
-                    .ok_or_else(|| Error::BadArg(opcode, 0))?;
-
-                let expression = RefCell::new(opargs.get(1)
-                    .ok_or_else(|| Error::MissingArg(opcode, 1))?
-                    .into_expression());
-
-                // If we haven't seen the identifier yet, declare it.
-                if !self.seen_identifiers.contains(&identifier_arg) {
-                    self.seen_identifiers.insert(*identifier_arg);
-
-                    // Only declare identifiers that this function owns.
-                    match identifier_arg.kind() {
-                        ArgKind::FunWord => return Ok(vec![Statement::VarDeclare {
-                            datatype: RefCell::new(match opcode {
-                                // Floats are *always* floats, but bytecode ints
-                                // are sometimes pointers or some other datatype.
-                                // We'll leave detecting that to type inference.
-                                Opcode::SetFloat => DataType::Float,
-                                _                => DataType::Any,
-                            }),
-                            identifier,
-                            expression: Some(expression),
-                        }]),
+                let identifier_arg = *opargs.first().ok_or(Error::MissingArg(opcode, 0))?;
+                let identifier = identifier_arg
+                    .into_identifier()
+                    .ok_or(Error::BadArg(opcode, 0))?;
 
-                        ArgKind::FunFlag => return Ok(vec![Statement::VarDeclare {
-                            datatype: RefCell::new(DataType::Bool),
-                            identifier,
-                            expression: Some(expression),
-                        }]),
+                let value_arg = *opargs.get(1).ok_or(Error::MissingArg(opcode, 1))?;
+                let expression = match opcode {
+                    Opcode::SetRef => ExpressionKind::Const(Box::new(value_arg.into_expression())),
+                    _ => value_arg.into_expression().kind,
+                };
 
-                        _ => (),
+                // If we haven't seen the identifier yet, declare it. Only
+                // declare identifiers that this function owns.
+                let mut datatype = match (
+                    identifier_arg.kind(),
+                    self.seen_identifiers.insert(identifier_arg),
+                ) {
+                    // Floats are *always* floats, but bytecode ints are
+                    // sometimes pointers or some other datatype. We'll leave
+                    // detecting that to type inference.
+                    (ArgKind::FunWord, true) => Some(match opcode {
+                        Opcode::SetFloat => DataType::Float,
+                        _ => DataType::Any,
+                    }),
+                    (ArgKind::FunFlag, true) => Some(DataType::Bool),
+                    _ => None,
+                };
+
+                // Undeclared types are inferred from the value, as the
+                // compiler does. SetRef's value is an encoded argument, so
+                // only a literal has a type.
+                let infer = |bc: &Bytecode, datatype: &DataType| match (datatype, opcode) {
+                    (DataType::Any, Opcode::SetRef) => match value_arg.kind() {
+                        ArgKind::Int => DataType::Int,
+                        ArgKind::Float => DataType::Float,
+                        _ => DataType::Any,
+                    },
+                    (DataType::Any, _) => bc.value_datatype(value_arg),
+                    (datatype, _) => datatype.clone(),
+                };
+
+                // The compiler sets a variable with SetFloat if either it or
+                // the value is a float, so a variable that is set to another
+                // type of value than before is declared again.
+                if opcode != Opcode::SetRef {
+                    let var_datatype = match &datatype {
+                        Some(datatype) => infer(self, datatype),
+                        None => self
+                            .declared
+                            .get(&identifier_arg)
+                            .cloned()
+                            .unwrap_or(DataType::Any),
                     };
+
+                    match (opcode, var_datatype, self.value_datatype(value_arg)) {
+                        (Opcode::SetInt, _, DataType::Float) => {
+                            return Err(Error::BadArg(opcode, 1))
+                        }
+                        (Opcode::SetInt, DataType::Float, _) => datatype = Some(DataType::Int),
+                        (Opcode::SetFloat, DataType::Float, _)
+                        | (Opcode::SetFloat, _, DataType::Float) => (),
+                        (Opcode::SetFloat, _, _) => datatype = Some(DataType::Float),
+                        _ => (),
+                    }
+                }
+
+                match datatype {
+                    Some(datatype) => {
+                        let inferred = infer(self, &datatype);
+                        self.declared.insert(identifier_arg, inferred);
+
+                        Ok(vec![StatementKind::VarDeclare {
+                            datatype: RefCell::new(datatype),
+                            identifier,
+                            expression: Some(RefCell::new(expression.into())),
+                        }])
+                    }
+
+                    // If we've reached here, it's just assignment; no declaration needed.
+                    None => Ok(vec![StatementKind::VarAssign {
+                        identifier,
+                        expression: RefCell::new(expression.into()),
+                    }]),
                 }
+            }
+
+            Opcode::AddInt
+            | Opcode::SubInt
+            | Opcode::MulInt
+            | Opcode::DivInt
+            | Opcode::ModInt
+            | Opcode::AddFloat
+            | Opcode::SubFloat
+            | Opcode::MulFloat
+            | Opcode::DivFloat
+            | Opcode::And
+            | Opcode::Or => {
+                let identifier_arg = *opargs.first().ok_or(Error::MissingArg(opcode, 0))?;
+                let identifier = identifier_arg
+                    .into_identifier()
+                    .ok_or(Error::BadArg(opcode, 0))?;
+                let value_arg = *opargs.get(1).ok_or(Error::MissingArg(opcode, 1))?;
 
-                // If we've reached here, it's just assignment; no declaration needed.
-                Ok(vec![Statement::VarAssign { identifier, expression }])
-            },
+                let datatype = match opcode {
+                    Opcode::AddFloat | Opcode::SubFloat | Opcode::MulFloat | Opcode::DivFloat => {
+                        DataType::Float
+                    }
+                    _ => DataType::Int,
+                };
+
+                // Whether the compiler would choose the wrong opcode. Only
+                // arithmetic has a float version; the rest are always int.
+                let wrong = |bc: &Bytecode| match bc.operation_datatype(identifier_arg, value_arg) {
+                    DataType::Any => match opcode {
+                        Opcode::ModInt | Opcode::And | Opcode::Or => false,
+                        _ => true,
+                    },
+                    chosen => chosen != datatype,
+                };
+
+                // Declare the variable's type if it doesn't already say.
+                let mut stmts = Vec::new();
+                if wrong(self) {
+                    self.declared.insert(identifier_arg, datatype.clone());
+                    self.seen_identifiers.insert(identifier_arg);
+
+                    if wrong(self) {
+                        return Err(Error::BadArg(opcode, 1));
+                    }
 
-            Opcode::Call | Opcode::ExecWait | Opcode::Exec => Ok(vec![Statement::MethodCall {
-                method: opargs.get(0)
-                    .ok_or_else(|| Error::MissingArg(opcode, 0))?
-                    .into_ident_or_ptr()
-                    .ok_or_else(|| Error::BadArg(opcode, 0))?,
-                arguments: opargs.iter()
+                    stmts.push(StatementKind::VarDeclare {
+                        datatype: RefCell::new(datatype.clone()),
+                        identifier: identifier.clone(),
+                        expression: None,
+                    });
+                }
+
+                stmts.push(StatementKind::VarOpAssign {
+                    identifier,
+                    op: opcode.into_operator().unwrap(),
+                    expression: RefCell::new(value_arg.into_expression()),
+                    datatype,
+                });
+                Ok(stmts)
+            }
+
+            Opcode::UseIntBuffer | Opcode::UseFloatBuffer => Ok(vec![StatementKind::UseBuffer {
+                datatype: match opcode {
+                    Opcode::UseIntBuffer => DataType::Int,
+                    _ => DataType::Float,
+                },
+                buffer: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression(),
+            }]),
+
+            Opcode::Get1Int
+            | Opcode::Get2Int
+            | Opcode::Get3Int
+            | Opcode::Get4Int
+            | Opcode::Get1Float
+            | Opcode::Get2Float
+            | Opcode::Get3Float
+            | Opcode::Get4Float => {
+                let (count, datatype) = match opcode {
+                    Opcode::Get1Int => (1, DataType::Int),
+                    Opcode::Get2Int => (2, DataType::Int),
+                    Opcode::Get3Int => (3, DataType::Int),
+                    Opcode::Get4Int => (4, DataType::Int),
+                    Opcode::Get1Float => (1, DataType::Float),
+                    Opcode::Get2Float => (2, DataType::Float),
+                    Opcode::Get3Float => (3, DataType::Float),
+                    _ => (4, DataType::Float),
+                };
+
+                Ok(vec![StatementKind::BufferRead {
+                    identifiers: (0..count)
+                        .map(|n| {
+                            opargs
+                                .get(n as usize)
+                                .ok_or(Error::MissingArg(opcode, n))?
+                                .into_identifier()
+                                .ok_or(Error::BadArg(opcode, n))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    datatype,
+                }])
+            }
+            Opcode::GetIntN | Opcode::GetFloatN => Ok(vec![StatementKind::BufferReadIndex {
+                identifier: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_identifier()
+                    .ok_or(Error::BadArg(opcode, 0))?,
+                index: opargs
+                    .get(1)
+                    .ok_or(Error::MissingArg(opcode, 1))?
+                    .into_expression(),
+                datatype: match opcode {
+                    Opcode::GetIntN => DataType::Int,
+                    _ => DataType::Float,
+                },
+            }]),
+
+            Opcode::UseArray | Opcode::UseFlagArray => Ok(vec![StatementKind::UseArray {
+                kind: match opcode {
+                    Opcode::UseArray => ArrayKind::Words,
+                    _ => ArrayKind::Flags,
+                },
+                array: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression(),
+            }]),
+            Opcode::AllocArray => Ok(vec![StatementKind::ArrayDeclare {
+                identifier: opargs
+                    .get(1)
+                    .ok_or(Error::MissingArg(opcode, 1))?
+                    .into_identifier()
+                    .ok_or(Error::BadArg(opcode, 1))?,
+                size: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
+                    .into_expression(),
+            }]),
+
+            Opcode::Call | Opcode::ExecWait | Opcode::Exec => Ok(vec![StatementKind::MethodCall {
+                method: {
+                    let method = opargs
+                        .first()
+                        .ok_or(Error::MissingArg(opcode, 0))?
+                        .into_ident_or_ptr()
+                        .ok_or(Error::BadArg(opcode, 0))?;
+
+                    if let (Opcode::Exec, IdentifierOrPointer::Pointer(ptr))
+                    | (Opcode::ExecWait, IdentifierOrPointer::Pointer(ptr)) = (opcode, &method)
+                    {
+                        self.exec_targets.insert(*ptr);
+                    }
+
+                    method
+                },
+                arguments: opargs
+                    .iter()
                     .skip(1)
                     .map(|oparg| RefCell::new(oparg.into_expression()))
                     .collect(),
                 threading: match opcode {
                     Opcode::Exec => MethodThreading::Yes,
-                    _            => MethodThreading::No,
+                    _ => MethodThreading::No,
                 },
             }]),
-            Opcode::ExecRet => Ok(vec![Statement::MethodCall {
-                method: opargs.get(0)
-                    .ok_or_else(|| Error::MissingArg(opcode, 0))?
-                    .into_ident_or_ptr()
-                    .ok_or_else(|| Error::BadArg(opcode, 0))?,
-                arguments: opargs.iter()
+            Opcode::ExecRet => Ok(vec![StatementKind::MethodCall {
+                method: {
+                    let method = opargs
+                        .first()
+                        .ok_or(Error::MissingArg(opcode, 0))?
+                        .into_ident_or_ptr()
+                        .ok_or(Error::BadArg(opcode, 0))?;
+
+                    if let IdentifierOrPointer::Pointer(ptr) = method {
+                        self.exec_targets.insert(ptr);
+                    }
+
+                    method
+                },
+                arguments: opargs
+                    .iter()
                     .skip(2)
                     .map(|oparg| RefCell::new(oparg.into_expression()))
                     .collect(),
-                threading: MethodThreading::Assign(opargs.get(1)
-                    .ok_or_else(|| Error::MissingArg(opcode, 1))?
-                    .into_identifier()
-                    .ok_or_else(|| Error::BadArg(opcode, 1))?),
+                threading: MethodThreading::Assign(
+                    opargs
+                        .get(1)
+                        .ok_or(Error::MissingArg(opcode, 1))?
+                        .into_identifier()
+                        .ok_or(Error::BadArg(opcode, 1))?,
+                ),
             }]),
 
-            Opcode::Wait | Opcode::WaitSeconds => Ok(vec![Statement::Wait {
-                time: opargs.get(0)
-                    .ok_or_else(|| Error::MissingArg(opcode, 0))?
+            Opcode::Wait | Opcode::WaitSeconds => Ok(vec![StatementKind::Wait {
+                time: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
                     .into_expression(),
                 unit: match opcode {
                     Opcode::Wait => TimeUnit::Frames,
@@ -292,25 +816,27 @@ impl Bytecode {
                 },
             }]),
 
-            Opcode::Label => Ok(vec![Statement::Label {
-                name: opargs.get(0)
-                    .ok_or_else(|| Error::MissingArg(opcode, 0))?
+            Opcode::Label => Ok(vec![StatementKind::Label {
+                name: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
                     .into_int()
-                    .ok_or_else(|| Error::BadArg(opcode, 0))?
+                    .ok_or(Error::BadArg(opcode, 0))?
                     .to_string(),
             }]),
-            Opcode::Goto => Ok(vec![Statement::Goto {
-                label_name: opargs.get(0)
-                    .ok_or_else(|| Error::MissingArg(opcode, 0))?
+            Opcode::Goto => Ok(vec![StatementKind::Goto {
+                label_name: opargs
+                    .first()
+                    .ok_or(Error::MissingArg(opcode, 0))?
                     .into_int()
-                    .ok_or_else(|| Error::BadArg(opcode, 0))?
+                    .ok_or(Error::BadArg(opcode, 0))?
                     .to_string(),
             }]),
 
-            Opcode::Return => Ok(vec![Statement::Return]),
+            Opcode::Return => Ok(vec![StatementKind::Return]),
 
             Opcode::End => Err(Error::UnexpectedEnd),
-            _           => Err(Error::UnimplementedOpcode(opcode)),
+            _ => Err(Error::UnimplementedOpcode(opcode)),
         }
     }
 }
@@ -323,6 +849,9 @@ pub enum Error {
     #[fail(display = "missing End opcode")]
     MissingEnd,
 
+    #[fail(display = "case group is malformed")]
+    BadCaseGroup,
+
     #[fail(display = "unexpected End opcode")]
     UnexpectedEnd,
 
@@ -333,53 +862,156 @@ pub enum Error {
     BadArg(Opcode, u8),
 }
 
+#[derive(Debug, Fail)]
+pub enum DecodeError {
+    #[fail(display = "unknown opcode {:02X} at {}", _0, _1)]
+    UnknownOpcode(u32, Location),
+
+    #[fail(display = "opcode at {} has {} args", _1, _0)]
+    TooManyArgs(u32, Location),
+
+    #[fail(display = "script at {} is missing its End opcode", _0)]
+    TooLong(Location),
+
+    #[fail(display = "{}", _0)]
+    Read(#[fail(cause)] ReadError),
+}
+
+impl From<ReadError> for DecodeError {
+    fn from(error: ReadError) -> DecodeError {
+        DecodeError::Read(error)
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(error: std::io::Error) -> DecodeError {
+        DecodeError::Read(ReadError::Io(error))
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u32)] // The game stores opcodes as words, so we will too.
 pub enum Opcode {
-    End = 1, Return,
-    Label, Goto,
-    Loop, EndLoop, BreakLoop,
-    Wait, WaitSeconds,
-    IfEq, IfNe, IfLt, IfGt, IfLte, IfGte, IfAndNz, IfAndZ, Else, EndIf,
-    Switch, SwitchConst, CaseEq, CaseNe, CaseLt, CaseGt, CaseLte, CaseGte, CaseDefault,
-    CaseOrEq, CaseAndEq, CaseAndZ, EndCaseGroup, CaseRange, BreakCase, EndSwitch,
-    SetInt, SetRef, SetFloat,
-    AddInt, SubInt, MulInt, DivInt, ModInt,
-    AddFloat, SubFloat, MulFloat, DivFloat,
-    UseIntBuffer, Get1Int, Get2Int, Get3Int, Get4Int, GetIntN,
-    UseFloatBuffer, Get1Float, Get2Float, Get3Float, Get4Float, GetFloatN,
-    UseArray, UseFlagArray, AllocArray,
-    And, AndRef, Or, OrRef, // Unused?
-    Call, Exec, ExecRet, ExecWait,
-    Bind, Unbind,
-    Kill, Jump,
-    SetPriority, SetTimescale, SetSuspensionGroup,
+    End = 1,
+    Return,
+    Label,
+    Goto,
+    Loop,
+    EndLoop,
+    BreakLoop,
+    Wait,
+    WaitSeconds,
+    IfEq,
+    IfNe,
+    IfLt,
+    IfGt,
+    IfLte,
+    IfGte,
+    IfAndNz,
+    IfAndZ,
+    Else,
+    EndIf,
+    Switch,
+    SwitchConst,
+    CaseEq,
+    CaseNe,
+    CaseLt,
+    CaseGt,
+    CaseLte,
+    CaseGte,
+    CaseDefault,
+    CaseOrEq,
+    CaseAndEq,
+    CaseAndZ,
+    EndCaseGroup,
+    CaseRange,
+    BreakCase,
+    EndSwitch,
+    SetInt,
+    SetRef,
+    SetFloat,
+    AddInt,
+    SubInt,
+    MulInt,
+    DivInt,
+    ModInt,
+    AddFloat,
+    SubFloat,
+    MulFloat,
+    DivFloat,
+    UseIntBuffer,
+    Get1Int,
+    Get2Int,
+    Get3Int,
+    Get4Int,
+    GetIntN,
+    UseFloatBuffer,
+    Get1Float,
+    Get2Float,
+    Get3Float,
+    Get4Float,
+    GetFloatN,
+    UseArray,
+    UseFlagArray,
+    AllocArray,
+    And,
+    AndRef,
+    Or,
+    OrRef, // Unused?
+    Call,
+    Exec,
+    ExecRet,
+    ExecWait,
+    Bind,
+    Unbind,
+    Kill,
+    Jump,
+    SetPriority,
+    SetTimescale,
+    SetSuspensionGroup,
     BindLock,
-    SuspendAll, ResumeAll, SuspendOthers, ResumeOthers, Suspend, Resume,
-    DoesScriptExist, Thread, EndThread, ChildThread, EndChildThread,
+    SuspendAll,
+    ResumeAll,
+    SuspendOthers,
+    ResumeOthers,
+    Suspend,
+    Resume,
+    DoesScriptExist,
+    Thread,
+    EndThread,
+    ChildThread,
+    EndChildThread,
 }
 
 impl Opcode {
     pub fn into_operator(self) -> Option<Operator> {
         match self {
-            Opcode::IfEq    => Some(Operator::Eq),
-            Opcode::IfNe    => Some(Operator::Ne),
-            Opcode::IfLt    => Some(Operator::Lt),
-            Opcode::IfGt    => Some(Operator::Gt),
-            Opcode::IfLte   => Some(Operator::Lte),
-            Opcode::IfGte   => Some(Operator::Gte),
+            Opcode::IfEq => Some(Operator::Eq),
+            Opcode::IfNe => Some(Operator::Ne),
+            Opcode::IfLt => Some(Operator::Lt),
+            Opcode::IfGt => Some(Operator::Gt),
+            Opcode::IfLte => Some(Operator::Lte),
+            Opcode::IfGte => Some(Operator::Gte),
             Opcode::IfAndNz => Some(Operator::BitAndNz),
-            Opcode::IfAndZ  => Some(Operator::BitAndZ),
-
-            Opcode::CaseEq      => Some(Operator::Eq),
-            Opcode::CaseOrEq    => Some(Operator::Eq),
-            Opcode::CaseAndEq   => Some(Operator::Eq),
-            Opcode::CaseNe      => Some(Operator::Ne),
-            Opcode::CaseLt      => Some(Operator::Lt),
-            Opcode::CaseGt      => Some(Operator::Gt),
-            Opcode::CaseLte     => Some(Operator::Lte),
-            Opcode::CaseGte     => Some(Operator::Gte),
-            Opcode::CaseAndZ    => Some(Operator::BitAndZ),
+            Opcode::IfAndZ => Some(Operator::BitAndZ),
+
+            Opcode::CaseEq => Some(Operator::Eq),
+            Opcode::CaseOrEq => Some(Operator::Eq),
+            Opcode::CaseAndEq => Some(Operator::Eq),
+            Opcode::CaseNe => Some(Operator::Ne),
+            Opcode::CaseLt => Some(Operator::Lt),
+            Opcode::CaseGt => Some(Operator::Gt),
+            Opcode::CaseLte => Some(Operator::Lte),
+            Opcode::CaseGte => Some(Operator::Gte),
+            Opcode::CaseAndZ => Some(Operator::BitAndZ),
+
+            Opcode::AddInt | Opcode::AddFloat => Some(Operator::Add),
+            Opcode::SubInt | Opcode::SubFloat => Some(Operator::Sub),
+            Opcode::MulInt | Opcode::MulFloat => Some(Operator::Mul),
+            Opcode::DivInt | Opcode::DivFloat => Some(Operator::Div),
+            Opcode::ModInt => Some(Operator::Mod),
+            Opcode::And => Some(Operator::BitAnd),
+            Opcode::Or => Some(Operator::BitOr),
 
             _ => None,
         }
@@ -387,31 +1019,48 @@ impl Opcode {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct Arg(u32);
+pub struct Arg(pub u32);
 
-#[derive(Debug)]
+impl From<u32> for Arg {
+    fn from(word: u32) -> Arg {
+        Arg(word)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ArgKind {
-    Int, Float,
-    GameByte, AreaByte, MapWord, FunWord,
-    GameFlag, AreaFlag, MapFlag, FunFlag,
-    FlagArrayIndex, ArrayIndex,
+    Int,
+    Float,
+    GameByte,
+    AreaByte,
+    MapWord,
+    FunWord,
+    GameFlag,
+    AreaFlag,
+    MapFlag,
+    FunFlag,
+    FlagArrayIndex,
+    ArrayIndex,
 }
 
 impl Arg {
     pub fn into_expression(self) -> Expression {
         let s = self.as_signed();
 
-        match self.kind() {
-            ArgKind::Int   => Expression::LiteralInt(self.0),
-            ArgKind::Float => Expression::LiteralFloat(((s + 230000000) as f32) / 1024.0),
+        Expression::from(match self.kind() {
+            ArgKind::Int => ExpressionKind::LiteralInt(self.0),
+            ArgKind::Float => ExpressionKind::LiteralFloat(((s + 230000000) as f32) / 1024.0),
 
-            ArgKind::FlagArrayIndex =>
-                Expression::ArrayIndex(Identifier(FLAGARRAY_STR.to_string()), (s + 190000000) as u8),
-            ArgKind::ArrayIndex =>
-                Expression::ArrayIndex(Identifier(ARRAY_STR.to_string()), (s + 210000000) as u8),
+            ArgKind::FlagArrayIndex => ExpressionKind::ArrayIndex(
+                Identifier(FLAGARRAY_STR.to_string()),
+                (s + 210000000) as u8,
+            ),
+            ArgKind::ArrayIndex => {
+                ExpressionKind::ArrayIndex(Identifier(ARRAY_STR.to_string()), (s + 190000000) as u8)
+            }
 
-            _ => Expression::Identifier(self.into_identifier().unwrap()),
-        }
+            _ => ExpressionKind::Identifier(self.into_identifier().unwrap()),
+        })
     }
 
     pub fn into_identifier(self) -> Option<Identifier> {
@@ -419,23 +1068,19 @@ impl Arg {
 
         // TODO: check all the calculations here are correct
         match self.kind() {
-            ArgKind::GameByte =>
-                Some(Identifier(format!("{}_{:X}", GAMEBYTE_STR, (s + 170000000) as u32))),
-            ArgKind::AreaByte =>
-                Some(Identifier(format!("{}_{:X}", AREABYTE_STR, s + 150000000))),
-            ArgKind::MapWord =>
-                Some(Identifier(format!("{}_{:X}", MAPWORD_STR, s + 50000000))),
-            ArgKind::FunWord =>
-                Some(Identifier(format!("{}_{:X}", FUNWORD_STR, s + 30000000))),
-
-            ArgKind::GameFlag =>
-                Some(Identifier(format!("{}_{:X}", GAMEFLAG_STR, s + 130000000))),
-            ArgKind::AreaFlag =>
-                Some(Identifier(format!("{}_{:X}", AREAFLAG_STR, s + 110000000))),
-            ArgKind::MapFlag =>
-                Some(Identifier(format!("{}_{:X}", MAPFLAG_STR, s + 90000000))),
-            ArgKind::FunFlag =>
-                Some(Identifier(format!("{}_{:X}", FUNFLAG_STR, s + 70000000))),
+            ArgKind::GameByte => Some(Identifier(format!(
+                "{}_{:X}",
+                GAMEBYTE_STR,
+                (s + 170000000) as u32
+            ))),
+            ArgKind::AreaByte => Some(Identifier(format!("{}_{:X}", AREABYTE_STR, s + 150000000))),
+            ArgKind::MapWord => Some(Identifier(format!("{}_{:X}", MAPWORD_STR, s + 50000000))),
+            ArgKind::FunWord => Some(Identifier(format!("{}_{:X}", FUNWORD_STR, s + 30000000))),
+
+            ArgKind::GameFlag => Some(Identifier(format!("{}_{:X}", GAMEFLAG_STR, s + 130000000))),
+            ArgKind::AreaFlag => Some(Identifier(format!("{}_{:X}", AREAFLAG_STR, s + 110000000))),
+            ArgKind::MapFlag => Some(Identifier(format!("{}_{:X}", MAPFLAG_STR, s + 90000000))),
+            ArgKind::FunFlag => Some(Identifier(format!("{}_{:X}", FUNFLAG_STR, s + 70000000))),
 
             _ => None,
         }
@@ -495,3 +1140,238 @@ impl Arg {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::compile::compile_fun;
+    use crate::script::parse::{parse_script, Unparse};
+
+    /// Decompiles `asm` to source, then compiles that back to bytecode.
+    fn recompile(asm: &str) -> Bytecode {
+        let mut scope = Scope::new();
+        let block = Bytecode::assemble(asm)
+            .unwrap()
+            .decompile(&mut scope)
+            .unwrap();
+
+        let source = Declaration::from(DeclarationKind::Fun {
+            name: IdentifierOrPointer::Identifier(Identifier("test".to_string())),
+            arguments: Vec::new(),
+            block,
+        })
+        .unparse(&scope);
+
+        let script = parse_script(&source).unwrap();
+        compile_fun(&script.0[0], &scope).unwrap()
+    }
+
+    fn assert_recompiles(asm: &str) {
+        assert_eq!(
+            recompile(asm).disassemble(),
+            Bytecode::assemble(asm).unwrap().disassemble(),
+        );
+    }
+
+    #[test]
+    fn case_ranges_and_groups() {
+        let asm = "
+            Switch word_0
+                CaseRange 1, 5
+                    AddInt word_1, 1
+                    BreakCase
+                CaseOrEq 6
+                CaseOrEq 7
+                    And word_1, 255
+                EndCaseGroup
+                CaseAndEq 8
+                    Or word_1, 4
+                EndCaseGroup
+                CaseDefault
+            EndSwitch
+            End
+        ";
+
+        assert_recompiles(asm);
+    }
+
+    #[test]
+    fn ungrouped_end_case_group() {
+        let asm = "
+            Switch word_0
+                CaseEq 1
+                EndCaseGroup
+            EndSwitch
+            End
+        ";
+
+        let mut scope = Scope::new();
+        match Bytecode::assemble(asm).unwrap().decompile(&mut scope) {
+            Err(Error::BadCaseGroup) => (),
+            result => panic!("expected BadCaseGroup, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn loops() {
+        assert_recompiles(
+            "
+            Loop 0
+                Loop 3
+                    Wait 1
+                    IfEq word_0, 1
+                        BreakLoop
+                    EndIf
+                EndLoop
+                Loop word_1
+                EndLoop
+                BreakLoop
+            EndLoop
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn threads() {
+        assert_recompiles(
+            "
+            Thread
+                Wait 10
+                ChildThread
+                    SetInt word_0, 1
+                EndChildThread
+            EndThread
+            ChildThread
+            EndChildThread
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_recompiles(
+            "
+            SetInt word_0, 5
+            AddInt word_0, 1
+            SubInt word_0, word_1
+            MulInt word_0, 2
+            DivInt word_0, 3
+            ModInt word_0, 4
+            And word_0, 255
+            Or word_0, 16
+            SetFloat word_2, 1.0
+            AddFloat word_2, 0.5
+            SubFloat word_2, word_0
+            MulFloat word_2, 2.0
+            DivFloat word_2, 4
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn buffers_and_arrays() {
+        assert_recompiles(
+            "
+            UseIntBuffer 0x80240000
+            Get1Int word_0
+            Get2Int word_1, word_2
+            Get4Int word_3, word_4, word_5, word_6
+            GetIntN word_7, 2
+            UseFloatBuffer 0x80240100
+            Get3Float word_8, word_9, word_A
+            GetFloatN word_B, 1
+            AllocArray 4, mapword_0
+            UseArray mapword_0
+            UseFlagArray 0x80240200
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn bind_and_suspend() {
+        assert_recompiles(
+            "
+            Bind 0x80240000, 256, 1, 0, word_0
+            Bind 0x80240000, 256, 2, 1, 0
+            BindLock 0x80240000, 256, 3, 0x80240300, word_1, 1
+            Unbind
+            Kill word_0
+            Jump 0x80240000
+            SetPriority 5
+            SetTimescale 2.0
+            SetSuspensionGroup 1
+            SuspendAll 1
+            SuspendOthers 2
+            Suspend word_0
+            ResumeAll 1
+            ResumeOthers 2
+            Resume word_0
+            DoesScriptExist word_0, word_2
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        // Variables whose type doesn't say which opcode to use are declared.
+        assert_recompiles(
+            "
+            SetFloat word_0, 1.5
+            AddFloat word_0, 2.0
+            AddFloat word_1, word_2
+            MulFloat word_1, word_0
+            SetInt word_1, 3
+            AddInt word_1, word_3
+            SubInt word_4, word_5
+            DivFloat mapword_0, 4
+            ModInt mapword_0, 3
+            End
+            ",
+        );
+
+        // The compiler would choose SetFloat for this.
+        let mut scope = Scope::new();
+        let asm = "SetInt word_0, 1.5\nEnd";
+        match Bytecode::assemble(asm).unwrap().decompile(&mut scope) {
+            Err(Error::BadArg(Opcode::SetInt, 1)) => (),
+            result => panic!("expected BadArg, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn exec() {
+        // Scripts that are executed are known to be funs, so that waiting
+        // on one isn't compiled as a Call.
+        assert_recompiles(
+            "
+            Exec 0x80240100
+            ExecWait 0x80240100
+            ExecRet 0x80240200, word_0
+            ExecWait 0x80240200
+            Call 0x80240300, 1
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn constants() {
+        assert_recompiles(
+            "
+            SetRef word_0, mapword_1
+            SetRef word_0, 5
+            SetRef mapword_2, word_0
+            SwitchConst word_0
+                CaseEq 1
+                    BreakCase
+            EndSwitch
+            End
+            ",
+        );
+    }
+}
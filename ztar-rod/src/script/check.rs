@@ -0,0 +1,1042 @@
+use super::api::Api;
+use super::asm::assemble_arg;
+use super::datatype::{DataType, TypeDefinition};
+use super::globals::*;
+use super::parse::ast::*;
+use super::parse::Unparse;
+use super::Scope;
+use crate::rom::Region;
+use failure_derive::*;
+use std::collections::HashMap;
+
+/// Type-checks a parsed script against the global methods and its own
+/// declarations, returning every error found rather than just the first.
+///
+/// Variables named by number (e.g. `word_3`) may hold anything, so are `any`;
+/// as are calls to addresses with no known signature.
+pub fn check_script(script: &Script, api: &Api, region: Region) -> Result<(), Vec<Error>> {
+    let mut checker = Checker {
+        scope: Scope::new(),
+        errors: Vec::new(),
+        signatures: HashMap::new(),
+    };
+
+    // Bring global methods into scope
+    api.declare(&mut checker.scope, region);
+
+    // Methods that share a name take the same arguments, so the first is as
+    // good as any.
+    for method in api.methods.iter() {
+        checker
+            .signatures
+            .entry(method.name.clone())
+            .or_insert_with(|| Signature {
+                parameters: method.arguments.clone(),
+                doc: method.doc.clone(),
+            });
+    }
+
+    // Declarations can refer to each other regardless of order.
+    for declaration in script.0.iter() {
+        match &declaration.kind {
+            DeclarationKind::Enum {
+                name: Identifier(name),
+                variants,
+            } => {
+                let variants = variants
+                    .iter()
+                    .map(|(Identifier(id), value)| (id.clone(), *value))
+                    .collect();
+                checker
+                    .scope
+                    .insert_type(name.clone(), TypeDefinition::Enum(variants));
+            }
+            DeclarationKind::Struct {
+                name: Identifier(name),
+                fields,
+            } => {
+                let fields = fields
+                    .iter()
+                    .map(|(Identifier(id), ty)| (id.clone(), ty.clone()))
+                    .collect();
+                checker
+                    .scope
+                    .insert_type(name.clone(), TypeDefinition::Struct(fields));
+            }
+            _ => (),
+        }
+    }
+
+    checker.scope.push();
+    for declaration in script.0.iter() {
+        let (name, datatype) = match &declaration.kind {
+            DeclarationKind::Fun {
+                name, arguments, ..
+            } => (
+                name,
+                DataType::Fun(arguments.iter().map(|(_, ty)| ty.clone()).collect()),
+            ),
+            DeclarationKind::Data { name, datatype, .. } => {
+                (name, DataType::Arr(Box::new(datatype.clone())))
+            }
+            _ => continue,
+        };
+
+        if let IdentifierOrPointer::Identifier(Identifier(name)) = name {
+            checker.scope.insert_name(name.clone(), datatype);
+
+            if let DeclarationKind::Fun { arguments, .. } = &declaration.kind {
+                let parameters = arguments
+                    .iter()
+                    .map(|(Identifier(name), datatype)| (name.clone(), datatype.clone()))
+                    .collect();
+
+                checker.signatures.insert(
+                    name.clone(),
+                    Signature {
+                        parameters,
+                        doc: None,
+                    },
+                );
+            }
+        }
+    }
+
+    for declaration in script.0.iter() {
+        checker.declaration(declaration);
+    }
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+/// A type error, and the span of source it was found in, if known. Errors in
+/// calls to methods with named parameters are noted with their signature.
+#[derive(Debug, Fail)]
+#[fail(display = "{}", kind)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "undeclared identifier '{}'", _0)]
+    UndeclaredIdentifier(String),
+
+    #[fail(display = "expected {}, found {}", expected, found)]
+    TypeMismatch { expected: DataType, found: DataType },
+
+    #[fail(
+        display = "'{}' expects {} arguments, found {}",
+        method, expected, found
+    )]
+    ArgCount {
+        method: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[fail(
+        display = "argument '{}' of '{}' expects {}, found {}",
+        parameter, method, expected, found
+    )]
+    ArgumentMismatch {
+        method: String,
+        parameter: String,
+        expected: DataType,
+        found: DataType,
+    },
+
+    #[fail(display = "'{}' is {}, which cannot be called", _0, _1)]
+    NotCallable(String, DataType),
+
+    #[fail(display = "'{}' is not a fun, so cannot be run as a thread", _0)]
+    NotThreadable(String),
+
+    #[fail(display = "operator '{}' cannot be applied to {}", _0, _1)]
+    BadOperand(String, DataType),
+
+    #[fail(display = "unknown type '{}'", _0)]
+    UnknownType(String),
+
+    #[fail(display = "enum '{}' has no variant '{}'", _0, _1)]
+    UnknownVariant(String, String),
+
+    #[fail(display = "struct '{}' has no field '{}'", _0, _1)]
+    UnknownField(String, String),
+
+    #[fail(display = "missing field '{}' of struct '{}'", _1, _0)]
+    MissingField(String, String),
+}
+
+struct Checker {
+    scope: Scope,
+    errors: Vec<Error>,
+
+    /// Parameter names and docs of the methods that have them, by name.
+    signatures: HashMap<String, Signature>,
+}
+
+struct Signature {
+    parameters: Vec<(String, DataType)>,
+    doc: Option<String>,
+}
+
+impl Checker {
+    fn error(&mut self, kind: ErrorKind, span: Option<Span>) {
+        self.errors.push(Error {
+            kind,
+            span,
+            notes: Vec::new(),
+        });
+    }
+
+    /// Notes for an error in a call to `method`: its signature, with the
+    /// names of its parameters, and what it does, if known.
+    fn signature_notes(&self, method: &str) -> Vec<String> {
+        let signature = match self.signatures.get(method) {
+            Some(signature) => signature,
+            None => return Vec::new(),
+        };
+
+        let parameters: Vec<String> = signature
+            .parameters
+            .iter()
+            .map(|(name, datatype)| format!("{}: {}", name, datatype))
+            .collect();
+
+        let mut notes = vec![format!("{}({})", method, parameters.join(", "))];
+        notes.extend(signature.doc.iter().cloned());
+        notes
+    }
+
+    /// Errors unless a value of type `found` can be used where `expected` is.
+    fn expect(&mut self, expected: DataType, found: DataType, span: Option<Span>) {
+        if !assignable(&expected, &found) {
+            self.error(ErrorKind::TypeMismatch { expected, found }, span);
+        }
+    }
+
+    /// Errors if the datatype refers to an enum or struct that doesn't exist.
+    fn datatype(&mut self, datatype: &DataType, span: Option<Span>) {
+        match datatype {
+            DataType::Arr(item) => self.datatype(item, span),
+
+            DataType::Fun(arguments) | DataType::Asm(arguments) => {
+                for argument in arguments.iter() {
+                    self.datatype(argument, span);
+                }
+            }
+
+            DataType::Named(name) => {
+                if self.scope.lookup_type(name).is_none() {
+                    self.error(ErrorKind::UnknownType(name.clone()), span);
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        let span = declaration.span;
+
+        match &declaration.kind {
+            DeclarationKind::Fun {
+                arguments, block, ..
+            } => {
+                self.scope.push();
+
+                for (Identifier(name), datatype) in arguments.iter() {
+                    self.datatype(datatype, span);
+                    self.scope.insert_name(name.clone(), datatype.clone());
+                }
+
+                self.block(block);
+                self.scope.pop();
+            }
+
+            DeclarationKind::Data {
+                datatype, items, ..
+            } => {
+                self.datatype(datatype, span);
+
+                for item in items.iter() {
+                    let found = self.expression(item);
+                    self.expect(datatype.clone(), found, item.span.or(span));
+                }
+            }
+
+            DeclarationKind::Enum { .. } => (),
+
+            DeclarationKind::Struct { fields, .. } => {
+                for (_, datatype) in fields.iter() {
+                    self.datatype(datatype, span);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, block: &[Statement]) {
+        self.scope.push();
+
+        for stmt in block.iter() {
+            self.statement(stmt);
+        }
+
+        self.scope.pop();
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        let span = stmt.span;
+
+        match &stmt.kind {
+            StatementKind::Return => (),
+            StatementKind::Label { .. } => (),
+            StatementKind::Goto { .. } => (),
+            StatementKind::BreakLoop | StatementKind::BreakCase => (),
+            StatementKind::Unbind => (),
+
+            StatementKind::VarAssign {
+                identifier,
+                expression,
+            } => {
+                let expression = expression.borrow();
+                let expected = self.variable(identifier, span);
+                let found = self.expression(&expression);
+                self.expect(expected, found, expression.span.or(span));
+            }
+
+            StatementKind::VarOpAssign {
+                identifier,
+                op,
+                expression,
+                ..
+            } => {
+                let expression = expression.borrow();
+                let var = self.variable(identifier, span);
+                let value = self.expression(&expression);
+
+                // Bitwise operators only make sense for ints.
+                let operand: fn(&DataType) -> bool = match op {
+                    Operator::BitAnd | Operator::BitOr => integer,
+                    _ => numeric,
+                };
+
+                if !operand(&var) {
+                    self.error(
+                        ErrorKind::BadOperand(op.clone().unparse(&self.scope), var.clone()),
+                        span,
+                    );
+                } else if !operand(&value) {
+                    self.error(
+                        ErrorKind::BadOperand(op.clone().unparse(&self.scope), value),
+                        expression.span.or(span),
+                    );
+                } else {
+                    self.expect(var, value, expression.span.or(span));
+                }
+            }
+
+            StatementKind::VarDeclare {
+                datatype,
+                identifier: Identifier(name),
+                expression,
+            } => {
+                let declared = datatype.borrow().clone();
+                self.datatype(&declared, span);
+
+                let found = match expression {
+                    Some(expression) => {
+                        let expression = expression.borrow();
+                        let found = self.expression(&expression);
+                        self.expect(declared.clone(), found.clone(), expression.span.or(span));
+                        found
+                    }
+                    None => DataType::Any,
+                };
+
+                // Undeclared types are inferred from the initial value.
+                let datatype = match declared {
+                    DataType::Any => found,
+                    declared => declared,
+                };
+
+                self.scope.insert_name(name.clone(), datatype);
+            }
+
+            StatementKind::MethodCall {
+                method,
+                arguments,
+                threading,
+            } => {
+                let method_name = method.clone().unparse(&self.scope);
+
+                let datatype = match method {
+                    IdentifierOrPointer::Identifier(identifier) => self.variable(identifier, span),
+
+                    // Addresses with no name could be anything.
+                    IdentifierOrPointer::Pointer(ptr) => match self.scope.lookup_ptr(*ptr) {
+                        Some(name) => self
+                            .scope
+                            .lookup_name(name)
+                            .cloned()
+                            .unwrap_or(DataType::Any),
+                        None => DataType::Any,
+                    },
+                };
+
+                let found: Vec<(DataType, Option<Span>)> = arguments
+                    .iter()
+                    .map(|argument| {
+                        let argument = argument.borrow();
+                        (self.expression(&argument), argument.span.or(span))
+                    })
+                    .collect();
+
+                match &datatype {
+                    DataType::Fun(parameters) | DataType::Asm(parameters) => {
+                        if parameters.len() != found.len() {
+                            self.errors.push(Error {
+                                kind: ErrorKind::ArgCount {
+                                    method: method_name.clone(),
+                                    expected: parameters.len(),
+                                    found: found.len(),
+                                },
+                                span,
+                                notes: self.signature_notes(&method_name),
+                            });
+                        }
+
+                        for (n, (expected, (found, argument_span))) in
+                            parameters.iter().zip(found.into_iter()).enumerate()
+                        {
+                            if assignable(expected, &found) {
+                                continue;
+                            }
+
+                            // Name the parameter, where the signature does.
+                            let parameter = self
+                                .signatures
+                                .get(&method_name)
+                                .and_then(|signature| signature.parameters.get(n))
+                                .map(|(name, _)| name.clone());
+
+                            match parameter {
+                                Some(parameter) => self.errors.push(Error {
+                                    kind: ErrorKind::ArgumentMismatch {
+                                        method: method_name.clone(),
+                                        parameter,
+                                        expected: expected.clone(),
+                                        found,
+                                    },
+                                    span: argument_span,
+                                    notes: self.signature_notes(&method_name),
+                                }),
+                                None => self.error(
+                                    ErrorKind::TypeMismatch {
+                                        expected: expected.clone(),
+                                        found,
+                                    },
+                                    argument_span,
+                                ),
+                            }
+                        }
+                    }
+
+                    DataType::Any => (),
+
+                    datatype => self.error(
+                        ErrorKind::NotCallable(method_name.clone(), datatype.clone()),
+                        span,
+                    ),
+                }
+
+                match threading {
+                    MethodThreading::No => (),
+                    _ => match datatype {
+                        DataType::Fun(_) | DataType::Any => (),
+                        _ => self.error(ErrorKind::NotThreadable(method_name), span),
+                    },
+                }
+
+                // Threads are identified by an int.
+                if let MethodThreading::Assign(identifier) = threading {
+                    self.assign(identifier, DataType::Int, span);
+                }
+            }
+
+            StatementKind::Wait { time, .. } => {
+                let found = self.expression(time);
+
+                if !numeric(&found) {
+                    self.error(
+                        ErrorKind::TypeMismatch {
+                            expected: DataType::Int,
+                            found,
+                        },
+                        time.span.or(span),
+                    );
+                }
+            }
+
+            StatementKind::If {
+                condition,
+                block_true,
+                block_false,
+            } => {
+                let found = self.expression(condition);
+                self.expect(DataType::Bool, found, condition.span.or(span));
+
+                self.block(block_true);
+                self.block(block_false);
+            }
+
+            StatementKind::Switch { expression, cases } => {
+                let datatype = self.expression(expression);
+
+                for (case, block) in cases.iter() {
+                    match case {
+                        Case::Test { operator, against } => {
+                            let found = self.expression(against);
+
+                            match operator {
+                                Operator::BitAndZ => {
+                                    self.expect(DataType::Int, found, against.span.or(span))
+                                }
+                                _ => self.expect(datatype.clone(), found, against.span.or(span)),
+                            }
+                        }
+                        Case::Range { low, high } => {
+                            for value in [low, high].iter() {
+                                let found = self.expression(value);
+                                self.expect(datatype.clone(), found, value.span.or(span));
+                            }
+                        }
+                        Case::AnyOf(values) | Case::AllOf(values) => {
+                            for value in values.iter() {
+                                let found = self.expression(value);
+                                self.expect(datatype.clone(), found, value.span.or(span));
+                            }
+                        }
+                        Case::Default => (),
+                    }
+
+                    self.block(block);
+                }
+            }
+
+            StatementKind::Loop { count, block } => {
+                if let Some(count) = count {
+                    let found = self.expression(count);
+                    self.expect(DataType::Int, found, count.span.or(span));
+                }
+
+                self.block(block);
+            }
+
+            StatementKind::UseBuffer { datatype, buffer } => {
+                // Buffers are usually data tables, but any pointer will do.
+                match self.expression(buffer) {
+                    DataType::Arr(item) => {
+                        self.expect(datatype.clone(), *item, buffer.span.or(span))
+                    }
+                    found => self.expect(DataType::Int, found, buffer.span.or(span)),
+                }
+            }
+            StatementKind::BufferRead {
+                identifiers,
+                datatype,
+            } => {
+                for identifier in identifiers.iter() {
+                    self.assign(identifier, datatype.clone(), span);
+                }
+            }
+            StatementKind::BufferReadIndex {
+                identifier,
+                index,
+                datatype,
+            } => {
+                let found = self.expression(index);
+                self.expect(DataType::Int, found, index.span.or(span));
+
+                self.assign(identifier, datatype.clone(), span);
+            }
+
+            StatementKind::UseArray { array, .. } => {
+                self.expression(array);
+            }
+            StatementKind::ArrayDeclare { identifier, size } => {
+                let found = self.expression(size);
+                self.expect(DataType::Int, found, size.span.or(span));
+
+                self.variable(identifier, span);
+            }
+
+            StatementKind::Bind {
+                script,
+                trigger,
+                target,
+                items,
+                prompt,
+                handle,
+            } => {
+                self.expression(script);
+                self.expression(trigger);
+                self.expression(target);
+                self.expression(prompt);
+
+                if let Some(items) = items {
+                    self.expression(items);
+                }
+
+                // The handle is the trigger that was bound, i.e. a pointer.
+                if let Some(handle) = handle {
+                    self.assign(handle, DataType::Int, span);
+                }
+            }
+
+            StatementKind::Kill { script } => {
+                self.expression(script);
+            }
+            StatementKind::Jump { script } => {
+                self.expression(script);
+            }
+            StatementKind::Priority { priority } => {
+                self.expression(priority);
+            }
+            StatementKind::Timescale { timescale } => {
+                self.expression(timescale);
+            }
+            StatementKind::Group { group } => {
+                self.expression(group);
+            }
+
+            StatementKind::Suspend { target } | StatementKind::Resume { target } => match target {
+                ScriptTarget::Script(expression)
+                | ScriptTarget::Group(expression)
+                | ScriptTarget::Others(expression) => {
+                    self.expression(expression);
+                }
+            },
+
+            StatementKind::ScriptExists { identifier, script } => {
+                self.expression(script);
+                self.assign(identifier, DataType::Bool, span);
+            }
+
+            StatementKind::Thread { block, .. } => self.block(block),
+        }
+    }
+
+    /// Checks that a value of type `found` can be stored in a variable.
+    fn assign(&mut self, identifier: &Identifier, found: DataType, span: Option<Span>) {
+        let expected = self.variable(identifier, span);
+        self.expect(expected, found, span);
+    }
+
+    /// The datatype of a variable, erroring if it's undeclared.
+    fn variable(&mut self, Identifier(name): &Identifier, span: Option<Span>) -> DataType {
+        // Numbered variables, and `array[n]` etc., are always in scope.
+        if assemble_arg(name).is_some() {
+            return DataType::Any;
+        }
+
+        match self.scope.lookup_name(name) {
+            Some(datatype) => datatype.clone(),
+            None => {
+                self.error(ErrorKind::UndeclaredIdentifier(name.clone()), span);
+                DataType::Any
+            }
+        }
+    }
+
+    /// Errors for each operand that the operator can't be applied to.
+    fn operands(
+        &mut self,
+        op: &Operator,
+        operands: &[(&DataType, Option<Span>)],
+        check: fn(&DataType) -> bool,
+    ) {
+        for (datatype, span) in operands.iter() {
+            if !check(datatype) {
+                let op = op.clone().unparse(&self.scope);
+                self.error(ErrorKind::BadOperand(op, (*datatype).clone()), *span);
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> DataType {
+        let span = expression.span;
+
+        match &expression.kind {
+            ExpressionKind::LiteralInt(_) => DataType::Int,
+            ExpressionKind::LiteralFloat(_) => DataType::Float,
+            ExpressionKind::LiteralBool(_) => DataType::Bool,
+
+            ExpressionKind::Identifier(identifier) => self.variable(identifier, span),
+
+            ExpressionKind::ArrayIndex(Identifier(name), _) => {
+                if name == ARRAY_STR || name == FLAGARRAY_STR {
+                    return DataType::Any;
+                }
+
+                match self.variable(&Identifier(name.clone()), span) {
+                    DataType::Arr(item) => *item,
+                    DataType::Any => DataType::Any,
+                    found => {
+                        let expected = DataType::Arr(Box::new(DataType::Any));
+                        self.error(ErrorKind::TypeMismatch { expected, found }, span);
+                        DataType::Any
+                    }
+                }
+            }
+
+            ExpressionKind::Variant {
+                enum_name: Identifier(enum_name),
+                variant: Identifier(variant),
+            } => {
+                let has_variant = match self.scope.lookup_type(enum_name) {
+                    Some(definition) => definition.variant_value(variant).is_some(),
+                    None => {
+                        self.error(ErrorKind::UnknownType(enum_name.clone()), span);
+                        return DataType::Any;
+                    }
+                };
+
+                if !has_variant {
+                    self.error(
+                        ErrorKind::UnknownVariant(enum_name.clone(), variant.clone()),
+                        span,
+                    );
+                }
+
+                DataType::Named(enum_name.clone())
+            }
+
+            ExpressionKind::StructLiteral {
+                name: Identifier(name),
+                fields,
+            } => {
+                let definition = match self.scope.lookup_type(name) {
+                    Some(TypeDefinition::Struct(definition)) => definition.clone(),
+                    _ => {
+                        self.error(ErrorKind::UnknownType(name.clone()), span);
+                        return DataType::Any;
+                    }
+                };
+
+                for (Identifier(field), value) in fields.iter() {
+                    let found = self.expression(value);
+
+                    match definition.iter().find(|(name, _)| name == field) {
+                        Some((_, expected)) => {
+                            self.expect(expected.clone(), found, value.span.or(span))
+                        }
+                        None => self.error(
+                            ErrorKind::UnknownField(name.clone(), field.clone()),
+                            value.span.or(span),
+                        ),
+                    }
+                }
+
+                for (field, _) in definition.iter() {
+                    if !fields.iter().any(|(Identifier(name), _)| name == field) {
+                        self.error(ErrorKind::MissingField(name.clone(), field.clone()), span);
+                    }
+                }
+
+                DataType::Named(name.clone())
+            }
+
+            ExpressionKind::Operation { lhs, op, rhs } => {
+                let lhs_type = self.expression(lhs);
+                let rhs_type = self.expression(rhs);
+
+                let operands = [
+                    (&lhs_type, lhs.span.or(span)),
+                    (&rhs_type, rhs.span.or(span)),
+                ];
+
+                match op {
+                    Operator::Add
+                    | Operator::Sub
+                    | Operator::Mul
+                    | Operator::Div
+                    | Operator::Mod => {
+                        self.operands(op, &operands, numeric);
+
+                        match (&lhs_type, &rhs_type) {
+                            (DataType::Float, _) | (_, DataType::Float) => DataType::Float,
+                            (DataType::Int, DataType::Int) => DataType::Int,
+                            _ => DataType::Any,
+                        }
+                    }
+
+                    Operator::Eq | Operator::Ne => {
+                        if !assignable(&lhs_type, &rhs_type) && !assignable(&rhs_type, &lhs_type) {
+                            self.error(
+                                ErrorKind::TypeMismatch {
+                                    expected: lhs_type,
+                                    found: rhs_type,
+                                },
+                                rhs.span.or(span),
+                            );
+                        }
+
+                        DataType::Bool
+                    }
+
+                    Operator::Gt | Operator::Lt | Operator::Gte | Operator::Lte => {
+                        self.operands(op, &operands, numeric);
+                        DataType::Bool
+                    }
+
+                    Operator::BitAndZ | Operator::BitAndNz => {
+                        self.operands(op, &operands, integer);
+                        DataType::Bool
+                    }
+
+                    Operator::BitAnd | Operator::BitOr => {
+                        self.operands(op, &operands, integer);
+                        DataType::Int
+                    }
+
+                    Operator::And | Operator::Or | Operator::Not => {
+                        self.operands(op, &operands, boolean);
+                        DataType::Bool
+                    }
+                }
+            }
+
+            // A variable's encoding isn't of the variable's type.
+            ExpressionKind::Const(expression) => {
+                let found = self.expression(expression);
+
+                match expression.kind {
+                    ExpressionKind::Identifier(_) | ExpressionKind::ArrayIndex(..) => DataType::Any,
+                    _ => found,
+                }
+            }
+        }
+    }
+}
+
+/// Whether a value of type `found` can be used where `expected` is. Ints are
+/// converted to floats implicitly, but not the other way around.
+fn assignable(expected: &DataType, found: &DataType) -> bool {
+    match (expected, found) {
+        (DataType::Any, _) | (_, DataType::Any) => true,
+
+        (DataType::Int, DataType::Int) => true,
+        (DataType::Float, DataType::Float) => true,
+        (DataType::Float, DataType::Int) => true,
+        (DataType::Bool, DataType::Bool) => true,
+
+        (DataType::Arr(expected), DataType::Arr(found)) => assignable(expected, found),
+
+        // Enums are ints and structs are pointed to by them, but they're only
+        // interchangeable with ints, not each other.
+        (DataType::Named(expected), DataType::Named(found)) => expected == found,
+        (DataType::Named(_), DataType::Int) | (DataType::Int, DataType::Named(_)) => true,
+
+        (DataType::Fun(expected), DataType::Fun(found))
+        | (DataType::Asm(expected), DataType::Asm(found)) => {
+            expected.len() == found.len()
+                && expected
+                    .iter()
+                    .zip(found.iter())
+                    .all(|(expected, found)| assignable(expected, found))
+        }
+
+        _ => false,
+    }
+}
+
+fn numeric(datatype: &DataType) -> bool {
+    match datatype {
+        DataType::Any | DataType::Int | DataType::Float => true,
+        _ => false,
+    }
+}
+
+fn integer(datatype: &DataType) -> bool {
+    match datatype {
+        DataType::Any | DataType::Int => true,
+        _ => false,
+    }
+}
+
+fn boolean(datatype: &DataType) -> bool {
+    match datatype {
+        DataType::Any | DataType::Bool => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::parse::parse_script;
+
+    /// Checks `block` as the body of a fun, with `helper(int, float)` declared
+    /// alongside it, and returns each error's message.
+    fn check(block: &str) -> Vec<String> {
+        let source = format!(
+            "fun test() {{\n{}\n}}\nfun helper(a: int, b: float) {{\n}}",
+            block
+        );
+        let script = parse_script(&source).unwrap();
+
+        match check_script(&script, &Api::bundled(), Region::America) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn well_typed() {
+        let errors = check(concat!(
+            "var i = 1\n",
+            "var f: float = 2\n",
+            "f += i\n",
+            "i &= 3\n",
+            "helper(i, f)\n",
+            "if i == 1 and f > 0.5 {\n",
+            "    word_0 = 1.5\n",
+            "}",
+        ));
+
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(
+            check("helper(1)\nhelper(1, 2.0, 3)"),
+            vec![
+                "'helper' expects 2 arguments, found 1",
+                "'helper' expects 2 arguments, found 3",
+            ],
+        );
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(
+            check(concat!(
+                "var i = 1\n",
+                "var f = 1.5\n",
+                "var b = true\n",
+                "i &= f\n",
+                "f |= 1\n",
+                "b += 1\n",
+                "if b + 1 == 2 {\n",
+                "}\n",
+                "if i or b {\n",
+                "}",
+            )),
+            vec![
+                "operator '&' cannot be applied to float",
+                "operator '|' cannot be applied to float",
+                "operator '+' cannot be applied to bool",
+                "operator '+' cannot be applied to bool",
+                "operator 'or' cannot be applied to int",
+            ],
+        );
+    }
+
+    #[test]
+    fn undeclared() {
+        assert_eq!(
+            check("y = 1\nmissing()\nwait z"),
+            vec![
+                "undeclared identifier 'y'",
+                "undeclared identifier 'missing'",
+                "undeclared identifier 'z'",
+            ],
+        );
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(
+            check(concat!(
+                "var i = 1\n",
+                "i = true\n",
+                "i = 1.5\n",
+                "var b: bool = 1\n",
+                "helper(1.5, 1)\n",
+                "if i {\n",
+                "}",
+            )),
+            vec![
+                "expected int, found bool",
+                "expected int, found float",
+                "expected bool, found int",
+                "argument 'a' of 'helper' expects int, found float",
+                "expected bool, found int",
+            ],
+        );
+    }
+
+    #[test]
+    fn signature_notes() {
+        let script = parse_script("fun test() {\n    model_set_vis(1, 2)\n}").unwrap();
+        let errors = check_script(&script, &Api::bundled(), Region::America).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "argument 'visible' of 'model_set_vis' expects bool, found int",
+        );
+        assert_eq!(
+            errors[0].notes,
+            vec![
+                "model_set_vis(model: int, visible: bool)",
+                "Shows or hides a model."
+            ],
+        );
+    }
+
+    #[test]
+    fn errors_are_collected() {
+        // Errors in one statement don't stop the rest being checked, and
+        // each is pointed at where it was found.
+        let source = concat!(
+            "fun test() {\n",
+            "    x = 1\n",
+            "    var f = 1.5\n",
+            "    f &= 1\n",
+            "    test(1)\n",
+            "}",
+        );
+        let script = parse_script(source).unwrap();
+        let errors = check_script(&script, &Api::bundled(), Region::America).unwrap_err();
+
+        let found: Vec<(String, &str)> = errors
+            .iter()
+            .map(|error| {
+                let span = error.span.unwrap();
+                (error.to_string(), &source[span.start..span.end])
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("undeclared identifier 'x'".to_string(), "x = 1"),
+                (
+                    "operator '&' cannot be applied to float".to_string(),
+                    "f &= 1",
+                ),
+                ("'test' expects 0 arguments, found 1".to_string(), "test(1)"),
+            ],
+        );
+    }
+}
@@ -0,0 +1,1278 @@
+use super::asm::assemble_arg;
+use super::bc::{Arg, ArgKind, Bytecode, Opcode, Operation};
+use super::datatype::{DataType, TypeDefinition};
+use super::globals::*;
+use super::parse::ast::*;
+use super::parse::Unparse;
+use super::Scope;
+use failure_derive::*;
+use std::collections::{HashMap, HashSet};
+
+/// Number of FunWords and FunFlags a script has to itself.
+const FUNWORD_COUNT: u32 = 16;
+const FUNFLAG_COUNT: u32 = 96;
+
+/// Number of labels a script can jump to.
+const LABEL_COUNT: i32 = 16;
+
+/// Lowers a function declaration to bytecode.
+///
+/// Named locals (and arguments) are given FunWord slots, or FunFlag slots if
+/// they are bools, that aren't used by name (e.g. `word_3`) elsewhere in the
+/// function. Functions called with arguments are passed them the same way the
+/// game does: by setting `word_0`, `word_1`, etc. before calling.
+pub fn compile_fun(declaration: &Declaration, scope: &Scope) -> Result<Bytecode, Error> {
+    let (arguments, block) = match &declaration.kind {
+        DeclarationKind::Fun {
+            arguments, block, ..
+        } => (arguments, block),
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::NotFun,
+                span: declaration.span,
+            })
+        }
+    };
+
+    // The first pass finds which variables and labels are referred to by
+    // number, so that the second doesn't allocate them to anything else.
+    let mut first_pass = Compiler::new(scope, HashSet::new(), HashSet::new());
+    first_pass.fun(arguments, block)?;
+
+    let mut compiler = Compiler::new(scope, first_pass.numbered_vars, first_pass.numbered_labels);
+    compiler.fun(arguments, block)?;
+
+    Ok(Bytecode::new(compiler.data.into_iter().collect()))
+}
+
+/// Lays a data declaration out as words.
+///
+/// Structs take a word per field, in the order the struct declares them, with
+/// floats stored as they are in memory rather than as arguments. Anything else
+/// is encoded as an argument, since that is how buffers read tables.
+pub fn compile_data(declaration: &Declaration, scope: &Scope) -> Result<Vec<u32>, Error> {
+    let items = match &declaration.kind {
+        DeclarationKind::Data { items, .. } => items,
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::NotData,
+                span: declaration.span,
+            })
+        }
+    };
+
+    let mut compiler = Compiler::new(scope, HashSet::new(), HashSet::new());
+    let mut words = Vec::new();
+
+    for item in items.iter() {
+        match &item.kind {
+            ExpressionKind::StructLiteral { name, fields } => {
+                compiler
+                    .struct_literal(name, fields, &mut words)
+                    .map_err(|error| error.or_span(item.span))?;
+            }
+            _ => words.push(compiler.expression(item)?.0),
+        }
+    }
+
+    Ok(words)
+}
+
+/// A compile error, and the span of source it was caused by, if known.
+#[derive(Debug, Fail)]
+#[fail(display = "{}", kind)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "only functions can be compiled")]
+    NotFun,
+
+    #[fail(display = "only data can be laid out")]
+    NotData,
+
+    #[fail(display = "unknown identifier '{}'", _0)]
+    UnknownIdentifier(String),
+
+    #[fail(display = "unknown struct '{}'", _0)]
+    UnknownStruct(String),
+
+    #[fail(display = "struct '{}' has no field '{}'", _0, _1)]
+    UnknownField(String, String),
+
+    #[fail(display = "missing field '{}' of struct '{}'", _1, _0)]
+    MissingField(String, String),
+
+    #[fail(display = "unknown label '{}'", _0)]
+    UnknownLabel(String),
+
+    #[fail(display = "label '{}' is defined more than once", _0)]
+    DuplicateLabel(String),
+
+    #[fail(display = "'{}' cannot be encoded as an argument", _0)]
+    BadExpression(String),
+
+    #[fail(display = "'{}' cannot be used as a condition", _0)]
+    BadCondition(String),
+
+    #[fail(display = "'{}' is not a variable", _0)]
+    NotVariable(String),
+
+    #[fail(display = "operator {:?} cannot be used here", _0)]
+    BadOperator(Operator),
+
+    #[fail(display = "cannot tell whether '{}' is an int or a float", _0)]
+    AmbiguousType(String),
+
+    #[fail(display = "too many local {}s", _0)]
+    TooManyLocals(DataType),
+
+    #[fail(display = "label {} is out of range", _0)]
+    LabelOutOfRange(i32),
+
+    #[fail(display = "too many labels")]
+    TooManyLabels,
+}
+
+impl Error {
+    /// Gives the error a span, unless it already has a more specific one.
+    fn or_span(mut self, span: Option<Span>) -> Error {
+        if self.span.is_none() {
+            self.span = span;
+        }
+        self
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error { kind, span: None }
+    }
+}
+
+struct Compiler<'a> {
+    scope: &'a Scope,
+    data: Vec<Operation>,
+
+    /// Slot and datatype of each named local, and the datatype of each
+    /// variable named by number that has been declared.
+    locals: HashMap<String, (Arg, DataType)>,
+
+    /// FunWords/FunFlags and labels that the function refers to by number.
+    numbered_vars: HashSet<Arg>,
+    numbered_labels: HashSet<i32>,
+
+    /// Label numbers, which labels have been defined, and where each is
+    /// jumped to from.
+    labels: HashMap<String, i32>,
+    defined_labels: HashSet<String>,
+    gotos: Vec<(String, Option<Span>)>,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(
+        scope: &'a Scope,
+        numbered_vars: HashSet<Arg>,
+        numbered_labels: HashSet<i32>,
+    ) -> Compiler<'a> {
+        Compiler {
+            scope,
+            data: Vec::new(),
+            locals: HashMap::new(),
+            numbered_vars,
+            numbered_labels,
+            labels: HashMap::new(),
+            defined_labels: HashSet::new(),
+            gotos: Vec::new(),
+        }
+    }
+
+    fn fun(
+        &mut self,
+        arguments: &[(Identifier, DataType)],
+        block: &[Statement],
+    ) -> Result<(), Error> {
+        // Arguments are passed in word_0, word_1, etc.
+        for (n, (Identifier(name), datatype)) in arguments.iter().enumerate() {
+            let arg = encode_var(FUNWORD_STR, n as u32);
+
+            if assemble_arg(name).is_none() {
+                self.locals.insert(name.clone(), (arg, datatype.clone()));
+            }
+            self.numbered_vars.insert(arg);
+        }
+
+        self.block(block)?;
+        self.push(Opcode::End, vec![]);
+
+        // Every label jumped to must exist.
+        for (name, span) in self.gotos.iter() {
+            if !self.defined_labels.contains(name) {
+                return Err(Error {
+                    kind: ErrorKind::UnknownLabel(name.clone()),
+                    span: *span,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, opcode: Opcode, args: Vec<Arg>) {
+        self.data.push((opcode, args));
+    }
+
+    fn block(&mut self, block: &[Statement]) -> Result<(), Error> {
+        for stmt in block.iter() {
+            self.statement(stmt)?;
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Result<(), Error> {
+        self.lower(stmt).map_err(|error| error.or_span(stmt.span))
+    }
+
+    fn lower(&mut self, stmt: &Statement) -> Result<(), Error> {
+        match &stmt.kind {
+            StatementKind::Return => self.push(Opcode::Return, vec![]),
+
+            StatementKind::Label { name } => {
+                if !self.defined_labels.insert(name.clone()) {
+                    return Err(ErrorKind::DuplicateLabel(name.clone()).into());
+                }
+
+                let label = self.label(name)?;
+                self.push(Opcode::Label, vec![label]);
+            }
+            StatementKind::Goto { label_name } => {
+                self.gotos.push((label_name.clone(), stmt.span));
+
+                let label = self.label(label_name)?;
+                self.push(Opcode::Goto, vec![label]);
+            }
+
+            StatementKind::VarAssign {
+                identifier,
+                expression,
+            } => self.assign(identifier, &expression.borrow())?,
+
+            StatementKind::VarOpAssign {
+                identifier,
+                op,
+                expression,
+                datatype,
+            } => {
+                let expression = expression.borrow();
+                let var = self.variable(identifier)?;
+                let value = self.expression(&expression)?;
+
+                // As with assignment, the operation is a float one if either
+                // side is a float.
+                let datatype = match datatype {
+                    DataType::Any => match (
+                        self.variable_datatype(identifier),
+                        self.datatype(&expression),
+                    ) {
+                        (DataType::Float, _) | (_, DataType::Float) => DataType::Float,
+                        (DataType::Any, DataType::Any) => match op {
+                            Operator::Add | Operator::Sub | Operator::Mul | Operator::Div => {
+                                let Identifier(name) = identifier;
+                                return Err(ErrorKind::AmbiguousType(name.clone()).into());
+                            }
+                            _ => DataType::Int,
+                        },
+                        _ => DataType::Int,
+                    },
+                    datatype => datatype.clone(),
+                };
+
+                let opcode = match (op, datatype) {
+                    (Operator::Add, DataType::Float) => Opcode::AddFloat,
+                    (Operator::Sub, DataType::Float) => Opcode::SubFloat,
+                    (Operator::Mul, DataType::Float) => Opcode::MulFloat,
+                    (Operator::Div, DataType::Float) => Opcode::DivFloat,
+                    (Operator::Mod, DataType::Float)
+                    | (Operator::BitAnd, DataType::Float)
+                    | (Operator::BitOr, DataType::Float) => {
+                        return Err(ErrorKind::BadOperator(op.clone()).into())
+                    }
+                    (Operator::Add, _) => Opcode::AddInt,
+                    (Operator::Sub, _) => Opcode::SubInt,
+                    (Operator::Mul, _) => Opcode::MulInt,
+                    (Operator::Div, _) => Opcode::DivInt,
+                    (Operator::Mod, _) => Opcode::ModInt,
+                    (Operator::BitAnd, _) => Opcode::And,
+                    (Operator::BitOr, _) => Opcode::Or,
+                    (op, _) => return Err(ErrorKind::BadOperator(op.clone()).into()),
+                };
+
+                self.push(opcode, vec![var, value]);
+            }
+
+            StatementKind::VarDeclare {
+                datatype,
+                identifier,
+                expression,
+            } => {
+                let Identifier(name) = identifier;
+
+                let datatype = match datatype.borrow().clone() {
+                    DataType::Any => match expression {
+                        Some(expression) => self.datatype(&expression.borrow()),
+                        None => DataType::Any,
+                    },
+                    datatype => datatype,
+                };
+
+                // Variables named by number need no slot, only their type.
+                match assemble_arg(name) {
+                    Some(arg) => {
+                        self.numbered_vars.insert(arg);
+                        self.locals.insert(name.clone(), (arg, datatype));
+                    }
+                    None => {
+                        let slot = self.allocate(&datatype)?;
+                        self.locals.insert(name.clone(), (slot, datatype));
+                    }
+                }
+
+                if let Some(expression) = expression {
+                    self.assign(identifier, &expression.borrow())?;
+                }
+            }
+
+            StatementKind::MethodCall {
+                method,
+                arguments,
+                threading,
+            } => {
+                let scope = self.scope;
+                let (ptr, datatype) = match method {
+                    IdentifierOrPointer::Pointer(ptr) => (
+                        *ptr,
+                        scope
+                            .lookup_ptr(*ptr)
+                            .and_then(|name| scope.lookup_name(name)),
+                    ),
+                    IdentifierOrPointer::Identifier(Identifier(name)) => (
+                        scope
+                            .lookup_name_ptr(name)
+                            .ok_or_else(|| ErrorKind::UnknownIdentifier(name.clone()))?,
+                        scope.lookup_name(name),
+                    ),
+                };
+                let ptr = Arg(ptr);
+
+                let is_fun = match (datatype, threading) {
+                    (Some(DataType::Fun(_)), _) => true,
+                    (_, MethodThreading::No) => false,
+                    (Some(DataType::Asm(_)), _) => {
+                        return Err(ErrorKind::BadExpression(method.clone().unparse(scope)).into())
+                    }
+
+                    // Only funs can be run as threads, so whatever is must be one.
+                    _ => true,
+                };
+
+                if is_fun {
+                    // Functions take their arguments from the caller's words.
+                    for (n, argument) in arguments.iter().enumerate() {
+                        let argument = argument.borrow();
+                        let var = encode_var(FUNWORD_STR, n as u32);
+                        let value = self.expression(&argument)?;
+
+                        // Locals mustn't be allocated the words being passed.
+                        self.numbered_vars.insert(var);
+
+                        if var != value {
+                            let opcode = match self.datatype(&argument) {
+                                DataType::Float => Opcode::SetFloat,
+                                _ => Opcode::SetInt,
+                            };
+                            self.push(opcode, vec![var, value]);
+                        }
+                    }
+
+                    match threading {
+                        MethodThreading::No => self.push(Opcode::ExecWait, vec![ptr]),
+                        MethodThreading::Yes => self.push(Opcode::Exec, vec![ptr]),
+                        MethodThreading::Assign(identifier) => {
+                            let var = self.variable(identifier)?;
+                            self.push(Opcode::ExecRet, vec![ptr, var]);
+                        }
+                    }
+                } else {
+                    let mut args = vec![ptr];
+                    for argument in arguments.iter() {
+                        args.push(self.expression(&argument.borrow())?);
+                    }
+
+                    self.push(Opcode::Call, args);
+                }
+            }
+
+            StatementKind::Wait { time, unit } => {
+                let time = self.expression(time)?;
+
+                match unit {
+                    TimeUnit::Frames => self.push(Opcode::Wait, vec![time]),
+                    TimeUnit::Seconds => self.push(Opcode::WaitSeconds, vec![time]),
+                }
+            }
+
+            StatementKind::If {
+                condition,
+                block_true,
+                block_false,
+            } => {
+                let (opcode, args) = self.condition(condition)?;
+                self.push(opcode, args);
+
+                self.block(block_true)?;
+
+                if !block_false.is_empty() {
+                    self.push(Opcode::Else, vec![]);
+                    self.block(block_false)?;
+                }
+
+                self.push(Opcode::EndIf, vec![]);
+            }
+
+            StatementKind::Switch { expression, cases } => {
+                match &expression.kind {
+                    ExpressionKind::Const(value) => {
+                        let value = self.expression(value)?;
+                        self.push(Opcode::SwitchConst, vec![value]);
+                    }
+                    _ => {
+                        let value = self.expression(expression)?;
+                        self.push(Opcode::Switch, vec![value]);
+                    }
+                }
+
+                for (case, block) in cases.iter() {
+                    match case {
+                        Case::Default => self.push(Opcode::CaseDefault, vec![]),
+                        Case::Test { operator, against } => {
+                            let opcode = match operator {
+                                Operator::Eq => Opcode::CaseEq,
+                                Operator::Ne => Opcode::CaseNe,
+                                Operator::Lt => Opcode::CaseLt,
+                                Operator::Gt => Opcode::CaseGt,
+                                Operator::Lte => Opcode::CaseLte,
+                                Operator::Gte => Opcode::CaseGte,
+                                Operator::BitAndZ => Opcode::CaseAndZ,
+                                op => return Err(ErrorKind::BadOperator(op.clone()).into()),
+                            };
+
+                            let against = self.expression(against)?;
+                            self.push(opcode, vec![against]);
+                        }
+                        Case::Range { low, high } => {
+                            let low = self.expression(low)?;
+                            let high = self.expression(high)?;
+                            self.push(Opcode::CaseRange, vec![low, high]);
+                        }
+                        Case::AnyOf(values) | Case::AllOf(values) => {
+                            let opcode = match case {
+                                Case::AllOf(_) => Opcode::CaseAndEq,
+                                _ => Opcode::CaseOrEq,
+                            };
+
+                            for value in values.iter() {
+                                let value = self.expression(value)?;
+                                self.push(opcode, vec![value]);
+                            }
+                        }
+                    }
+
+                    self.block(block)?;
+
+                    if let Case::AnyOf(_) | Case::AllOf(_) = case {
+                        self.push(Opcode::EndCaseGroup, vec![]);
+                    }
+                }
+
+                self.push(Opcode::EndSwitch, vec![]);
+            }
+
+            StatementKind::Loop { count, block } => {
+                let count = match count {
+                    Some(count) => self.expression(count)?,
+                    None => Arg(0), // Forever
+                };
+
+                self.push(Opcode::Loop, vec![count]);
+                self.block(block)?;
+                self.push(Opcode::EndLoop, vec![]);
+            }
+            StatementKind::BreakLoop => self.push(Opcode::BreakLoop, vec![]),
+            StatementKind::BreakCase => self.push(Opcode::BreakCase, vec![]),
+
+            StatementKind::UseBuffer { datatype, buffer } => {
+                let buffer = self.expression(buffer)?;
+
+                match datatype {
+                    DataType::Float => self.push(Opcode::UseFloatBuffer, vec![buffer]),
+                    _ => self.push(Opcode::UseIntBuffer, vec![buffer]),
+                }
+            }
+            StatementKind::BufferRead {
+                identifiers,
+                datatype,
+            } => {
+                // Reads are sequential, so long lists can be split up.
+                for chunk in identifiers.chunks(4) {
+                    let opcode = match (chunk.len(), datatype) {
+                        (1, DataType::Float) => Opcode::Get1Float,
+                        (2, DataType::Float) => Opcode::Get2Float,
+                        (3, DataType::Float) => Opcode::Get3Float,
+                        (_, DataType::Float) => Opcode::Get4Float,
+                        (1, _) => Opcode::Get1Int,
+                        (2, _) => Opcode::Get2Int,
+                        (3, _) => Opcode::Get3Int,
+                        (_, _) => Opcode::Get4Int,
+                    };
+
+                    let mut args = Vec::with_capacity(chunk.len());
+                    for identifier in chunk.iter() {
+                        args.push(self.variable(identifier)?);
+                    }
+
+                    self.push(opcode, args);
+                }
+            }
+            StatementKind::BufferReadIndex {
+                identifier,
+                index,
+                datatype,
+            } => {
+                let var = self.variable(identifier)?;
+                let index = self.expression(index)?;
+
+                match datatype {
+                    DataType::Float => self.push(Opcode::GetFloatN, vec![var, index]),
+                    _ => self.push(Opcode::GetIntN, vec![var, index]),
+                }
+            }
+
+            StatementKind::UseArray { kind, array } => {
+                let array = self.expression(array)?;
+
+                match kind {
+                    ArrayKind::Words => self.push(Opcode::UseArray, vec![array]),
+                    ArrayKind::Flags => self.push(Opcode::UseFlagArray, vec![array]),
+                }
+            }
+            StatementKind::ArrayDeclare { identifier, size } => {
+                let size = self.expression(size)?;
+                let var = self.variable(identifier)?;
+                self.push(Opcode::AllocArray, vec![size, var]);
+            }
+
+            StatementKind::Bind {
+                script,
+                trigger,
+                target,
+                items,
+                prompt,
+                handle,
+            } => {
+                let script = self.expression(script)?;
+                let trigger = self.expression(trigger)?;
+                let target = self.expression(target)?;
+                let prompt = self.expression(prompt)?;
+                let handle = match handle {
+                    Some(handle) => self.variable(handle)?,
+                    None => Arg(0),
+                };
+
+                match items {
+                    Some(items) => {
+                        let items = self.expression(items)?;
+                        self.push(
+                            Opcode::BindLock,
+                            vec![script, trigger, target, items, handle, prompt],
+                        );
+                    }
+                    None => self.push(Opcode::Bind, vec![script, trigger, target, prompt, handle]),
+                }
+            }
+            StatementKind::Unbind => self.push(Opcode::Unbind, vec![]),
+
+            StatementKind::Kill { script } => {
+                let script = self.expression(script)?;
+                self.push(Opcode::Kill, vec![script]);
+            }
+            StatementKind::Jump { script } => {
+                let script = self.expression(script)?;
+                self.push(Opcode::Jump, vec![script]);
+            }
+
+            StatementKind::Priority { priority } => {
+                let priority = self.expression(priority)?;
+                self.push(Opcode::SetPriority, vec![priority]);
+            }
+            StatementKind::Timescale { timescale } => {
+                let timescale = self.expression(timescale)?;
+                self.push(Opcode::SetTimescale, vec![timescale]);
+            }
+            StatementKind::Group { group } => {
+                let group = self.expression(group)?;
+                self.push(Opcode::SetSuspensionGroup, vec![group]);
+            }
+
+            StatementKind::Suspend { target } | StatementKind::Resume { target } => {
+                let suspend = match stmt.kind {
+                    StatementKind::Suspend { .. } => true,
+                    _ => false,
+                };
+
+                let (opcode, value) = match (target, suspend) {
+                    (ScriptTarget::Script(script), true) => (Opcode::Suspend, script),
+                    (ScriptTarget::Group(group), true) => (Opcode::SuspendAll, group),
+                    (ScriptTarget::Others(group), true) => (Opcode::SuspendOthers, group),
+                    (ScriptTarget::Script(script), false) => (Opcode::Resume, script),
+                    (ScriptTarget::Group(group), false) => (Opcode::ResumeAll, group),
+                    (ScriptTarget::Others(group), false) => (Opcode::ResumeOthers, group),
+                };
+
+                let value = self.expression(value)?;
+                self.push(opcode, vec![value]);
+            }
+
+            StatementKind::ScriptExists { identifier, script } => {
+                let script = self.expression(script)?;
+                let var = self.variable(identifier)?;
+                self.push(Opcode::DoesScriptExist, vec![script, var]);
+            }
+
+            StatementKind::Thread { kind, block } => {
+                let (start, end) = match kind {
+                    ThreadKind::Detached => (Opcode::Thread, Opcode::EndThread),
+                    ThreadKind::Child => (Opcode::ChildThread, Opcode::EndChildThread),
+                };
+
+                self.push(start, vec![]);
+                self.block(block)?;
+                self.push(end, vec![]);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn assign(&mut self, identifier: &Identifier, expression: &Expression) -> Result<(), Error> {
+        let var = self.variable(identifier)?;
+
+        if let ExpressionKind::Const(value) = &expression.kind {
+            let value = self.expression(value)?;
+            self.push(Opcode::SetRef, vec![var, value]);
+            return Ok(());
+        }
+
+        let value = self.expression(expression)?;
+
+        let opcode = match (
+            self.variable_datatype(identifier),
+            self.datatype(expression),
+        ) {
+            (DataType::Float, _) | (_, DataType::Float) => Opcode::SetFloat,
+            _ => Opcode::SetInt,
+        };
+
+        self.push(opcode, vec![var, value]);
+        Ok(())
+    }
+
+    /// Turns a condition into an If opcode and its arguments.
+    fn condition(&mut self, condition: &Expression) -> Result<(Opcode, Vec<Arg>), Error> {
+        match &condition.kind {
+            ExpressionKind::Operation { lhs, op, rhs } => {
+                let opcode = match op {
+                    Operator::Eq => Opcode::IfEq,
+                    Operator::Ne => Opcode::IfNe,
+                    Operator::Lt => Opcode::IfLt,
+                    Operator::Gt => Opcode::IfGt,
+                    Operator::Lte => Opcode::IfLte,
+                    Operator::Gte => Opcode::IfGte,
+                    Operator::BitAndNz => Opcode::IfAndNz,
+                    Operator::BitAndZ => Opcode::IfAndZ,
+                    _ => {
+                        return Err(Error {
+                            kind: ErrorKind::BadCondition(condition.clone().unparse(self.scope)),
+                            span: condition.span,
+                        })
+                    }
+                };
+
+                Ok((opcode, vec![self.expression(lhs)?, self.expression(rhs)?]))
+            }
+
+            // `if flag` is `if flag != false`.
+            _ => Ok((Opcode::IfNe, vec![self.expression(condition)?, Arg(0)])),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<Arg, Error> {
+        self.encode(expression)
+            .map_err(|error| error.or_span(expression.span))
+    }
+
+    /// Encodes an expression as a single argument.
+    fn encode(&mut self, expression: &Expression) -> Result<Arg, Error> {
+        let bad_expression = || {
+            Error::from(ErrorKind::BadExpression(
+                expression.clone().unparse(self.scope),
+            ))
+        };
+
+        let (arg, expected_kind) = match &expression.kind {
+            ExpressionKind::LiteralInt(int) => (Arg(*int), ArgKind::Int),
+            ExpressionKind::LiteralBool(bool) => (Arg(*bool as u32), ArgKind::Int),
+            ExpressionKind::LiteralFloat(float) => {
+                let fixed = (f64::from(*float) * 1024.0).round() as i64 - 230000000;
+
+                if fixed < i64::from(i32::min_value()) || fixed > i64::from(i32::max_value()) {
+                    return Err(bad_expression());
+                }
+
+                (Arg(fixed as i32 as u32), ArgKind::Float)
+            }
+
+            ExpressionKind::Identifier(identifier) => return self.variable(identifier),
+
+            ExpressionKind::ArrayIndex(Identifier(name), index) => {
+                let arg =
+                    assemble_arg(&format!("{}[{}]", name, index)).ok_or_else(bad_expression)?;
+                return Ok(arg);
+            }
+
+            ExpressionKind::Variant {
+                enum_name: Identifier(enum_name),
+                variant: Identifier(variant),
+            } => {
+                let value = self
+                    .scope
+                    .lookup_type(enum_name)
+                    .and_then(|definition| definition.variant_value(variant))
+                    .ok_or_else(|| {
+                        ErrorKind::UnknownIdentifier(format!("{}::{}", enum_name, variant))
+                    })?;
+
+                (Arg(value), ArgKind::Int)
+            }
+
+            // Structs are only found in data, which is pointed to, and only
+            // assignment and `switch` take an encoded argument as it is.
+            ExpressionKind::StructLiteral { .. }
+            | ExpressionKind::Operation { .. }
+            | ExpressionKind::Const(_) => return Err(bad_expression()),
+        };
+
+        // Literals that fall in the range of some other kind of arg would be
+        // read as that instead.
+        if arg.kind() == expected_kind {
+            Ok(arg)
+        } else {
+            Err(bad_expression())
+        }
+    }
+
+    /// Lays out a struct literal a word per field, in the struct's order.
+    fn struct_literal(
+        &mut self,
+        Identifier(name): &Identifier,
+        fields: &[(Identifier, Expression)],
+        words: &mut Vec<u32>,
+    ) -> Result<(), Error> {
+        let definition = match self.scope.lookup_type(name) {
+            Some(TypeDefinition::Struct(definition)) => definition,
+            _ => return Err(ErrorKind::UnknownStruct(name.clone()).into()),
+        };
+
+        for (Identifier(field), value) in fields.iter() {
+            if !definition.iter().any(|(declared, _)| declared == field) {
+                return Err(Error {
+                    kind: ErrorKind::UnknownField(name.clone(), field.clone()),
+                    span: value.span,
+                });
+            }
+        }
+
+        for (field, datatype) in definition.iter() {
+            let value = fields
+                .iter()
+                .find(|(Identifier(name), _)| name == field)
+                .map(|(_, value)| value)
+                .ok_or_else(|| ErrorKind::MissingField(name.clone(), field.clone()))?;
+
+            let word = match (datatype, &value.kind) {
+                (DataType::Float, ExpressionKind::LiteralFloat(float)) => float.to_bits(),
+                (DataType::Float, ExpressionKind::LiteralInt(int)) => {
+                    (*int as i32 as f32).to_bits()
+                }
+                (DataType::Float, _) | (_, ExpressionKind::LiteralFloat(_)) => {
+                    return Err(Error {
+                        kind: ErrorKind::BadExpression(value.clone().unparse(self.scope)),
+                        span: value.span,
+                    })
+                }
+                _ => self.expression(value)?.0,
+            };
+
+            words.push(word);
+        }
+
+        Ok(())
+    }
+
+    /// Encodes an identifier: a variable named by number, a local, or a named
+    /// pointer.
+    fn variable(&mut self, identifier: &Identifier) -> Result<Arg, Error> {
+        let Identifier(name) = identifier;
+
+        if let Some(arg) = assemble_arg(name) {
+            match arg.kind() {
+                ArgKind::Int | ArgKind::Float => {
+                    return Err(ErrorKind::NotVariable(name.clone()).into())
+                }
+                _ => (),
+            }
+
+            self.numbered_vars.insert(arg);
+            return Ok(arg);
+        }
+
+        if let Some((arg, _)) = self.locals.get(name) {
+            return Ok(*arg);
+        }
+
+        if let Some(ptr) = self.scope.lookup_name_ptr(name) {
+            return Ok(Arg(ptr));
+        }
+
+        Err(ErrorKind::UnknownIdentifier(name.clone()).into())
+    }
+
+    fn datatype(&self, expression: &Expression) -> DataType {
+        match &expression.kind {
+            ExpressionKind::Identifier(identifier) => self.variable_datatype(identifier),
+            _ => expression.infer_datatype(self.scope),
+        }
+    }
+
+    fn variable_datatype(&self, Identifier(name): &Identifier) -> DataType {
+        match (self.locals.get(name), self.scope.lookup_name(name)) {
+            (Some((_, datatype)), _) => datatype.clone(),
+            (None, Some(datatype)) => datatype.clone(),
+            (None, None) => DataType::Any,
+        }
+    }
+
+    /// Finds a free FunFlag (for bools) or FunWord (for anything else).
+    fn allocate(&mut self, datatype: &DataType) -> Result<Arg, Error> {
+        let (name, count) = match datatype {
+            DataType::Bool => (FUNFLAG_STR, FUNFLAG_COUNT),
+            _ => (FUNWORD_STR, FUNWORD_COUNT),
+        };
+
+        let taken: HashSet<Arg> = self.locals.values().map(|(arg, _)| *arg).collect();
+
+        for n in 0..count {
+            let arg = encode_var(name, n);
+
+            if !taken.contains(&arg) && !self.numbered_vars.contains(&arg) {
+                return Ok(arg);
+            }
+        }
+
+        Err(ErrorKind::TooManyLocals(datatype.clone()).into())
+    }
+
+    /// Labels named by number keep it; others are given one that is unused.
+    fn label(&mut self, name: &str) -> Result<Arg, Error> {
+        if let Ok(number) = name.parse::<i32>() {
+            if number < 0 || number >= LABEL_COUNT {
+                return Err(ErrorKind::LabelOutOfRange(number).into());
+            }
+
+            self.numbered_labels.insert(number);
+            return Ok(Arg(number as u32));
+        }
+
+        if let Some(number) = self.labels.get(name) {
+            return Ok(Arg(*number as u32));
+        }
+
+        let number = (0..LABEL_COUNT)
+            .find(|n| !self.numbered_labels.contains(n) && !self.labels.values().any(|m| m == n))
+            .ok_or(ErrorKind::TooManyLabels)?;
+
+        self.labels.insert(name.to_string(), number);
+        Ok(Arg(number as u32))
+    }
+}
+
+fn encode_var(name: &str, n: u32) -> Arg {
+    assemble_arg(&format!("{}_{:X}", name, n)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::area::AreaTable;
+    use crate::rom::{test_rom, Region, RomRead};
+    use crate::script::api::Api;
+    use crate::script::parse::parse_script;
+
+    /// Compiles the only function in `source`, with `helper(int, float)` in
+    /// scope, and checks that it assembles to `asm`.
+    fn assert_compiles(source: &str, asm: &str) {
+        let mut scope = Scope::new();
+        scope.insert_ptr(
+            0x8024_0000,
+            "helper".to_string(),
+            DataType::Fun(vec![DataType::Int, DataType::Float]),
+        );
+
+        let script = parse_script(source).unwrap();
+        let bc = compile_fun(&script.0[0], &scope).unwrap();
+
+        assert_eq!(
+            bc.disassemble(),
+            Bytecode::assemble(asm).unwrap().disassemble()
+        );
+    }
+
+    fn compile_error(source: &str) -> ErrorKind {
+        let script = parse_script(source).unwrap();
+        compile_fun(&script.0[0], &Scope::new()).unwrap_err().kind
+    }
+
+    #[test]
+    fn if_else() {
+        assert_compiles(
+            "fun test(x: int) {
+                if x == 1 {
+                    x = 2
+                } else {
+                    x += 3
+                }
+            }",
+            "
+            IfEq word_0, 1
+                SetInt word_0, 2
+            Else
+                AddInt word_0, 3
+            EndIf
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn switch() {
+        assert_compiles(
+            "fun test() {
+                switch word_2 {
+                    case == 1 {
+                        word_3 = 1
+                    }
+                    case 2..4 {
+                        break case
+                    }
+                    default {
+                    }
+                }
+            }",
+            "
+            Switch word_2
+                CaseEq 1
+                    SetInt word_3, 1
+                CaseRange 2, 4
+                    BreakCase
+                CaseDefault
+            EndSwitch
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn loops() {
+        assert_compiles(
+            "fun test() {
+                loop 5 {
+                    wait 1
+                }
+                loop {
+                    break
+                }
+            }",
+            "
+            Loop 5
+                Wait 1
+            EndLoop
+            Loop 0
+                BreakLoop
+            EndLoop
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn var_declare() {
+        // Locals keep out of the way of words used by number.
+        assert_compiles(
+            "fun test() {
+                word_0 = 1
+                var a = 2
+                var b: float = 1.5
+                var c: bool = true
+            }",
+            "
+            SetInt word_0, 1
+            SetInt word_1, 2
+            SetFloat word_2, 1.5
+            SetInt flag_0, 1
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn call_with_args() {
+        // The words that arguments are passed in aren't given to locals, and
+        // floats are passed with SetFloat.
+        assert_compiles(
+            "fun test() {
+                var a = 7
+                var b: float = 2.5
+                helper(a, b)
+                helper(1, 0.5)
+            }",
+            "
+            SetInt word_2, 7
+            SetFloat word_3, 2.5
+            SetInt word_0, word_2
+            SetFloat word_1, word_3
+            ExecWait 0x80240000
+            SetInt word_0, 1
+            SetFloat word_1, 0.5
+            ExecWait 0x80240000
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn op_assign() {
+        // Like assignment, operations are float ones if either side is a
+        // float, including variables named by number that are declared so.
+        assert_compiles(
+            "fun test(x: float) {
+                x += 1
+                var word_3: float
+                word_3 *= 2
+                word_4 -= 0.5
+                word_5 += 1
+                word_5 &= word_6
+            }",
+            "
+            AddFloat word_0, 1
+            MulFloat word_3, 2
+            SubFloat word_4, 0.5
+            AddInt word_5, 1
+            And word_5, word_6
+            End
+            ",
+        );
+
+        match compile_error("fun test() {\n word_0 += word_1\n }") {
+            ErrorKind::AmbiguousType(ref name) if name == "word_0" => (),
+            kind => panic!("expected AmbiguousType, got {:?}", kind),
+        }
+
+        match compile_error("fun test() {\n word_0 %= 1.5\n }") {
+            ErrorKind::BadOperator(Operator::Mod) => (),
+            kind => panic!("expected BadOperator, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn threads() {
+        // Only funs can be run as threads, so whatever is is one.
+        assert_compiles(
+            "fun test() {
+                thread 0x80240100()
+                word_2 = thread 0x80240100()
+                helper(1, 2.0)
+            }",
+            "
+            Exec 0x80240100
+            ExecRet 0x80240100, word_2
+            SetInt word_0, 1
+            SetFloat word_1, 2.0
+            ExecWait 0x80240000
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn constants() {
+        assert_compiles(
+            "fun test() {
+                word_0 = const mapword_1
+                var x = const 5
+                switch const word_0 {
+                    case == 1 {
+                    }
+                }
+            }",
+            "
+            SetRef word_0, mapword_1
+            SetRef word_1, 5
+            SwitchConst word_0
+                CaseEq 1
+            EndSwitch
+            End
+            ",
+        );
+
+        match compile_error("fun test() {\n wait const 1\n }") {
+            ErrorKind::BadExpression(_) => (),
+            kind => panic!("expected BadExpression, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn data() {
+        let mut scope = Scope::new();
+        Api::bundled().declare(&mut scope, Region::America);
+
+        let layout = |source: &str| {
+            let script = parse_script(source).unwrap();
+            compile_data(&script.0[0], &scope)
+        };
+
+        // Tables are encoded as arguments, structs as they are in memory.
+        assert_eq!(
+            layout("data table: [float] = [1.5, 2.0]").unwrap(),
+            vec![
+                assemble_arg("1.5").unwrap().0,
+                assemble_arg("2.0").unwrap().0,
+            ]
+        );
+
+        assert_eq!(
+            layout(concat!(
+                "data entrances: [Entrance] = [",
+                "Entrance { x: -10.0, y: 0.0, z: 25.5, angle: 90 }, ",
+                "Entrance { angle: 270.0, z: 0.0, y: 1.0, x: 2.0 }]",
+            ))
+            .unwrap(),
+            vec![
+                (-10.0f32).to_bits(),
+                0.0f32.to_bits(),
+                25.5f32.to_bits(),
+                90.0f32.to_bits(),
+                2.0f32.to_bits(),
+                1.0f32.to_bits(),
+                0.0f32.to_bits(),
+                270.0f32.to_bits(),
+            ]
+        );
+
+        match layout("data e: [Entrance] = [Entrance { x: 0.0, y: 0.0, z: 0.0 }]") {
+            Err(Error {
+                kind: ErrorKind::MissingField(_, ref field),
+                ..
+            }) if field == "angle" => (),
+            result => panic!("expected MissingField, got {:?}", result),
+        }
+
+        match layout(
+            "data e: [Entrance] = [Entrance { x: 0.0, y: 0.0, z: 0.0, angle: 0.0, w: 0.0 }]",
+        ) {
+            Err(Error {
+                kind: ErrorKind::UnknownField(_, ref field),
+                ..
+            }) if field == "w" => (),
+            result => panic!("expected UnknownField, got {:?}", result),
+        }
+
+        match layout("fun test() {}") {
+            Err(Error {
+                kind: ErrorKind::NotData,
+                ..
+            }) => (),
+            result => panic!("expected NotData, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn goto_label() {
+        assert_compiles(
+            "fun test() {
+                label .3
+                label .start
+                goto .start
+                goto .3
+            }",
+            "
+            Label 3
+            Label 0
+            Goto 0
+            Goto 3
+            End
+            ",
+        );
+    }
+
+    #[test]
+    fn label_limit() {
+        match compile_error("fun test() {\n label .16\n }") {
+            ErrorKind::LabelOutOfRange(16) => (),
+            kind => panic!("expected LabelOutOfRange, got {:?}", kind),
+        }
+
+        let labels: String = (0..17).map(|n| format!("label .l{}\n", n)).collect();
+        match compile_error(&format!("fun test() {{\n{}}}", labels)) {
+            ErrorKind::TooManyLabels => (),
+            kind => panic!("expected TooManyLabels, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn real_script() {
+        let mut rom = test_rom();
+        let map = AreaTable::read(&mut rom).unwrap().areas[0].maps[0].clone();
+
+        let mut scope = Scope::new();
+        Api::bundled().declare(&mut scope, rom.region);
+
+        let loc = map.main_fun(&mut rom).unwrap();
+        let bc = Bytecode::read(&mut rom, loc).unwrap();
+
+        let source = Declaration::from(DeclarationKind::Fun {
+            name: IdentifierOrPointer::Identifier(Identifier("main".to_string())),
+            arguments: Vec::new(),
+            block: bc.clone().decompile(&mut scope).unwrap(),
+        })
+        .unparse(&scope);
+
+        let script = parse_script(&source).unwrap();
+        let recompiled = compile_fun(&script.0[0], &scope).unwrap();
+
+        assert_eq!(recompiled.disassemble(), bc.disassemble());
+    }
+}
@@ -1,7 +1,7 @@
-use std::fmt::{self, Display, Formatter};
 use itertools::Itertools;
+use std::fmt::{self, Display, Formatter};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DataType {
     Any,
     Int,
@@ -10,27 +10,58 @@ pub enum DataType {
     Arr(Box<DataType>),
     Fun(Vec<DataType>),
     Asm(Vec<DataType>),
-    // TODO: custom structs and enums
+
+    /// An enum or struct, defined elsewhere by a `TypeDefinition`. Enums are
+    /// passed around as their value, and structs by pointer.
+    Named(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeDefinition {
+    Enum(Vec<(String, u32)>),
+    Struct(Vec<(String, DataType)>),
+}
+
+impl TypeDefinition {
+    /// The value of an enum's variant.
+    pub fn variant_value(&self, name: &str) -> Option<u32> {
+        match self {
+            TypeDefinition::Enum(variants) => variants
+                .iter()
+                .find(|(variant, _)| variant == name)
+                .map(|(_, value)| *value),
+            TypeDefinition::Struct(_) => None,
+        }
+    }
+
+    /// The name of the enum variant with the given value, if any.
+    pub fn variant_name(&self, value: u32) -> Option<&str> {
+        match self {
+            TypeDefinition::Enum(variants) => variants
+                .iter()
+                .find(|(_, v)| *v == value)
+                .map(|(variant, _)| variant.as_str()),
+            TypeDefinition::Struct(_) => None,
+        }
+    }
 }
 
 impl Display for DataType {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         use DataType::*;
         match self {
-            Any       => write!(f, "any"),
-            Int       => write!(f, "int"),
-            Float     => write!(f, "float"),
-            Bool      => write!(f, "bool"),
+            Any => write!(f, "any"),
+            Int => write!(f, "int"),
+            Float => write!(f, "float"),
+            Bool => write!(f, "bool"),
             Arr(item) => write!(f, "[{}]", item),
             Fun(args) => write!(f, "fun({})", join(args, ", ")),
             Asm(args) => write!(f, "asm({})", join(args, ", ")),
+            Named(name) => write!(f, "{}", name),
         }
     }
 }
 
 fn join<T: Display>(slice: &[T], sep: &str) -> String {
-    slice
-        .iter()
-        .map(|item| format!("{}", item))
-        .join(sep)
+    slice.iter().map(|item| format!("{}", item)).join(sep)
 }
@@ -0,0 +1,305 @@
+use super::datatype::DataType;
+use super::parse::{self, ast::Span};
+use super::{check, compile, Error};
+use pest::error::{ErrorVariant, InputLocation};
+use std::fmt::Write;
+
+/// A user-facing error report that points at the part of a script's source
+/// that caused it, like so:
+///
+/// ```text
+/// error: unknown label 'loop_start'
+///   --> mod/map/script/kmr_00.txt:12:5
+///    |
+/// 12 |     goto .loop_start
+///    |     ^^^^^^^^^^^^^^^^
+///    |
+///    = note: labels are declared with `label .name`
+/// ```
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new<S: ToString>(message: S, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            message: message.to_string(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note<S: ToString>(mut self, note: S) -> Diagnostic {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Renders the report, given the path and source of the script it's about.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "error: {}", self.message).unwrap();
+
+        match self.span {
+            Some(span) => {
+                let (line_number, line_start) = source[..span.start.min(source.len())]
+                    .char_indices()
+                    .filter(|(_, c)| *c == '\n')
+                    .fold((1, 0), |(n, _), (i, _)| (n + 1, i + 1));
+
+                let line = source[line_start..].lines().next().unwrap_or("");
+                let column = source[line_start..span.start.min(source.len())]
+                    .chars()
+                    .count()
+                    + 1;
+
+                // Spans over several lines are underlined to the end of the first.
+                let line_end = line_start + line.len();
+                let underline = source[span.start.min(line_end)..span.end.min(line_end)]
+                    .chars()
+                    .count();
+
+                let gutter = " ".repeat(line_number.to_string().len());
+
+                writeln!(out, "{}--> {}:{}:{}", gutter, path, line_number, column).unwrap();
+                writeln!(out, "{} |", gutter).unwrap();
+                writeln!(out, "{} | {}", line_number, line).unwrap();
+                writeln!(
+                    out,
+                    "{} | {}{}",
+                    gutter,
+                    " ".repeat(column - 1),
+                    "^".repeat(underline.max(1))
+                )
+                .unwrap();
+
+                if !self.notes.is_empty() {
+                    writeln!(out, "{} |", gutter).unwrap();
+                }
+
+                for note in self.notes.iter() {
+                    writeln!(out, "{} = note: {}", gutter, note).unwrap();
+                }
+            }
+            None => {
+                writeln!(out, "--> {}", path).unwrap();
+
+                for note in self.notes.iter() {
+                    writeln!(out, "= note: {}", note).unwrap();
+                }
+            }
+        }
+
+        out
+    }
+}
+
+impl<'a> From<&'a parse::Error> for Diagnostic {
+    fn from(error: &parse::Error) -> Diagnostic {
+        let span = match error.location {
+            InputLocation::Pos(pos) => Span {
+                start: pos,
+                end: pos,
+            },
+            InputLocation::Span((start, end)) => Span { start, end },
+        };
+
+        let message = match &error.variant {
+            ErrorVariant::CustomError { message } => message.clone(),
+            ErrorVariant::ParsingError {
+                positives,
+                negatives,
+            } => {
+                // "a, b or c"
+                let rules = |rules: &[parse::Rule]| {
+                    let names: Vec<String> = rules
+                        .iter()
+                        .map(|rule| format!("{:?}", rule).replace('_', " "))
+                        .collect();
+
+                    match names.split_last() {
+                        Some((last, [])) => last.clone(),
+                        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+                        None => String::new(),
+                    }
+                };
+
+                match (positives.is_empty(), negatives.is_empty()) {
+                    (false, true) => format!("expected {}", rules(positives)),
+                    (true, false) => format!("unexpected {}", rules(negatives)),
+                    (false, false) => format!(
+                        "expected {}; unexpected {}",
+                        rules(positives),
+                        rules(negatives)
+                    ),
+                    (true, true) => "unexpected input".to_string(),
+                }
+            }
+        };
+
+        Diagnostic::new(message, Some(span))
+    }
+}
+
+impl<'a> From<&'a compile::Error> for Diagnostic {
+    fn from(error: &compile::Error) -> Diagnostic {
+        use compile::ErrorKind::*;
+
+        let diagnostic = Diagnostic::new(&error.kind, error.span);
+
+        let note = match error.kind {
+            UnknownIdentifier(_) => "variables must be declared with `var` before they are used",
+            UnknownLabel(_) => "labels are declared with `label .name`",
+            BadExpression(_) => {
+                "arguments must be literals or variables; assign anything else first"
+            }
+            BadCondition(_) => "conditions compare two values, e.g. `x == 1`, or test a single one",
+            AmbiguousType(_) => "declare the variable's type first, e.g. `var word_0: float`",
+            TooManyLocals(_) => "each function has 16 words and 96 flags to keep its variables in",
+            LabelOutOfRange(_) | TooManyLabels => "each function has 16 labels, numbered 0 to 15",
+            _ => return diagnostic,
+        };
+
+        diagnostic.with_note(note)
+    }
+}
+
+impl<'a> From<&'a check::Error> for Diagnostic {
+    fn from(error: &check::Error) -> Diagnostic {
+        use check::ErrorKind::*;
+
+        let mut diagnostic = Diagnostic::new(&error.kind, error.span);
+
+        let note = match error.kind {
+            UndeclaredIdentifier(_) => {
+                Some("variables must be declared with `var` before they are used")
+            }
+            TypeMismatch {
+                expected: DataType::Int,
+                found: DataType::Float,
+            }
+            | ArgumentMismatch {
+                expected: DataType::Int,
+                found: DataType::Float,
+                ..
+            } => Some("floats are not converted to ints implicitly"),
+            NotThreadable(_) => Some("only script functions can be run as threads, not asm"),
+            UnknownType(_) => {
+                Some("declare types with `enum Name { ... }`, `struct Name { ... }` or in api.json")
+            }
+            _ => None,
+        };
+
+        for note in note
+            .iter()
+            .map(|note| note.to_string())
+            .chain(error.notes.clone())
+        {
+            diagnostic = diagnostic.with_note(note);
+        }
+
+        diagnostic
+    }
+}
+
+impl<'a> From<&'a Error> for Diagnostic {
+    fn from(error: &Error) -> Diagnostic {
+        match error {
+            Error::VarDeclareTypeMismatch { span, .. } => Diagnostic::new(error, *span),
+            _ => Diagnostic::new(error, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize) -> Option<Span> {
+        Some(Span { start, end })
+    }
+
+    #[test]
+    fn line_and_column() {
+        let source = "fun main() {\n    goto .start\n}\n";
+        let diagnostic = Diagnostic::new("unknown label 'start'", span(17, 28))
+            .with_note("labels are declared with `label .name`");
+
+        assert_eq!(
+            diagnostic.render("test.txt", source),
+            concat!(
+                "error: unknown label 'start'\n",
+                " --> test.txt:2:5\n",
+                "  |\n",
+                "2 |     goto .start\n",
+                "  |     ^^^^^^^^^^^\n",
+                "  |\n",
+                "  = note: labels are declared with `label .name`\n",
+            )
+        );
+    }
+
+    #[test]
+    fn wide_gutter() {
+        let source = "\n".repeat(11) + "x = 1.5\n";
+        let diagnostic = Diagnostic::new("mismatch", span(15, 18));
+
+        assert_eq!(
+            diagnostic.render("test.txt", &source),
+            concat!(
+                "error: mismatch\n",
+                "  --> test.txt:12:5\n",
+                "   |\n",
+                "12 | x = 1.5\n",
+                "   |     ^^^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn empty_span() {
+        // Parse errors point at a single position; it still gets a caret.
+        let diagnostic = Diagnostic::new("expected expr", span(4, 4));
+
+        assert_eq!(
+            diagnostic.render("test.txt", "x = \n"),
+            concat!(
+                "error: expected expr\n",
+                " --> test.txt:1:5\n",
+                "  |\n",
+                "1 | x = \n",
+                "  |     ^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn multi_line_span() {
+        // Only the first line is shown, underlined to its end.
+        let source = "if x {\n    y = 1\n}\n";
+        let diagnostic = Diagnostic::new("bad if", span(0, source.len()));
+
+        assert_eq!(
+            diagnostic.render("test.txt", source),
+            concat!(
+                "error: bad if\n",
+                " --> test.txt:1:1\n",
+                "  |\n",
+                "1 | if x {\n",
+                "  | ^^^^^^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn no_span() {
+        let diagnostic = Diagnostic::new("something went wrong", None).with_note("a note");
+
+        assert_eq!(
+            diagnostic.render("test.txt", ""),
+            "error: something went wrong\n--> test.txt\n= note: a note\n"
+        );
+    }
+}
@@ -1,49 +1,94 @@
-use std::fmt::Write;
-use std::collections::{VecDeque, HashMap};
-use std::cell::RefCell;
+use crate::data::area::{Entrance, Map};
+use crate::rom::{ReadError, Rom, RomRead, Seek};
 use failure_derive::*;
-use crate::rom::{Rom, Map};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
 
-pub mod datatype;
+pub mod api;
+pub mod asm;
 pub mod bc;
+pub mod check;
+pub mod compile;
+pub mod datatype;
+pub mod diagnostic;
 mod globals;
 pub mod parse;
 
+use api::Api;
+use bc::{Arg, Bytecode};
 use datatype::*;
 use parse::{ast::*, Unparse};
 
-pub fn decompile_map(map: Map, _rom: &mut Rom) -> Result<String, Error> {
-    let mut scope        = Scope::new();
+pub fn decompile_map(map: &Map, rom: &mut Rom, api: &Api) -> Result<String, Error> {
+    let mut scope = Scope::new();
     let mut declarations = Vec::new();
 
     // Bring global methods into scope
-    for (ptr, name, ty) in &*globals::METHODS {
-        scope.insert_ptr(*ptr, name.to_string(), ty.clone());
-    }
+    api.declare(&mut scope, rom.region);
 
     {
-        let (loc, bc) = map.main_fun;
+        let loc = map.main_fun(rom)?;
+        let bc = Bytecode::read(rom, loc)?;
 
         // Main function takes no arguments
         scope.insert_ptr(loc.into(), "main".to_string(), DataType::Fun(vec![]));
 
         // Decompile the bytecode
-        let mut decl = Declaration::Fun {
-            name:      IdentifierOrPointer::Pointer(loc.into()),
+        let mut decl = Declaration::from(DeclarationKind::Fun {
+            name: IdentifierOrPointer::Pointer(loc.into()),
             arguments: Vec::new(),
-            block:     bc.decompile(&mut scope)?,
-        };
+            block: bc.decompile(&mut scope)?,
+        });
 
+        let mut conflicts = HashSet::new();
         for mut block in decl.inner_blocks_mut() {
             // TODO: decompile pointers within, followed by a type inference pass
 
             fix_call_arg_capture(&mut block, &scope)?;
-            infer_datatypes(&mut block, &mut scope)?;
+            infer_datatypes(&mut block, &mut scope, &mut conflicts)?;
+            name_enum_args(&mut block, &scope);
         }
 
         // TODO: replace decl.arguments with the types that were inferred
 
+        // Decompile the tables that buffers are read from.
+        let mut tables = BTreeMap::new();
+        for block in decl.inner_blocks() {
+            find_buffer_tables(block, &mut None, &mut tables);
+        }
+
         declarations.push(decl);
+
+        for (ptr, (datatype, len)) in tables {
+            if len == 0 || !map.dma.contains_vaddr(ptr) {
+                continue;
+            }
+
+            rom.file.seek(map.dma.loc_at_vaddr(ptr).into())?;
+
+            let mut items = Vec::with_capacity(len);
+            for _ in 0..len {
+                items.push(Arg::from(u32::read(rom)?).into_expression());
+            }
+
+            let name = format!("data_{:08X}", ptr);
+            scope.insert_ptr(ptr, name, DataType::Arr(Box::new(datatype.clone())));
+
+            declarations.push(Declaration::from(DeclarationKind::Data {
+                name: IdentifierOrPointer::Pointer(ptr),
+                datatype,
+                items,
+            }));
+        }
+    }
+
+    if !map.entrances.is_empty() {
+        let ptr = map.entrances_vaddr(rom)?;
+
+        if let Some(decl) = entrance_data(ptr, &map.entrances, &mut scope) {
+            declarations.push(decl);
+        }
     }
 
     // Unparse everything
@@ -56,6 +101,118 @@ pub fn decompile_map(map: Map, _rom: &mut Rom) -> Result<String, Error> {
     Ok(out)
 }
 
+/// Disassembles the main function of a map; see `Bytecode::disassemble`.
+pub fn disassemble_map(map: &Map, rom: &mut Rom) -> Result<String, Error> {
+    let loc = map.main_fun(rom)?;
+    Ok(Bytecode::read(rom, loc)?.disassemble())
+}
+
+/// Declares a map's entrance list, at `ptr`, as data of the API's `Entrance`
+/// struct. Returns None if the API doesn't declare a struct of four floats to
+/// lay the entrances out with.
+fn entrance_data(ptr: u32, entrances: &[Entrance], scope: &mut Scope) -> Option<Declaration> {
+    let fields: Vec<Identifier> = match scope.lookup_type("Entrance") {
+        Some(TypeDefinition::Struct(fields))
+            if fields.len() == 4 && fields.iter().all(|(_, ty)| *ty == DataType::Float) =>
+        {
+            fields
+                .iter()
+                .map(|(name, _)| Identifier(name.clone()))
+                .collect()
+        }
+        _ => return None,
+    };
+
+    let datatype = DataType::Named("Entrance".to_string());
+
+    let items = entrances
+        .iter()
+        .map(|entrance| {
+            let values = [entrance.x, entrance.y, entrance.z, entrance.yaw];
+
+            Expression::from(ExpressionKind::StructLiteral {
+                name: Identifier("Entrance".to_string()),
+                fields: fields
+                    .iter()
+                    .cloned()
+                    .zip(
+                        values
+                            .iter()
+                            .map(|value| Expression::from(ExpressionKind::LiteralFloat(*value))),
+                    )
+                    .collect(),
+            })
+        })
+        .collect();
+
+    scope.insert_ptr(
+        ptr,
+        "entrances".to_string(),
+        DataType::Arr(Box::new(datatype.clone())),
+    );
+
+    Some(Declaration::from(DeclarationKind::Data {
+        name: IdentifierOrPointer::Pointer(ptr),
+        datatype,
+        items,
+    }))
+}
+
+/// Finds the tables that `block` reads with buffers, and how many items of each
+/// it reads. Tables referenced by variables rather than pointers are skipped.
+/// `current` is the buffer in use and how far through it reading has got.
+///
+/// Reads are counted as they appear, so reading inside of a loop will
+/// undercount the table's length.
+fn find_buffer_tables(
+    block: &[Statement],
+    current: &mut Option<(u32, usize)>,
+    tables: &mut BTreeMap<u32, (DataType, usize)>,
+) {
+    for stmt in block.iter() {
+        match &stmt.kind {
+            StatementKind::UseBuffer { datatype, buffer } => {
+                *current = match buffer.kind {
+                    ExpressionKind::LiteralInt(ptr) => {
+                        tables.entry(ptr).or_insert((datatype.clone(), 0));
+                        Some((ptr, 0))
+                    }
+                    _ => None,
+                }
+            }
+
+            StatementKind::BufferRead { identifiers, .. } => {
+                if let Some((ptr, position)) = current {
+                    *position += identifiers.len();
+
+                    let (_, len) = tables.get_mut(ptr).unwrap();
+                    *len = (*len).max(*position);
+                }
+            }
+
+            StatementKind::BufferReadIndex {
+                index:
+                    Expression {
+                        kind: ExpressionKind::LiteralInt(index),
+                        ..
+                    },
+                ..
+            } => {
+                if let Some((ptr, _)) = current {
+                    let (_, len) = tables.get_mut(ptr).unwrap();
+                    *len = (*len).max(*index as usize + 1);
+                }
+            }
+
+            _ => (),
+        }
+
+        for inner_block in stmt.inner_blocks() {
+            find_buffer_tables(inner_block, current, tables);
+        }
+    }
+}
+
 /// Paper Mario function calls capture their environment -- that is, they take
 /// every single FunWord/FunFlag as an argument by default. This fixes method
 /// calls to do just that depending on the function signature defined in the
@@ -64,19 +221,26 @@ pub fn decompile_map(map: Map, _rom: &mut Rom) -> Result<String, Error> {
 ///
 /// For example, entry_walk takes a single argument, so the following:
 ///
-///     callback = myscript
-///     entry_walk()
+/// ```text
+/// callback = myscript
+/// entry_walk()
+/// ```
 ///
 /// Would be transformed into:
 ///
-///     callback = myscript
-///     entry_walk(callback)
+/// ```text
+/// callback = myscript
+/// entry_walk(callback)
+/// ```
 ///
 /// Note that this transformation should only be applied to decompiled ASTs, not
 /// those the user gives us; this should be a missing-method-arg error.
 fn fix_call_arg_capture(block: &mut Vec<Statement>, scope: &Scope) -> Result<(), Error> {
     for stmt in block.iter_mut() {
-        if let Statement::MethodCall { method, arguments, .. } = stmt {
+        if let StatementKind::MethodCall {
+            method, arguments, ..
+        } = &mut stmt.kind
+        {
             // Only functions capture - asm methods take args normally.
             if let Some((_, DataType::Fun(argument_types))) = method.lookup(scope) {
                 assert_eq!(arguments.len(), 0);
@@ -87,7 +251,9 @@ fn fix_call_arg_capture(block: &mut Vec<Statement>, scope: &Scope) -> Result<(),
 
                     let name = format!("{}_{:X}", globals::FUNWORD_STR, n);
 
-                    arguments.push(RefCell::new(Expression::Identifier(Identifier(name))));
+                    arguments.push(RefCell::new(
+                        ExpressionKind::Identifier(Identifier(name)).into(),
+                    ));
                 }
             }
         }
@@ -101,9 +267,59 @@ fn fix_call_arg_capture(block: &mut Vec<Statement>, scope: &Scope) -> Result<(),
     Ok(())
 }
 
+/// Replaces int literals passed as enum arguments with the variant they stand
+/// for, so that `cam_set_flag2(0, 1)` becomes `cam_set_flag2(Camera::World, 1)`.
+/// Values that aren't a variant of the enum are left as they are.
+fn name_enum_args(block: &mut Vec<Statement>, scope: &Scope) {
+    for stmt in block.iter_mut() {
+        if let StatementKind::MethodCall {
+            method, arguments, ..
+        } = &mut stmt.kind
+        {
+            if let Some((_, DataType::Fun(argument_types)))
+            | Some((_, DataType::Asm(argument_types))) = method.lookup(scope)
+            {
+                for (argument, datatype) in arguments.iter().zip(argument_types.iter()) {
+                    let enum_name = match datatype {
+                        DataType::Named(enum_name) => enum_name,
+                        _ => continue,
+                    };
+
+                    let mut argument = argument.borrow_mut();
+                    let value = match argument.kind {
+                        ExpressionKind::LiteralInt(value) => value,
+                        _ => continue,
+                    };
+
+                    let variant = scope
+                        .lookup_type(enum_name)
+                        .and_then(|definition| definition.variant_name(value));
+
+                    if let Some(variant) = variant {
+                        argument.kind = ExpressionKind::Variant {
+                            enum_name: Identifier(enum_name.clone()),
+                            variant: Identifier(variant.to_string()),
+                        };
+                    }
+                }
+            }
+        }
+
+        for mut inner_block in stmt.inner_blocks_mut() {
+            name_enum_args(&mut inner_block, scope);
+        }
+    }
+}
+
 /// Performs a single type inference pass. Replaces 'any' declarations and their
 /// respective scope mappings if their types can be inferred.
-fn infer_datatypes(block: &mut Vec<Statement>, mut scope: &mut Scope) -> Result<(), Error> {
+/// Infers the types of variables from how they're used. Variables used as
+/// more than one type are collected in `conflicts` and left as `any`.
+fn infer_datatypes(
+    block: &mut Vec<Statement>,
+    mut scope: &mut Scope,
+    conflicts: &mut HashSet<String>,
+) -> Result<(), Error> {
     let mut made_inferences = true;
 
     // This works like a bubble sort -- keep inferring types until we can't.
@@ -118,9 +334,15 @@ fn infer_datatypes(block: &mut Vec<Statement>, mut scope: &mut Scope) -> Result<
         // We iterate in reverse so we can figure out the types before we see their
         // declaration statement (once we do see it, we update its type).
         for stmt in block.iter_mut().rev() {
-            match stmt {
+            let span = stmt.span;
+
+            match &mut stmt.kind {
                 // Update var declarations with inferred types.
-                Statement::VarDeclare { datatype, identifier: Identifier(name), expression } => {
+                StatementKind::VarDeclare {
+                    datatype,
+                    identifier: Identifier(name),
+                    expression,
+                } => {
                     match scope.lookup_name_depth(&name, 0) {
                         Some(inferred_datatype) => match datatype.replace(DataType::Any) {
                             // User has left it up to the compiler to infer the
@@ -131,75 +353,161 @@ fn infer_datatypes(block: &mut Vec<Statement>, mut scope: &mut Scope) -> Result<
                                 if let DataType::Bool = inferred_datatype {
                                     // Update int literal to a bool literal.
                                     if let Some(expression) = expression {
-                                        if let Expression::LiteralInt(v) = expression.clone().into_inner() {
-                                            expression.replace(Expression::LiteralBool(v == 1));
+                                        let expression = &mut expression.borrow_mut().kind;
+                                        if let ExpressionKind::LiteralInt(v) = *expression {
+                                            *expression = ExpressionKind::LiteralBool(v == 1);
                                         }
                                     }
                                 }
-                            },
+                            }
+
+                            // Its uses disagree about its type, so it could be
+                            // anything.
+                            _ if conflicts.contains(name.as_str()) => (),
+
+                            // Already inferred by an earlier pass.
+                            declared if declared == *inferred_datatype => {
+                                datatype.replace(declared);
+                            }
+
+                            // Nothing else has told us its type, but the
+                            // declaration does.
+                            declared if *inferred_datatype == DataType::Any => {
+                                inferred.push((name.clone(), declared.clone()));
+                                datatype.replace(declared);
+                            }
 
                             // User declared the type but we inferred its use
                             // as some other type. Error.
-                            datatype => return Err(Error::VarDeclareTypeMismatch {
-                                identifier:        name.clone(),
-                                declared_datatype: datatype,
-                                inferred_datatype: inferred_datatype.clone(),
-                            }),
+                            datatype => {
+                                return Err(Error::VarDeclareTypeMismatch {
+                                    identifier: name.clone(),
+                                    declared_datatype: datatype,
+                                    inferred_datatype: inferred_datatype.clone(),
+                                    span,
+                                })
+                            }
                         },
 
                         // The variable is declared here but isn't in the current
                         // scope, so add it to the scope after this pass.
-                        None => inferred.push((name.clone(), match expression {
-                            Some(expression) => expression.borrow().infer_datatype(&scope),
-                            None             => DataType::Any,
-                        })),
+                        None => inferred.push((
+                            name.clone(),
+                            match expression {
+                                Some(expression) => expression.borrow().infer_datatype(&scope),
+                                None => DataType::Any,
+                            },
+                        )),
                     }
-                },
+                }
 
                 // Infer left-hand-type by the right-hand-type of var assignments.
-                Statement::VarAssign { identifier: Identifier(name), expression } => {
+                StatementKind::VarAssign {
+                    identifier: Identifier(name),
+                    expression,
+                } => {
                     match scope.lookup_name(name) {
                         // We only need to infer Any (i.e. unknown) types.
-                        Some(DataType::Any)
-                            => inferred.push((name.clone(), expression.borrow().infer_datatype(scope))),
+                        Some(DataType::Any) => {
+                            inferred.push((name.clone(), expression.borrow().infer_datatype(scope)))
+                        }
 
                         // Update int literal to bool literal.
                         Some(DataType::Bool) => {
-                            if let Expression::LiteralInt(v) = expression.clone().into_inner() {
-                                expression.replace(Expression::LiteralBool(v == 1));
+                            let expression = &mut expression.borrow_mut().kind;
+                            if let ExpressionKind::LiteralInt(v) = *expression {
+                                *expression = ExpressionKind::LiteralBool(v == 1);
                             }
-                        },
+                        }
 
                         _ => (),
                     }
-                },
+                }
+
+                // Arithmetic tells us whether the variable is an int or a float.
+                StatementKind::VarOpAssign {
+                    identifier: Identifier(name),
+                    datatype,
+                    ..
+                } => {
+                    if let Some(DataType::Any) = scope.lookup_name(name) {
+                        match datatype {
+                            DataType::Int | DataType::Float => {
+                                inferred.push((name.clone(), datatype.clone()))
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+
+                // Buffer reads give the type of what they read into.
+                StatementKind::BufferRead {
+                    identifiers,
+                    datatype,
+                } => {
+                    for Identifier(name) in identifiers.iter() {
+                        if let Some(DataType::Any) = scope.lookup_name(name) {
+                            inferred.push((name.clone(), datatype.clone()));
+                        }
+                    }
+                }
+                StatementKind::BufferReadIndex {
+                    identifier: Identifier(name),
+                    datatype,
+                    ..
+                } => {
+                    if let Some(DataType::Any) = scope.lookup_name(name) {
+                        inferred.push((name.clone(), datatype.clone()));
+                    }
+                }
+
+                StatementKind::ScriptExists {
+                    identifier: Identifier(name),
+                    ..
+                } => {
+                    if let Some(DataType::Any) = scope.lookup_name(name) {
+                        inferred.push((name.clone(), DataType::Bool));
+                    }
+                }
+
+                // Allocated arrays are of words, which could be anything.
+                StatementKind::ArrayDeclare {
+                    identifier: Identifier(name),
+                    ..
+                } => {
+                    if let Some(DataType::Any) = scope.lookup_name(name) {
+                        inferred.push((name.clone(), DataType::Arr(Box::new(DataType::Any))));
+                    }
+                }
 
                 // Infer types of method call arguments.
-                Statement::MethodCall { method, arguments, .. } => match method.lookup(scope) {
-                    Some((_, &DataType::Asm(ref arg_types))) |
-                    Some((_, &DataType::Fun(ref arg_types))) => {
+                StatementKind::MethodCall {
+                    method, arguments, ..
+                } => match method.lookup(scope) {
+                    Some((_, &DataType::Asm(ref arg_types)))
+                    | Some((_, &DataType::Fun(ref arg_types))) => {
                         for (ty, arg) in arg_types.iter().zip(arguments.iter()) {
-                            match arg.clone().into_inner() {
+                            match arg.clone().into_inner().kind {
                                 // Only identifiers influence type inference.
-                                Expression::Identifier(Identifier(name)) => {
+                                ExpressionKind::Identifier(Identifier(name)) => {
                                     // We only need to infer Any (i.e. unknown) types.
                                     if let Some(DataType::Any) = scope.lookup_name(&name) {
                                         // Define the inferred type!
                                         inferred.push((name.clone(), ty.clone()));
                                     }
-                                },
+                                }
 
                                 // Update int literal to bool literal.
-                                Expression::LiteralInt(v) => {
+                                ExpressionKind::LiteralInt(v) => {
                                     if let DataType::Bool = ty {
-                                        arg.replace(Expression::LiteralBool(v == 1));
+                                        arg.borrow_mut().kind = ExpressionKind::LiteralBool(v == 1);
                                     }
-                                },
+                                }
 
                                 _ => (),
                             }
                         }
-                    },
+                    }
 
                     _ => (),
                 },
@@ -208,24 +516,46 @@ fn infer_datatypes(block: &mut Vec<Statement>, mut scope: &mut Scope) -> Result<
             }
 
             for mut inner_block in stmt.inner_blocks_mut() {
-                infer_datatypes(&mut inner_block, &mut scope)?;
+                infer_datatypes(&mut inner_block, &mut scope, conflicts)?;
             }
         }
 
-        // Define the inferred types in-scope.
+        // A variable may be inferred by several statements; if they disagree,
+        // it could hold either, so is left as `any`.
+        let mut inferences: Vec<(String, DataType)> = Vec::new();
         for (name, datatype) in inferred.into_iter() {
             if let DataType::Any = datatype {
-                // ...why is this even here?
-                break
+                continue;
             }
 
-            match scope.insert_name(name, datatype) {
-                Some(DataType::Any) => (),
-                Some(_) => panic!("type inferred but var has a known type already"),
-                None => (),
+            match inferences.iter().find(|(n, _)| *n == name) {
+                Some((_, previous)) if *previous != datatype => {
+                    conflicts.insert(name);
+                }
+                Some(_) => (),
+                None => inferences.push((name, datatype)),
             }
+        }
 
-            made_inferences = true
+        // Define the inferred types in-scope.
+        for (name, datatype) in inferences.into_iter() {
+            let datatype = if conflicts.contains(&name) {
+                DataType::Any
+            } else {
+                datatype
+            };
+
+            match scope.insert_name(name.clone(), datatype.clone()) {
+                Some(ref previous) if *previous == datatype => (),
+                Some(DataType::Any) | None => made_inferences = true,
+
+                // Known to be something else already.
+                Some(_) => {
+                    conflicts.insert(name.clone());
+                    scope.insert_name(name, DataType::Any);
+                    made_inferences = true;
+                }
+            }
         }
     }
 
@@ -237,12 +567,21 @@ pub enum Error {
     #[fail(display = "failed to decompile bytecode: {}", _0)]
     BytecodeDecompile(#[fail(cause)] bc::Error),
 
-    #[fail(display = "variable '{}' declared as {} but is used as {}",
-        identifier, declared_datatype, inferred_datatype)]
+    #[fail(display = "failed to read bytecode: {}", _0)]
+    BytecodeDecode(#[fail(cause)] bc::DecodeError),
+
+    #[fail(display = "failed to read script: {}", _0)]
+    Read(#[fail(cause)] ReadError),
+
+    #[fail(
+        display = "variable '{}' declared as {} but is used as {}",
+        identifier, declared_datatype, inferred_datatype
+    )]
     VarDeclareTypeMismatch {
-        identifier:    String,
+        identifier: String,
         declared_datatype: DataType,
         inferred_datatype: DataType,
+        span: Option<Span>,
     },
 }
 
@@ -252,18 +591,42 @@ impl From<bc::Error> for Error {
     }
 }
 
+impl From<bc::DecodeError> for Error {
+    fn from(error: bc::DecodeError) -> Error {
+        Error::BytecodeDecode(error)
+    }
+}
+
+impl From<ReadError> for Error {
+    fn from(error: ReadError) -> Error {
+        Error::Read(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::Read(ReadError::Io(error))
+    }
+}
+
 /// A priority-queue mapping of (u32 -> String -> DataType); i.e. Scope provides
 /// lookups of pointer-to-name and name-to-datatype, preferring the current
 /// scope (see `push` and `pop`) when performing lookups.
 #[derive(Debug)]
 pub struct Scope {
     layers: VecDeque<(HashMap<u32, String>, HashMap<String, DataType>)>,
+
+    /// Enums and structs, which are always global.
+    types: HashMap<String, TypeDefinition>,
 }
 
 impl Scope {
     /// Creates a new Scope.
     pub fn new() -> Scope {
-        let mut scope = Scope { layers: VecDeque::new() };
+        let mut scope = Scope {
+            layers: VecDeque::new(),
+            types: HashMap::new(),
+        };
         scope.push();
         scope
     }
@@ -305,6 +668,17 @@ impl Scope {
         None
     }
 
+    /// Looks-up the pointer associated with a given name, if it has one.
+    pub fn lookup_name_ptr(&self, name: &str) -> Option<u32> {
+        for layer in self.layers.iter() {
+            if let Some((ptr, _)) = layer.0.iter().find(|(_, ptr_name)| *ptr_name == name) {
+                return Some(*ptr);
+            }
+        }
+
+        None
+    }
+
     /// Looks-up the datatype associated with a given name.
     pub fn lookup_name(&self, name: &str) -> Option<&DataType> {
         for layer in self.layers.iter() {
@@ -316,6 +690,20 @@ impl Scope {
         None
     }
 
+    /// Defines an enum or struct, returning its previous definition, if any.
+    pub fn insert_type(
+        &mut self,
+        name: String,
+        definition: TypeDefinition,
+    ) -> Option<TypeDefinition> {
+        self.types.insert(name, definition)
+    }
+
+    /// Looks-up the definition of an enum or struct.
+    pub fn lookup_type(&self, name: &str) -> Option<&TypeDefinition> {
+        self.types.get(name)
+    }
+
     /// Looks-up the datatype associated with a given name to a given depth.
     /// For example, providing a max depth of 0 would only search the current
     /// scope's mapping.
@@ -329,3 +717,85 @@ impl Scope {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Region;
+
+    /// Decompiles `asm` and infers the types of its variables.
+    fn infer(asm: &str) -> Scope {
+        let mut scope = Scope::new();
+        let mut block = Bytecode::assemble(asm)
+            .unwrap()
+            .decompile(&mut scope)
+            .unwrap();
+
+        infer_datatypes(&mut block, &mut scope, &mut HashSet::new()).unwrap();
+        scope
+    }
+
+    #[test]
+    fn infer_agreeing_uses() {
+        let scope = infer(
+            "SetFloat word_0, 1.5
+             AddFloat word_0, 2.0
+             End",
+        );
+
+        assert_eq!(scope.lookup_name("word_0"), Some(&DataType::Float));
+    }
+
+    #[test]
+    fn infer_conflicting_uses() {
+        // Read from a buffer as a float, then added to as an int.
+        let scope = infer(
+            "SetInt word_0, 0
+             UseFloatBuffer 0x80240000
+             Get1Float word_0
+             AddInt word_0, 1
+             End",
+        );
+
+        assert_eq!(scope.lookup_name("word_0"), Some(&DataType::Any));
+    }
+
+    #[test]
+    fn entrances_round_trip() {
+        let entrances = [
+            Entrance {
+                x: -135.0,
+                y: 0.0,
+                z: 42.5,
+                yaw: 90.0,
+            },
+            Entrance {
+                x: 0.1,
+                y: -20.0,
+                z: 0.0,
+                yaw: 270.0,
+            },
+        ];
+
+        let mut scope = Scope::new();
+        Api::bundled().declare(&mut scope, Region::America);
+
+        let source = entrance_data(0x8024_1000, &entrances, &mut scope)
+            .unwrap()
+            .unparse(&scope);
+        assert!(source.starts_with("data entrances: [Entrance] = [Entrance { x: -135.0"));
+
+        let script = parse::parse_script(&source).unwrap();
+        let words = compile::compile_data(&script.0[0], &scope).unwrap();
+
+        let expected: Vec<u32> = entrances
+            .iter()
+            .flat_map(|e| vec![e.x, e.y, e.z, e.yaw])
+            .map(f32::to_bits)
+            .collect();
+        assert_eq!(words, expected);
+
+        // Without the struct, there is nothing to lay the entrances out with.
+        assert!(entrance_data(0x8024_1000, &entrances, &mut Scope::new()).is_none());
+    }
+}
@@ -1,95 +1,255 @@
+pub use super::super::{datatype::DataType, Scope};
 use std::cell::RefCell;
-pub use super::super::{Scope, datatype::DataType};
 
 pub trait InnerBlocks {
     fn inner_blocks(&self) -> Vec<&Vec<Statement>>;
     fn inner_blocks_mut(&mut self) -> Vec<&mut Vec<Statement>>;
 }
 
+/// A range of bytes in a script's source, for pointing errors at. Nodes
+/// produced by the decompiler have no source, so no span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Script(pub Vec<Declaration>);
+
 #[derive(Debug, Clone)]
-pub struct Script(Vec<Declaration>);
+pub struct Declaration {
+    pub kind: DeclarationKind,
+    pub span: Option<Span>,
+}
 
 #[derive(Debug, Clone)]
-pub enum Declaration {
+pub enum DeclarationKind {
     Fun {
-        name:      IdentifierOrPointer,
+        name: IdentifierOrPointer,
         arguments: Vec<(Identifier, DataType)>,
-        block:     Vec<Statement>,
+        block: Vec<Statement>,
+    },
+
+    /// A table of data, such as one read through a buffer.
+    Data {
+        name: IdentifierOrPointer,
+        datatype: DataType, // Of each item
+        items: Vec<Expression>,
+    },
+
+    /// Names for the values of an int.
+    Enum {
+        name: Identifier,
+        variants: Vec<(Identifier, u32)>,
+    },
+
+    /// The layout of a blob of data that scripts point to, one word per field.
+    Struct {
+        name: Identifier,
+        fields: Vec<(Identifier, DataType)>,
     },
 }
 
 impl InnerBlocks for Declaration {
     fn inner_blocks(&self) -> Vec<&Vec<Statement>> {
-        match self {
-            Declaration::Fun { block, .. } => vec![block],
+        match &self.kind {
+            DeclarationKind::Fun { block, .. } => vec![block],
+            _ => vec![],
         }
     }
 
     fn inner_blocks_mut(&mut self) -> Vec<&mut Vec<Statement>> {
-        match self {
-            Declaration::Fun { block, .. } => vec![block],
+        match &mut self.kind {
+            DeclarationKind::Fun { block, .. } => vec![block],
+            _ => vec![],
         }
     }
 }
 
 #[derive(Debug, Clone)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
     Return,
 
-    Label { name: String },
-    Goto  { label_name: String },
+    Label {
+        name: String,
+    },
+    Goto {
+        label_name: String,
+    },
 
     VarAssign {
         identifier: Identifier,
         expression: RefCell<Expression>,
     },
 
+    /// `identifier op= expression`. `datatype` is `Int` or `Float`, depending
+    /// on which kind of arithmetic is performed, or `Any` if unknown.
+    VarOpAssign {
+        identifier: Identifier,
+        op: Operator,
+        expression: RefCell<Expression>,
+        datatype: DataType,
+    },
+
     VarDeclare {
-        datatype:   RefCell<DataType>,
+        datatype: RefCell<DataType>,
         identifier: Identifier,
         expression: Option<RefCell<Expression>>,
     },
 
     MethodCall {
-        method:    IdentifierOrPointer,
+        method: IdentifierOrPointer,
         arguments: Vec<RefCell<Expression>>,
         threading: MethodThreading,
     },
 
-    Wait { time: Expression, unit: TimeUnit },
+    Wait {
+        time: Expression,
+        unit: TimeUnit,
+    },
 
     If {
-        condition:   Expression,
-        block_true:  Vec<Statement>,
+        condition: Expression,
+        block_true: Vec<Statement>,
         block_false: Vec<Statement>,
     },
 
     Switch {
         expression: Expression,
-        cases:      Vec<(Case, Vec<Statement>)>,
+        cases: Vec<(Case, Vec<Statement>)>,
+    },
+
+    /// Loops `count` times, or forever if there is no count.
+    Loop {
+        count: Option<Expression>,
+        block: Vec<Statement>,
+    },
+    BreakLoop,
+
+    /// Leaves the innermost switch.
+    BreakCase,
+
+    /// Sets the buffer that `BufferRead` and `BufferReadIndex` read from.
+    /// `datatype` is `Int` or `Float`.
+    UseBuffer {
+        datatype: DataType,
+        buffer: Expression,
+    },
+
+    /// Reads the next item from the buffer into each identifier, in order.
+    BufferRead {
+        identifiers: Vec<Identifier>,
+        datatype: DataType,
+    },
+
+    /// Reads the `index`th item of the buffer into `identifier`.
+    BufferReadIndex {
+        identifier: Identifier,
+        index: Expression,
+        datatype: DataType,
+    },
+
+    /// Sets the array that `array[n]` or `flags[n]` refers to.
+    UseArray {
+        kind: ArrayKind,
+        array: Expression,
+    },
+
+    /// Allocates an array of `size` words, which becomes the current array,
+    /// and stores a pointer to it in `identifier`.
+    ArrayDeclare {
+        identifier: Identifier,
+        size: Expression,
+    },
+
+    /// Runs `script` whenever `trigger` happens to `target` (e.g. a collider).
+    /// Padlocks also give the list of `items` that can be used on them.
+    Bind {
+        script: Expression,
+        trigger: Expression,
+        target: Expression,
+        items: Option<Expression>,
+        prompt: Expression,         // Whether to show the interact prompt
+        handle: Option<Identifier>, // Set to the trigger that was bound
+    },
+    Unbind,
+
+    Kill {
+        script: Expression,
+    },
+    Jump {
+        script: Expression,
+    },
+
+    Priority {
+        priority: Expression,
+    },
+    Timescale {
+        timescale: Expression,
+    },
+    Group {
+        group: Expression,
+    },
+
+    Suspend {
+        target: ScriptTarget,
+    },
+    Resume {
+        target: ScriptTarget,
+    },
+
+    /// Sets `identifier` to whether the script with the given ID is running.
+    ScriptExists {
+        identifier: Identifier,
+        script: Expression,
+    },
+
+    /// Runs `block` in a new script alongside this one.
+    Thread {
+        kind: ThreadKind,
+        block: Vec<Statement>,
     },
 }
 
 impl InnerBlocks for Statement {
     fn inner_blocks(&self) -> Vec<&Vec<Statement>> {
-        match self {
-            Statement::If { block_true, block_false, .. } =>
-                vec![block_true, block_false],
+        match &self.kind {
+            StatementKind::If {
+                block_true,
+                block_false,
+                ..
+            } => vec![block_true, block_false],
+
+            StatementKind::Switch { cases, .. } => cases.iter().map(|(_, block)| block).collect(),
 
-            Statement::Switch { cases, .. } =>
-                cases.iter().map(|(_, block)| block).collect(),
+            StatementKind::Loop { block, .. } => vec![block],
+            StatementKind::Thread { block, .. } => vec![block],
 
             _ => vec![],
         }
     }
 
     fn inner_blocks_mut(&mut self) -> Vec<&mut Vec<Statement>> {
-        match self {
-            Statement::If { block_true, block_false, .. } =>
-                vec![block_true, block_false],
+        match &mut self.kind {
+            StatementKind::If {
+                block_true,
+                block_false,
+                ..
+            } => vec![block_true, block_false],
+
+            StatementKind::Switch { cases, .. } => {
+                cases.iter_mut().map(|(_, block)| block).collect()
+            }
 
-            Statement::Switch { cases, .. } =>
-                cases.iter_mut().map(|(_, block)| block).collect(),
+            StatementKind::Loop { block, .. } => vec![block],
+            StatementKind::Thread { block, .. } => vec![block],
 
             _ => vec![],
         }
@@ -97,7 +257,13 @@ impl InnerBlocks for Statement {
 }
 
 #[derive(Debug, Clone)]
-pub enum Expression {
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpressionKind {
     LiteralInt(u32),
     LiteralFloat(f32),
     LiteralBool(bool),
@@ -105,56 +271,104 @@ pub enum Expression {
     Identifier(Identifier),
     ArrayIndex(Identifier, u8),
 
+    /// `Enum::Variant`
+    Variant {
+        enum_name: Identifier,
+        variant: Identifier,
+    },
+
+    /// `Struct { field: value, ... }`, in any order.
+    StructLiteral {
+        name: Identifier,
+        fields: Vec<(Identifier, Expression)>,
+    },
+
     Operation {
         lhs: Box<Expression>,
-        op:  Operator,
+        op: Operator,
         rhs: Box<Expression>,
     },
+
+    /// `const x`: an argument as it is encoded, rather than the value of the
+    /// variable it names. Only assignment and `switch` take one.
+    Const(Box<Expression>),
 }
 
 impl Expression {
     pub fn infer_datatype(&self, scope: &Scope) -> DataType {
-        match self {
-            Expression::LiteralInt(_)   => DataType::Int,
-            Expression::LiteralFloat(_) => DataType::Float,
-            Expression::LiteralBool(_)  => DataType::Bool,
+        match &self.kind {
+            ExpressionKind::LiteralInt(_) => DataType::Int,
+            ExpressionKind::LiteralFloat(_) => DataType::Float,
+            ExpressionKind::LiteralBool(_) => DataType::Bool,
 
-            Expression::Identifier(Identifier(name)) => match scope.lookup_name(name) {
+            ExpressionKind::Identifier(Identifier(name)) => match scope.lookup_name(name) {
                 Some(datatype) => datatype.clone(),
-                None           => DataType::Any,
+                None => DataType::Any,
             },
 
-            Expression::ArrayIndex(Identifier(name), _) => match scope.lookup_name(name) {
+            ExpressionKind::ArrayIndex(Identifier(name), _) => match scope.lookup_name(name) {
                 Some(datatype) => match datatype {
                     DataType::Arr(item_ty) => *item_ty.clone(),
-                    _                      => DataType::Any, // ???
+                    _ => DataType::Any, // ???
                 },
-                None           => DataType::Any,
+                None => DataType::Any,
             },
 
-            Expression::Operation { lhs, op, .. } => match op {
-                Operator::Add |
-                Operator::Sub |
-                Operator::Mul |
-                Operator::Div |
-                Operator::Mod => lhs.infer_datatype(scope),
-
-                Operator::Eq  |
-                Operator::Ne  |
-                Operator::Gt  |
-                Operator::Lt  |
-                Operator::Gte |
-                Operator::Lte |
-                Operator::BitAndZ  |
-                Operator::BitAndNz |
-                Operator::And |
-                Operator::Or  |
-                Operator::Not => DataType::Bool,
-            }
+            ExpressionKind::Variant {
+                enum_name: Identifier(name),
+                ..
+            } => DataType::Named(name.clone()),
+            ExpressionKind::StructLiteral {
+                name: Identifier(name),
+                ..
+            } => DataType::Named(name.clone()),
+
+            ExpressionKind::Operation { lhs, op, .. } => match op {
+                Operator::Add | Operator::Sub | Operator::Mul | Operator::Div | Operator::Mod => {
+                    lhs.infer_datatype(scope)
+                }
+
+                Operator::BitAnd | Operator::BitOr => DataType::Int,
+
+                Operator::Eq
+                | Operator::Ne
+                | Operator::Gt
+                | Operator::Lt
+                | Operator::Gte
+                | Operator::Lte
+                | Operator::BitAndZ
+                | Operator::BitAndNz
+                | Operator::And
+                | Operator::Or
+                | Operator::Not => DataType::Bool,
+            },
+
+            ExpressionKind::Const(expression) => match expression.kind {
+                ExpressionKind::Identifier(_) | ExpressionKind::ArrayIndex(..) => DataType::Any,
+                _ => expression.infer_datatype(scope),
+            },
         }
     }
 }
 
+impl From<DeclarationKind> for Declaration {
+    fn from(kind: DeclarationKind) -> Declaration {
+        Declaration { kind, span: None }
+    }
+}
+
+impl From<StatementKind> for Statement {
+    fn from(kind: StatementKind) -> Statement {
+        Statement { kind, span: None }
+    }
+}
+
+impl From<ExpressionKind> for Expression {
+    fn from(kind: ExpressionKind) -> Expression {
+        Expression { kind, span: None }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum MethodThreading {
     No,                 // method()
@@ -162,6 +376,25 @@ pub enum MethodThreading {
     Assign(Identifier), // var = thread method()
 }
 
+#[derive(Debug, Clone)]
+pub enum ArrayKind {
+    Words, // array[n]
+    Flags, // flags[n]
+}
+
+#[derive(Debug, Clone)]
+pub enum ScriptTarget {
+    Script(Expression), // suspend script
+    Group(Expression),  // suspend group n
+    Others(Expression), // suspend others n; every script in the group but this one
+}
+
+#[derive(Debug, Clone)]
+pub enum ThreadKind {
+    Detached, // thread { ... }
+    Child,    // child thread { ... }; killed when its parent script ends
+}
+
 #[derive(Debug, Clone)]
 pub enum TimeUnit {
     Frames,
@@ -173,19 +406,45 @@ pub enum Case {
     Default,
     Test {
         operator: Operator,
-        against:  Expression,
+        against: Expression,
+    },
+
+    /// `low <= value <= high`.
+    Range {
+        low: Expression,
+        high: Expression,
     },
+
+    /// Equal to any one of the values, e.g. `case any == 1, 2`.
+    AnyOf(Vec<Expression>),
+
+    /// Equal to every one of the values, e.g. `case all == 1, 2`.
+    AllOf(Vec<Expression>),
 }
 
 #[derive(Debug, Clone)]
 pub enum Operator {
     // Arithmetic
-    Add, Sub, Mul, Div, Mod,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
 
     // Logic
-    Eq, Ne, Gt, Lt, Gte, Lte,
-    BitAndZ, BitAndNz,
-    And, Or, Not,
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Gte,
+    Lte,
+    BitAndZ,
+    BitAndNz,
+    And,
+    Or,
+    Not,
 }
 
 #[derive(Debug, Clone)]
@@ -197,15 +456,14 @@ pub enum IdentifierOrPointer {
 impl IdentifierOrPointer {
     pub fn lookup<'a>(&'a self, scope: &'a super::super::Scope) -> Option<(&'a str, &'a DataType)> {
         match self {
-            IdentifierOrPointer::Identifier(Identifier(name)) =>
-                scope.lookup_name(name).and_then(|ty| Some((name.as_str(), ty))),
+            IdentifierOrPointer::Identifier(Identifier(name)) => scope
+                .lookup_name(name)
+                .and_then(|ty| Some((name.as_str(), ty))),
 
-             // Look-up the pointer - if it has a name, use the name instead
+            // Look-up the pointer - if it has a name, use the name instead
             IdentifierOrPointer::Pointer(ptr) => match scope.lookup_ptr(*ptr) {
-                Some(name) =>
-                    scope.lookup_name(name)
-                    .and_then(|ty| Some((name, ty))),
-                None       => None,
+                Some(name) => scope.lookup_name(name).and_then(|ty| Some((name, ty))),
+                None => None,
             },
         }
     }
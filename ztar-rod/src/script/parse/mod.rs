@@ -3,14 +3,16 @@ mod unparse;
 
 pub use unparse::Unparse;
 
-// TODO
-
-/*
-use std::convert::{TryInto, TryFrom};
-use itertools::Itertools;
-use pest::{Parser, iterators::Pair};
+use ast::*;
+use lazy_static::lazy_static;
+use pest::{
+    iterators::Pair,
+    pratt_parser::{Assoc, Op, PrattParser},
+    Parser,
+};
 use pest_derive::*;
-use super::types::*;
+use std::cell::RefCell;
+use std::convert::{TryFrom, TryInto};
 
 /// Error type with associated location; e.g. `Span`. These have a very nice
 /// implementation of `std::fmt::Display`, so they're good for user-facing
@@ -19,16 +21,16 @@ pub type Error = pest::error::Error<Rule>;
 
 /// Returns an `Error` at the given `Span` with a custom message. Format strings
 /// and arguments are accepted.
-#[macro_export] macro_rules! bail_at {
+macro_rules! bail_at {
     ($span:expr, $str:expr) => {
         return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError {
             message: $str.to_string(),
-        }, $span));
+        }, $span))
     };
     ($span:expr, $fmt:expr, $($arg:tt)*) => {
         return Err(Error::new_from_span(pest::error::ErrorVariant::CustomError {
             message: format!($fmt, $($arg)*),
-        }, $span));
+        }, $span))
     };
 }
 
@@ -36,149 +38,236 @@ pub type Error = pest::error::Error<Rule>;
 #[grammar = "script/parse/grammar.pest"]
 struct ScriptParser;
 
-/// Parses a given string into a u32. Handles hex (0x) and binary (0b) too.
-pub fn parse_int(s: &str) -> Result<u32, std::num::ParseIntError> {
-    if s.starts_with("0x") {
+lazy_static! {
+    /// Operator precedence, loosest first. All operators are left-associative.
+    static ref PRATT: PrattParser<Rule> = PrattParser::new()
+        .op(Op::infix(Rule::op_lor, Assoc::Left))
+        .op(Op::infix(Rule::op_land, Assoc::Left))
+
+        .op(Op::infix(Rule::op_eq, Assoc::Left)  | Op::infix(Rule::op_ne, Assoc::Left)  |
+            Op::infix(Rule::op_lt, Assoc::Left)  | Op::infix(Rule::op_gt, Assoc::Left)  |
+            Op::infix(Rule::op_lte, Assoc::Left) | Op::infix(Rule::op_gte, Assoc::Left) |
+            Op::infix(Rule::op_and, Assoc::Left) | Op::infix(Rule::op_notand, Assoc::Left))
+
+        .op(Op::infix(Rule::op_add, Assoc::Left) | Op::infix(Rule::op_sub, Assoc::Left))
+
+        .op(Op::infix(Rule::op_mul, Assoc::Left) | Op::infix(Rule::op_div, Assoc::Left) |
+            Op::infix(Rule::op_mod, Assoc::Left));
+}
+
+/// Parses script sourcecode, as produced by `Unparse`, into its AST.
+///
+/// Names are not resolved, so pointers that were unparsed by name come back as
+/// identifiers rather than `LiteralInt`s.
+pub fn parse_script(source: &str) -> Result<Script, Error> {
+    let pair = ScriptParser::parse(Rule::script, source)?.next().unwrap();
+
+    let mut declarations = pair
+        .into_inner()
+        .filter(|pair| pair.as_rule() != Rule::EOI)
+        .map(|pair| pair.try_into())
+        .collect::<Result<Vec<Declaration>, _>>()?;
+
+    for declaration in declarations.iter_mut() {
+        for block in declaration.inner_blocks_mut() {
+            resolve_buffer_datatypes(block, &mut DataType::Int);
+        }
+    }
+
+    Ok(Script(declarations))
+}
+
+/// Parses a type, e.g. `fun(int, [float])`.
+pub fn parse_datatype(source: &str) -> Result<DataType, Error> {
+    ScriptParser::parse(Rule::datatype, source)?
+        .next()
+        .unwrap()
+        .into_inner()
+        .next()
+        .unwrap()
+        .try_into()
+}
+
+/// Buffer reads don't say what they read; it's whatever the `use` statement
+/// before them said.
+fn resolve_buffer_datatypes(block: &mut Vec<Statement>, current: &mut DataType) {
+    for stmt in block.iter_mut() {
+        match &mut stmt.kind {
+            StatementKind::UseBuffer { datatype, .. } => *current = datatype.clone(),
+
+            StatementKind::BufferRead { datatype, .. }
+            | StatementKind::BufferReadIndex { datatype, .. } => *datatype = current.clone(),
+
+            _ => (),
+        }
+
+        for block in stmt.inner_blocks_mut() {
+            resolve_buffer_datatypes(block, current);
+        }
+    }
+}
+
+impl<'a> From<pest::Span<'a>> for Span {
+    fn from(span: pest::Span<'a>) -> Span {
+        Span {
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
+/// Parses an integer literal into a u32. Handles hex (0x), binary (0b) and
+/// negatives too.
+fn parse_int(pair: &Pair<Rule>) -> Result<u32, Error> {
+    let s = pair.as_str();
+    let (negative, s) = match s.starts_with('-') {
+        true => (true, &s[1..]),
+        false => (false, s),
+    };
+
+    let magnitude = if s.starts_with("0x") {
         u32::from_str_radix(&s[2..], 16)
     } else if s.starts_with("0b") {
         u32::from_str_radix(&s[2..], 2)
     } else {
         s.parse()
+    };
+
+    match (magnitude, negative) {
+        (Ok(int), false) => Ok(int),
+        (Ok(int), true) if int <= 1 << 31 => Ok(int.wrapping_neg()),
+        _ => bail_at!(pair.as_span(), "integer out of range"),
     }
 }
 
-/// Parsing for `Type`.
-impl<'a> TryFrom<Pair<'a, Rule>> for Type {
+impl<'a> TryFrom<Pair<'a, Rule>> for Declaration {
     type Error = Error;
     fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
-        Ok(match pair.as_rule() {
-            Rule::ty => match pair.as_str() {
-                "int" => Type::Int,
-                "float" => Type::Float,
-                "bool" => Type::Bool,
-                "fun" => Type::Function,
-                "asm" => Type::Asm,
-                _ => bail_at!(pair.as_span(), "unknown type"),
-            },
-            _ => bail_at!(pair.as_span(), "expected type"),
+        let span = Some(pair.as_span().into());
+        Ok(Declaration {
+            kind: pair.try_into()?,
+            span,
         })
     }
 }
 
-/// Literal value known at compile-time.
-#[derive(Debug, PartialEq)]
-pub enum Value {
-    Int(u32),
-    //Uint(u8)
-    Float(f32),
-    Bool(bool),
-    //Function(Some(Identifier))
-    //Asm(Some(Identifier))
-}
-
-impl<'a> TryFrom<Pair<'a, Rule>> for Value {
+impl<'a> TryFrom<Pair<'a, Rule>> for DeclarationKind {
     type Error = Error;
     fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
         Ok(match pair.as_rule() {
-            Rule::literal => {
-                let pair = pair.into_inner().next().unwrap();
-                match pair.as_rule() {
-                    Rule::literal_int => Value::Int(parse_int(pair.as_str()).unwrap()),
-                    Rule::literal_float => Value::Float(pair.as_str().parse().unwrap()),
-                    Rule::literal_bool => Value::Bool(pair.as_str() == "true"),
-                    _ => bail_at!(pair.as_span(), "unimplemented literal"),
+            Rule::function => {
+                let mut pairs = pair.into_inner();
+
+                DeclarationKind::Fun {
+                    name: pairs.next().unwrap().try_into()?,
+                    arguments: pairs
+                        .next()
+                        .unwrap()
+                        .into_inner()
+                        .map(|arg| {
+                            let mut pairs = arg.into_inner();
+                            let id = pairs.next().unwrap().try_into()?;
+                            let ty = match pairs.next() {
+                                Some(ty) => ty.try_into()?,
+                                None => DataType::Any,
+                            };
+                            Ok((id, ty))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?,
+                    block: collect_stmts(pairs.next().unwrap())?,
                 }
-            },
-            _ => bail_at!(pair.as_span(), "expected literal"),
-        })
-    }
-}
+            }
 
-/// Represents a function that can be called in scripts.
-#[derive(Debug)]
-pub struct Function {
-    pub offset:    Option<u32>,
-    pub signature: Signature,
-    pub stmts:     Vec<Statement>,
-}
+            Rule::data => {
+                let mut pairs = pair.into_inner();
 
-impl<'a> TryFrom<Pair<'a, Rule>> for Function {
-    type Error = Error;
-    fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
-        Ok(match pair.as_rule() {
-            Rule::function => {
+                DeclarationKind::Data {
+                    name: pairs.next().unwrap().try_into()?,
+                    datatype: pairs.next().unwrap().try_into()?,
+                    items: pairs
+                        .next()
+                        .unwrap()
+                        .into_inner()
+                        .map(|pair| pair.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                }
+            }
+
+            Rule::enum_decl => {
                 let mut pairs = pair.into_inner();
 
-                Function {
-                    offset: None,
-                    signature: Signature {
-                        name: pairs.next().unwrap().as_str().to_string(),
-                        arguments: {
-                            let mut args = Vec::new();
-                            for (id, ty) in pairs.next().unwrap().into_inner().tuples() {
-                                args.push((
-                                    id.as_str().to_string(),
-                                    ty.try_into()?,
-                                ));
-                            }
-                            args
-                        },
-                    },
-                    stmts: collect_stmts(pairs.next().unwrap())?,
+                DeclarationKind::Enum {
+                    name: pairs.next().unwrap().try_into()?,
+                    variants: pairs
+                        .map(|variant| {
+                            let mut pairs = variant.into_inner();
+                            let id = pairs.next().unwrap().try_into()?;
+                            let value = parse_int(&pairs.next().unwrap())?;
+                            Ok((id, value))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?,
                 }
-            },
-            _ => bail_at!(pair.as_span(), "expected function"),
+            }
+
+            Rule::struct_decl => {
+                let mut pairs = pair.into_inner();
+
+                DeclarationKind::Struct {
+                    name: pairs.next().unwrap().try_into()?,
+                    fields: pairs
+                        .map(|field| {
+                            let mut pairs = field.into_inner();
+                            let id = pairs.next().unwrap().try_into()?;
+                            let ty = pairs.next().unwrap().try_into()?;
+                            Ok((id, ty))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?,
+                }
+            }
+
+            _ => bail_at!(pair.as_span(), "expected declaration"),
         })
     }
 }
 
-impl TryFrom<&str> for Function {
+/// Parsing for `DataType`.
+impl<'a> TryFrom<Pair<'a, Rule>> for DataType {
     type Error = Error;
-    fn try_from(source: &str) -> Result<Function, Error> {
-        let pair = ScriptParser::parse(Rule::function, &source)?
-            .next().unwrap();
-        Ok(pair.try_into()?)
-    }
-}
+    fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
+        let list = |pair: Option<Pair<Rule>>| match pair {
+            Some(pair) => pair
+                .into_inner()
+                .map(|pair| pair.try_into())
+                .collect::<Result<Vec<_>, _>>(),
+            None => Ok(Vec::new()),
+        };
 
-#[derive(Debug)]
-pub enum Statement {
-    Call(Call),
-    //VarDeclare { ident: Identifier, expr: Expression },
-    VarAssign  { ident: Identifier, op: char, expr: Expression },
-
-    SleepFrames { time: Expression },
-    SleepSecs   { time: Expression },
-    Return,
-    GotoLabel(String),
-    Label(String),
-
-    If {
-        cond: Expression,
-        if_true: Vec<Statement>,
-        if_false: Option<Vec<Statement>>,
-    },
-    Switch {
-        expr: Expression,
-        branches: Vec<(Vec<SwitchCase>, Vec<Statement>)>,
-    },
-}
+        Ok(match pair.as_rule() {
+            Rule::ty => pair.into_inner().next().unwrap().try_into()?,
+
+            Rule::ty_arr => DataType::Arr(Box::new(pair.into_inner().next().unwrap().try_into()?)),
+            Rule::ty_fun => DataType::Fun(list(pair.into_inner().next())?),
+            Rule::ty_asm => DataType::Asm(list(pair.into_inner().next())?),
+
+            Rule::ty_name => match pair.as_str() {
+                "any" => DataType::Any,
+                "int" => DataType::Int,
+                "float" => DataType::Float,
+                "bool" => DataType::Bool,
+                _ => bail_at!(pair.as_span(), "unknown type"),
+            },
+            Rule::ty_named => DataType::Named(pair.as_str().to_string()),
 
-#[derive(Debug)]
-pub enum SwitchCase {
-    DefaultCase,
-    Eq(Expression),
-    Ne(Expression),
-    Lt(Expression),
-    Gt(Expression),
-    Lte(Expression),
-    Gte(Expression),
+            _ => bail_at!(pair.as_span(), "expected type"),
+        })
+    }
 }
 
 fn collect_stmts(pair: Pair<Rule>) -> Result<Vec<Statement>, Error> {
     Ok(match pair.as_rule() {
-        Rule::stmt  => vec![pair.try_into()?],
-        Rule::stmts => pair.into_inner()
+        Rule::stmt => vec![pair.try_into()?],
+        Rule::stmts => pair
+            .into_inner()
             .map(|pair| pair.try_into())
             .collect::<Result<Vec<_>, _>>()?,
         _ => bail_at!(pair.as_span(), "expected statement(s)"),
@@ -188,298 +277,772 @@ fn collect_stmts(pair: Pair<Rule>) -> Result<Vec<Statement>, Error> {
 impl<'a> TryFrom<Pair<'a, Rule>> for Statement {
     type Error = Error;
     fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
-        Ok(match pair.as_rule() {
-            Rule::stmt => {
-                let pair = pair.into_inner().next().unwrap();
-                match pair.as_rule() {
-                    // Function or asm call.
-                    Rule::call => Statement::Call(pair.try_into()?),
-
-                    /*
-                    // Variable declarations include their type followed by their
-                    // identifier. They can optionally be followed by an initial value;
-                    // if a value is not specified we choose a sensible default.
-                    Rule::var_declare => {
-                        let mut pairs = pair.into_inner();
-                        let ty: Type = pairs.next().unwrap().try_into()?;
-
-                        Statement::VarDeclare {
-                            ident: pairs.next().unwrap().try_into()?,
-                            expr: if let Some(expr) = pairs.next() {
-                                expr.try_into()?
-                            } else {
-                                Expression::Literal(ty.default_value())
-                            }
-                        }
-                    },
-                    */
+        let span = Some(pair.as_span().into());
+        Ok(Statement {
+            kind: pair.try_into()?,
+            span,
+        })
+    }
+}
 
-                    Rule::var_assign => {
-                        let mut pairs = pair.into_inner();
+impl<'a> TryFrom<Pair<'a, Rule>> for StatementKind {
+    type Error = Error;
+    fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
+        let pair = match pair.as_rule() {
+            Rule::stmt => pair.into_inner().next().unwrap(),
+            _ => bail_at!(pair.as_span(), "expected statement"),
+        };
 
-                        Statement::VarAssign {
-                            ident: pairs.next().unwrap().try_into()?,
-                            op: pairs.next().unwrap().as_str().chars().next().unwrap(),
-                            expr: pairs.next().unwrap().try_into()?,
-                        }
-                    },
+        let span = pair.as_span();
+        let rule = pair.as_rule();
+        let mut pairs = pair.into_inner();
 
-                    Rule::sleep_stmt => {
-                        let mut pairs = pair.into_inner();
-                        let expr = pairs.next().unwrap().try_into()?;
-                        let time_unit = pairs.next().unwrap();
-                        match time_unit.as_str() {
-                            "secs" => Statement::SleepSecs   { time: expr },
-                            "" => Statement::SleepFrames { time: expr },
-                            _ => bail_at!(time_unit.as_span(), "unknown time unit"),
-                        }
+        Ok(match rule {
+            Rule::call => {
+                let mut threading = MethodThreading::No;
+
+                let mut method = pairs.next().unwrap();
+                if method.as_rule() == Rule::call_handle {
+                    let handle = method.into_inner().next().unwrap().try_into()?;
+                    threading = MethodThreading::Assign(handle);
+
+                    pairs.next().unwrap(); // thread
+                    method = pairs.next().unwrap();
+                } else if method.as_rule() == Rule::thread {
+                    threading = MethodThreading::Yes;
+                    method = pairs.next().unwrap();
+                }
+
+                StatementKind::MethodCall {
+                    method: method.try_into()?,
+                    arguments: pairs
+                        .next()
+                        .unwrap()
+                        .into_inner()
+                        .map(|pair| Ok(RefCell::new(pair.try_into()?)))
+                        .collect::<Result<Vec<_>, Error>>()?,
+                    threading,
+                }
+            }
+
+            Rule::var_assign => {
+                let identifier = pairs.next().unwrap().try_into()?;
+                let op = pairs.next().unwrap();
+                let expression = RefCell::new(pairs.next().unwrap().try_into()?);
+
+                let op = match op.as_str() {
+                    "=" => {
+                        return Ok(StatementKind::VarAssign {
+                            identifier,
+                            expression,
+                        })
+                    }
+                    "+=" => Operator::Add,
+                    "-=" => Operator::Sub,
+                    "*=" => Operator::Mul,
+                    "/=" => Operator::Div,
+                    "%=" => Operator::Mod,
+                    "&=" => Operator::BitAnd,
+                    "|=" => Operator::BitOr,
+                    _ => bail_at!(op.as_span(), "unknown assignment operator"),
+                };
+
+                StatementKind::VarOpAssign {
+                    identifier,
+                    op,
+                    expression,
+                    datatype: DataType::Any,
+                }
+            }
+
+            Rule::buffer_read => {
+                let mut identifiers = Vec::new();
+                let mut index = None;
+
+                for pair in pairs {
+                    match pair.as_rule() {
+                        Rule::id => identifiers.push(pair.try_into()?),
+                        _ => index = Some(pair.try_into()?),
+                    }
+                }
+
+                match index {
+                    None => StatementKind::BufferRead {
+                        identifiers,
+                        datatype: DataType::Any,
                     },
+                    Some(index) if identifiers.len() == 1 => StatementKind::BufferReadIndex {
+                        identifier: identifiers.pop().unwrap(),
+                        index,
+                        datatype: DataType::Any,
+                    },
+                    Some(_) => bail_at!(span, "only one variable can be read from a buffer index"),
+                }
+            }
+
+            Rule::exists_stmt => StatementKind::ScriptExists {
+                identifier: pairs.next().unwrap().try_into()?,
+                script: pairs.next().unwrap().try_into()?,
+            },
 
-                    Rule::return_stmt => Statement::Return,
-
-                    Rule::goto_stmt =>
-                        Statement::GotoLabel(pair.into_inner().next().unwrap().as_str().to_string()),
-                    Rule::label_stmt =>
-                        Statement::Label(pair.into_inner().next().unwrap().as_str().to_string()),
-
-                    // If-else statement. Else-ifs are parsed as nested stmts.
-                    Rule::if_stmt => {
-                        let mut pairs = pair.into_inner();
-                        Statement::If {
-                            cond: pairs.next().unwrap().try_into()?,
-                            if_true: collect_stmts(pairs.next().unwrap())?,
-                            if_false: match pairs.next() {
-                                Some(pair) => Some(collect_stmts(pair)?),
-                                None => None,
-                            },
+            Rule::bind_stmt => {
+                let mut handle = None;
+                let mut items = None;
+                let mut prompt = Expression::from(ExpressionKind::LiteralInt(0));
+                let mut exprs = Vec::new();
+
+                for pair in pairs {
+                    match pair.as_rule() {
+                        Rule::bind_handle => {
+                            handle = Some(pair.into_inner().next().unwrap().try_into()?)
                         }
-                    },
+                        Rule::bind_items => {
+                            items = Some(pair.into_inner().next().unwrap().try_into()?)
+                        }
+                        Rule::bind_prompt => {
+                            prompt = pair.into_inner().next().unwrap().try_into()?
+                        }
+                        _ => exprs.push(pair.try_into()?),
+                    }
+                }
 
-                    // Switch-case statement.
-                    Rule::switch_stmt => {
-                        let mut pairs = pair.into_inner();
-                        let value = pairs.next().unwrap().try_into()?;
-                        let mut branches = Vec::new();
-                        let mut seen_default = false;
-
-                        for pair in pairs {
-                            let mut cases = Vec::new();
-                            let mut stmts;
-
-                            // Each branch can have multiple clauses attached,
-                            // so we consume all of them.
-                            let mut switch_case = pair.into_inner();
-                            loop {
-                                let clause = switch_case.next().unwrap();
-
-                                // Error if we've already consumed a default
-                                // case yet there are clauses after it.
-                                if seen_default {
-                                    bail_at!(clause.as_span(), "unreachable case as there is a `default` above it");
-                                }
-
-                                cases.push(match clause.as_rule() {
-                                    Rule::default_case => {
-                                        seen_default = true;
-                                        SwitchCase::DefaultCase
-                                    },
-                                    Rule::op => {
-                                        let op = clause.into_inner().next().unwrap();
-                                        let expr = switch_case.next().unwrap().try_into()?;
-                                        match op.as_rule() {
-                                            Rule::op_eq  => SwitchCase::Eq(expr),
-                                            Rule::op_ne  => SwitchCase::Ne(expr),
-                                            Rule::op_lt  => SwitchCase::Lt(expr),
-                                            Rule::op_gt  => SwitchCase::Gt(expr),
-                                            Rule::op_lte => SwitchCase::Lte(expr),
-                                            Rule::op_gte => SwitchCase::Gte(expr),
-                                            _ => bail_at!(op.as_span(), "invalid comparison operation"),
-                                        }
-                                    },
-                                    _ => unreachable!(),
-                                });
-
-                                // Further cases are nested within the next
-                                // pair. If we detect statements instead, we
-                                // consume those and end the loop.
-                                let next_pair = switch_case.next().unwrap();
-                                match next_pair.as_rule() {
-                                    Rule::switch_case =>
-                                        switch_case = next_pair.into_inner(),
-                                    _ => {
-                                        stmts = collect_stmts(next_pair)?;
-                                        break;
-                                    },
-                                }
-                            }
+                let mut exprs = exprs.into_iter();
+                StatementKind::Bind {
+                    script: exprs.next().unwrap(),
+                    trigger: exprs.next().unwrap(),
+                    target: exprs.next().unwrap(),
+                    items,
+                    prompt,
+                    handle,
+                }
+            }
+
+            Rule::var_declare => {
+                let identifier = pairs.next().unwrap().try_into()?;
+                let mut datatype = DataType::Any;
+                let mut expression = None;
+
+                for pair in pairs {
+                    match pair.as_rule() {
+                        Rule::ty => datatype = pair.try_into()?,
+                        _ => expression = Some(RefCell::new(pair.try_into()?)),
+                    }
+                }
+
+                StatementKind::VarDeclare {
+                    datatype: RefCell::new(datatype),
+                    identifier,
+                    expression,
+                }
+            }
+
+            Rule::array_declare => StatementKind::ArrayDeclare {
+                identifier: pairs.next().unwrap().try_into()?,
+                size: pairs.next().unwrap().try_into()?,
+            },
+
+            Rule::use_buffer_stmt => StatementKind::UseBuffer {
+                datatype: pairs.next().unwrap().try_into()?,
+                buffer: pairs.next().unwrap().try_into()?,
+            },
+
+            Rule::use_array_stmt => StatementKind::UseArray {
+                kind: match pairs.next().unwrap().as_str() {
+                    "flags" => ArrayKind::Flags,
+                    _ => ArrayKind::Words,
+                },
+                array: pairs.next().unwrap().try_into()?,
+            },
+
+            Rule::wait_stmt => {
+                let unit = match pairs.next().unwrap().as_str() {
+                    "waitsecs" => TimeUnit::Seconds,
+                    _ => TimeUnit::Frames,
+                };
+
+                StatementKind::Wait {
+                    time: pairs.next().unwrap().try_into()?,
+                    unit,
+                }
+            }
+
+            Rule::return_stmt => StatementKind::Return,
+
+            Rule::goto_stmt => StatementKind::Goto {
+                label_name: label_name(pairs.next().unwrap()),
+            },
+            Rule::label_stmt => StatementKind::Label {
+                name: label_name(pairs.next().unwrap()),
+            },
+
+            // If-else statement. Else-ifs are parsed as nested stmts.
+            Rule::if_stmt => StatementKind::If {
+                condition: pairs.next().unwrap().try_into()?,
+                block_true: collect_stmts(pairs.next().unwrap())?,
+                block_false: match pairs.next() {
+                    Some(pair) => collect_stmts(pair)?,
+                    None => Vec::new(),
+                },
+            },
 
-                            branches.push((cases, stmts));
+            Rule::switch_stmt => {
+                let expression = pairs.next().unwrap().try_into()?;
+                let mut cases = Vec::new();
+                let mut seen_default = false;
+
+                for switch_case in pairs {
+                    let mut pairs = switch_case.into_inner();
+                    let clause = pairs.next().unwrap();
+
+                    // Error if we've already consumed a default case yet
+                    // there are cases after it.
+                    if seen_default {
+                        bail_at!(
+                            clause.as_span(),
+                            "unreachable case as there is a `default` above it"
+                        );
+                    }
+
+                    let case = match clause.as_rule() {
+                        Rule::default_case => {
+                            seen_default = true;
+                            Case::Default
                         }
+                        Rule::case_range => {
+                            let mut pairs = clause.into_inner();
+                            Case::Range {
+                                low: pairs.next().unwrap().try_into()?,
+                                high: pairs.next().unwrap().try_into()?,
+                            }
+                        }
+                        Rule::case_group => {
+                            let mut pairs = clause.into_inner();
+                            let kind = pairs.next().unwrap();
+                            let values =
+                                pairs.map(Expression::try_from).collect::<Result<_, _>>()?;
 
-                        Statement::Switch {
-                            expr: value,
-                            branches: branches,
+                            match kind.as_str() {
+                                "all" => Case::AllOf(values),
+                                _ => Case::AnyOf(values),
+                            }
+                        }
+                        _ => {
+                            let mut pairs = clause.into_inner();
+                            Case::Test {
+                                operator: pairs.next().unwrap().try_into()?,
+                                against: pairs.next().unwrap().try_into()?,
+                            }
                         }
+                    };
+
+                    cases.push((case, collect_stmts(pairs.next().unwrap())?));
+                }
+
+                StatementKind::Switch { expression, cases }
+            }
+
+            Rule::thread_stmt => {
+                let pair = pairs.next().unwrap();
+
+                match pair.as_rule() {
+                    Rule::child => StatementKind::Thread {
+                        kind: ThreadKind::Child,
+                        block: collect_stmts(pairs.next().unwrap())?,
                     },
+                    _ => StatementKind::Thread {
+                        kind: ThreadKind::Detached,
+                        block: collect_stmts(pair)?,
+                    },
+                }
+            }
 
-                    _ => bail_at!(pair.as_span(), "unimplemented statement"),
+            Rule::loop_stmt => {
+                let pair = pairs.next().unwrap();
+
+                match pair.as_rule() {
+                    Rule::expr => StatementKind::Loop {
+                        count: Some(pair.try_into()?),
+                        block: collect_stmts(pairs.next().unwrap())?,
+                    },
+                    _ => StatementKind::Loop {
+                        count: None,
+                        block: collect_stmts(pair)?,
+                    },
                 }
+            }
+            Rule::break_stmt => match pairs.next() {
+                Some(_) => StatementKind::BreakCase,
+                None => StatementKind::BreakLoop,
             },
 
-            _ => bail_at!(pair.as_span(), "expected statement"),
+            Rule::unbind_stmt => StatementKind::Unbind,
+
+            Rule::kill_stmt => StatementKind::Kill {
+                script: pairs.next().unwrap().try_into()?,
+            },
+            Rule::jump_stmt => StatementKind::Jump {
+                script: pairs.next().unwrap().try_into()?,
+            },
+            Rule::priority_stmt => StatementKind::Priority {
+                priority: pairs.next().unwrap().try_into()?,
+            },
+            Rule::timescale_stmt => StatementKind::Timescale {
+                timescale: pairs.next().unwrap().try_into()?,
+            },
+            Rule::group_stmt => StatementKind::Group {
+                group: pairs.next().unwrap().try_into()?,
+            },
+
+            Rule::suspend_stmt => StatementKind::Suspend {
+                target: pairs.next().unwrap().try_into()?,
+            },
+            Rule::resume_stmt => StatementKind::Resume {
+                target: pairs.next().unwrap().try_into()?,
+            },
+
+            _ => bail_at!(span, "unimplemented statement"),
         })
     }
 }
 
-#[derive(Debug)]
-pub enum Expression {
-    Literal(Value),
-    Identifier(Identifier),
-    Call(Call),
+fn label_name(pair: Pair<Rule>) -> String {
+    pair.into_inner().next().unwrap().as_str().to_string()
+}
 
-    // Prefix operators
-    Negate(Box<Expression>),
+impl<'a> TryFrom<Pair<'a, Rule>> for ScriptTarget {
+    type Error = Error;
+    fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
+        let mut pairs = pair.into_inner();
+        let first = pairs.next().unwrap();
+
+        Ok(match first.as_rule() {
+            Rule::target_kind => {
+                let expression = pairs.next().unwrap().try_into()?;
 
-    // Infix operators (see impl TryFrom for precedence/associativity)
-    Equal { lhs: Box<Expression>, rhs: Box<Expression> },
-    NotEqual { lhs: Box<Expression>, rhs: Box<Expression> },
+                match first.as_str() {
+                    "group" => ScriptTarget::Group(expression),
+                    _ => ScriptTarget::Others(expression),
+                }
+            }
+            _ => ScriptTarget::Script(first.try_into()?),
+        })
+    }
 }
 
 impl<'a> TryFrom<Pair<'a, Rule>> for Expression {
     type Error = Error;
     fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
-        use pest::prec_climber::*;
+        fn term(pair: Pair<Rule>) -> Result<Expression, Error> {
+            let span = Some(pair.as_span().into());
 
-        // note: this is magic
+            let kind = match pair.as_rule() {
+                Rule::paren_expr => return pair.into_inner().next().unwrap().try_into(),
+                Rule::const_expr => {
+                    ExpressionKind::Const(Box::new(term(pair.into_inner().next().unwrap())?))
+                }
 
-        let climber = PrecClimber::new(vec![
-            // == !=
-            Operator::new(Rule::op_eq, Assoc::Left) | Operator::new(Rule::op_ne, Assoc::Left),
-        ]);
+                Rule::arr_access => {
+                    let mut pairs = pair.into_inner();
+                    let id = pairs.next().unwrap().try_into()?;
+                    let index = pairs.next().unwrap();
 
-        fn term(pair: Pair<Rule>) -> Result<Expression, Error> {
-            Ok(match pair.as_rule() {
-                Rule::term => term(pair.into_inner().next().unwrap())?,
-                Rule::paren_expr => pair.into_inner().next().unwrap().try_into()?,
+                    match parse_int(&index)? {
+                        i if i <= 0xFF => ExpressionKind::ArrayIndex(id, i as u8),
+                        _ => bail_at!(index.as_span(), "array index out of range"),
+                    }
+                }
+
+                Rule::variant => {
+                    let mut pairs = pair.into_inner();
 
-                Rule::literal    => Expression::Literal(pair.try_into()?),
-                Rule::id         => Expression::Identifier(pair.try_into()?),
-                Rule::arr_access => Expression::Identifier(pair.try_into()?),
-                Rule::call       => Expression::Call(pair.try_into()?),
+                    ExpressionKind::Variant {
+                        enum_name: pairs.next().unwrap().try_into()?,
+                        variant: pairs.next().unwrap().try_into()?,
+                    }
+                }
 
-                Rule::negate => Expression::Negate(Box::new(term(pair.into_inner().next().unwrap())?)),
+                Rule::struct_literal => {
+                    let mut pairs = pair.into_inner();
+
+                    ExpressionKind::StructLiteral {
+                        name: pairs.next().unwrap().try_into()?,
+                        fields: pairs
+                            .map(|field| {
+                                let mut pairs = field.into_inner();
+                                let id = pairs.next().unwrap().try_into()?;
+                                let value = pairs.next().unwrap().try_into()?;
+                                Ok((id, value))
+                            })
+                            .collect::<Result<Vec<_>, Error>>()?,
+                    }
+                }
+
+                Rule::id => ExpressionKind::Identifier(pair.try_into()?),
+                Rule::literal_int => ExpressionKind::LiteralInt(parse_int(&pair)?),
+                Rule::literal_float => ExpressionKind::LiteralFloat(pair.as_str().parse().unwrap()),
+                Rule::literal_bool => ExpressionKind::LiteralBool(pair.as_str() == "true"),
 
                 _ => bail_at!(pair.as_span(), "unimplemented term: {}", pair),
-            })
+            };
+
+            Ok(Expression { kind, span })
         }
 
-        let infix =|lhs: Result<Expression, Error>, op: Pair<Rule>, rhs: Result<Expression, Error>| {
-            Ok(match op.as_rule() {
-                Rule::op_eq => Expression::Equal {
-                    lhs: Box::new(lhs?),
-                    rhs: Box::new(rhs?),
-                },
-                Rule::op_ne => Expression::NotEqual {
-                    lhs: Box::new(lhs?),
-                    rhs: Box::new(rhs?),
-                },
-                _ => bail_at!(op.as_span(), "unimplemented operator"),
-            })
-        };
+        let infix =
+            |lhs: Result<Expression, Error>, op: Pair<Rule>, rhs: Result<Expression, Error>| {
+                let (lhs, rhs) = (lhs?, rhs?);
+
+                // Operations span from the start of their lhs to the end of their rhs.
+                let span = match (lhs.span, rhs.span) {
+                    (Some(lhs), Some(rhs)) => Some(Span {
+                        start: lhs.start,
+                        end: rhs.end,
+                    }),
+                    _ => None,
+                };
+
+                Ok(Expression {
+                    kind: ExpressionKind::Operation {
+                        lhs: Box::new(lhs),
+                        op: op.try_into()?,
+                        rhs: Box::new(rhs),
+                    },
+                    span,
+                })
+            };
 
         match pair.as_rule() {
-            Rule::expr => climber.climb(pair.into_inner(), term, infix),
+            Rule::expr => PRATT
+                .map_primary(term)
+                .map_infix(infix)
+                .parse(pair.into_inner()),
             _ => bail_at!(pair.as_span(), "expected expression: {}", pair),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Call {
-    target: CallTarget,
-    args:   Vec<Expression>,
-    thread: bool,
-}
-
-#[derive(Debug)]
-pub enum CallTarget {
-    Known(Identifier),
-    Foreign(u32),
-}
-
-impl<'a> TryFrom<Pair<'a, Rule>> for Call {
+impl<'a> TryFrom<Pair<'a, Rule>> for Operator {
     type Error = Error;
     fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
         Ok(match pair.as_rule() {
-            Rule::call => {
-                let mut pairs = pair.into_inner();
-                let mut is_threaded = false;
-
-                Call {
-                    target: {
-                        let target = {
-                            let pair = pairs.next().unwrap();
-                            match pair.as_rule() {
-                                Rule::thread => {
-                                    is_threaded = true;
-                                    pairs.next().unwrap()
-                                },
-                                _ => pair,
-                            }
-                        };
+            Rule::op_add => Operator::Add,
+            Rule::op_sub => Operator::Sub,
+            Rule::op_mul => Operator::Mul,
+            Rule::op_div => Operator::Div,
+            Rule::op_mod => Operator::Mod,
 
-                        match target.as_rule() {
-                            Rule::id          => CallTarget::Known(target.try_into()?),
-                            Rule::literal_int => CallTarget::Foreign(parse_int(target.as_str()).unwrap()),
-                            _                 => unreachable!(),
-                        }
-                    },
-                    args: match pairs.next() {
-                        Some(pair) => pair.into_inner().map(|pair| pair.try_into()).collect::<Result<Vec<_>, _>>()?,
-                        None => Vec::new(),
-                    },
-                    thread: is_threaded,
-                }
-            },
-            _ => bail_at!(pair.as_span(), "expected call"),
+            Rule::op_eq => Operator::Eq,
+            Rule::op_ne => Operator::Ne,
+            Rule::op_lt => Operator::Lt,
+            Rule::op_gt => Operator::Gt,
+            Rule::op_lte => Operator::Lte,
+            Rule::op_gte => Operator::Gte,
+
+            Rule::op_and => Operator::BitAndZ,
+            Rule::op_notand => Operator::BitAndNz,
+
+            Rule::op_land => Operator::And,
+            Rule::op_lor => Operator::Or,
+
+            _ => bail_at!(pair.as_span(), "expected operator"),
         })
     }
 }
 
-#[derive(Debug)]
-pub enum Identifier {
-    Named(String),
-    ArrayAccess(String, Box<Expression>),
+impl<'a> TryFrom<Pair<'a, Rule>> for IdentifierOrPointer {
+    type Error = Error;
+    fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
+        Ok(match pair.as_rule() {
+            Rule::id => IdentifierOrPointer::Identifier(pair.try_into()?),
+            Rule::literal_int => IdentifierOrPointer::Pointer(parse_int(&pair)?),
+            _ => bail_at!(pair.as_span(), "expected identifier or pointer"),
+        })
+    }
 }
 
 impl<'a> TryFrom<Pair<'a, Rule>> for Identifier {
     type Error = Error;
     fn try_from(pair: Pair<'a, Rule>) -> Result<Self, Error> {
         Ok(match pair.as_rule() {
-            Rule::id => {
-                let name = pair.as_str();
+            // Array elements can be assigned to like any other variable.
+            Rule::id | Rule::arr_access => Identifier(pair.as_str().to_string()),
+            _ => bail_at!(pair.as_span(), "expected identifier"),
+        })
+    }
+}
 
-                match name {
-                    "if" | "else" | "switch" | "thread" | "asm" | "fun" |
-                    "int" | "float" | "any" | "bool" | "null" | "uint"
-                    => bail_at!(pair.as_span(), "'{}' is a reserved word", name),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Scope;
+
+    fn parse_fun(source: &str) -> Vec<Statement> {
+        let script = parse_script(&format!("fun test() {{\n{}\n}}", source)).unwrap();
+
+        match script.0.into_iter().next().unwrap().kind {
+            DeclarationKind::Fun { block, .. } => block,
+            kind => panic!("expected a fun, got {:?}", kind),
+        }
+    }
+
+    fn parse_expression(source: &str) -> Expression {
+        match parse_fun(&format!("x = {}", source)).remove(0).kind {
+            StatementKind::VarAssign { expression, .. } => expression.into_inner(),
+            kind => panic!("expected an assignment, got {:?}", kind),
+        }
+    }
+
+    fn parse_error(source: &str) -> String {
+        parse_script(source).unwrap_err().to_string()
+    }
+
+    /// Writes an expression with every operation bracketed.
+    fn bracketed(expression: Expression) -> String {
+        match expression.kind {
+            ExpressionKind::Operation { lhs, op, rhs } => format!(
+                "({} {} {})",
+                bracketed(*lhs),
+                op.unparse(&Scope::new()),
+                bracketed(*rhs),
+            ),
+            kind => Expression::from(kind).unparse(&Scope::new()),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let cases = [
+            ("1 + 2 * 3", "(1 + (2 * 3))"),
+            ("1 * 2 + 3", "((1 * 2) + 3)"),
+            ("1 - 2 - 3", "((1 - 2) - 3)"),
+            ("1 / 2 % 3", "((1 / 2) % 3)"),
+            ("(1 + 2) * 3", "((1 + 2) * 3)"),
+            ("a + 1 == b * 2", "((a + 1) == (b * 2))"),
+            ("a & 4 !& 8", "((a & 4) !& 8)"),
+            (
+                "a == 1 or b < 2 and c >= 3",
+                "((a == 1) or ((b < 2) and (c >= 3)))",
+            ),
+        ];
+
+        for (source, expected) in cases.iter() {
+            assert_eq!(bracketed(parse_expression(source)), *expected, "{}", source);
+        }
+    }
 
-                    _ => Identifier::Named(name.to_string()),
+    #[test]
+    fn unparse_brackets() {
+        for source in ["(1 + 2) * 3", "1 - (2 - 3)", "1 - 2 - 3", "(a or b) and c"].iter() {
+            assert_eq!(parse_expression(source).unparse(&Scope::new()), *source);
+        }
+    }
+
+    #[test]
+    fn else_chain() {
+        let mut block = parse_fun(concat!(
+            "if a == 1 {\n",
+            "    b = 1\n",
+            "} else if a == 2 {\n",
+            "    b = 2\n",
+            "} else {\n",
+            "    b = 3\n",
+            "}",
+        ));
+        assert_eq!(block.len(), 1);
+
+        match block.remove(0).kind {
+            StatementKind::If {
+                block_true,
+                mut block_false,
+                ..
+            } => {
+                assert_eq!(block_true.len(), 1);
+                assert_eq!(block_false.len(), 1);
+
+                match block_false.remove(0).kind {
+                    StatementKind::If {
+                        block_true,
+                        block_false,
+                        ..
+                    } => {
+                        assert_eq!(block_true.len(), 1);
+                        assert_eq!(block_false.len(), 1);
+                    }
+                    kind => panic!("expected an else if, got {:?}", kind),
                 }
+            }
+            kind => panic!("expected an if, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn if_block_is_not_struct() {
+        let block = parse_fun(concat!(
+            "if x {\n",
+            "    y = 1\n",
+            "}\n",
+            "var p = Point { x: 1, y: 2 }",
+        ));
+
+        match &block[0].kind {
+            StatementKind::If {
+                condition:
+                    Expression {
+                        kind: ExpressionKind::Identifier(Identifier(name)),
+                        ..
+                    },
+                block_true,
+                ..
+            } => {
+                assert_eq!(name, "x");
+                assert_eq!(block_true.len(), 1);
+            }
+            kind => panic!("expected an if, got {:?}", kind),
+        }
+
+        match &block[1].kind {
+            StatementKind::VarDeclare {
+                expression: Some(expression),
+                ..
+            } => match &expression.borrow().kind {
+                ExpressionKind::StructLiteral { fields, .. } => assert_eq!(fields.len(), 2),
+                kind => panic!("expected a struct literal, got {:?}", kind),
             },
-            Rule::arr_access => {
-                let mut pairs = pair.into_inner();
-                Identifier::ArrayAccess(pairs.next().unwrap().as_str().to_string(), Box::new(pairs.next().unwrap().try_into()?))
-            },
-            _ => bail_at!(pair.as_span(), "expected identifier"),
-        })
+            kind => panic!("expected a declaration, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn case_after_default() {
+        let error = parse_error(concat!(
+            "fun test() {\n",
+            "    switch x {\n",
+            "        default {\n",
+            "            break case\n",
+            "        }\n",
+            "        case == 1 {\n",
+            "            break case\n",
+            "        }\n",
+            "    }\n",
+            "}",
+        ));
+
+        assert!(error.contains("unreachable case"), "{}", error);
+    }
+
+    #[test]
+    fn int_range() {
+        let int = |source| match parse_expression(source).kind {
+            ExpressionKind::LiteralInt(int) => int,
+            kind => panic!("expected an int, got {:?}", kind),
+        };
+
+        assert_eq!(int("4294967295"), 0xFFFF_FFFF);
+        assert_eq!(int("0xFFFFFFFF"), 0xFFFF_FFFF);
+        assert_eq!(int("-2147483648"), 0x8000_0000);
+        assert_eq!(int("-0b1"), 0xFFFF_FFFF);
+
+        for source in ["4294967296", "0x100000000", "-2147483649"].iter() {
+            let error = parse_error(&format!("fun test() {{\nx = {}\n}}", source));
+            assert!(error.contains("integer out of range"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn unparse_round_trip() {
+        let source = concat!(
+            "fun test(a: int, b: float) {\n",
+            "    return\n",
+            "    label .top\n",
+            "    goto .top\n",
+            "    a = 1\n",
+            "    a += 2\n",
+            "    a = const b\n",
+            "    var c = 3\n",
+            "    var d: float = 1.5\n",
+            "    var e: bool\n",
+            "    helper(a, b)\n",
+            "    a = thread helper(1, 2.0)\n",
+            "    thread 0x80240000()\n",
+            "    wait 1\n",
+            "    waitsecs 2\n",
+            "    if a == 1 {\n",
+            "        a = 2\n",
+            "    } else if a > 1 {\n",
+            "        a = 3\n",
+            "    } else {\n",
+            "        a = 4\n",
+            "    }\n",
+            "    switch a {\n",
+            "        case == 1 {\n",
+            "            break case\n",
+            "        }\n",
+            "        case 2..4 {\n",
+            "            a = 5\n",
+            "        }\n",
+            "        case any == 5, 6 {\n",
+            "            a = 6\n",
+            "        }\n",
+            "        case all == 7 {\n",
+            "            a = 7\n",
+            "        }\n",
+            "        default {\n",
+            "            a = 8\n",
+            "        }\n",
+            "    }\n",
+            "    loop 3 {\n",
+            "        break\n",
+            "    }\n",
+            "    loop {\n",
+            "        wait 1\n",
+            "    }\n",
+            "    use int buffer table\n",
+            "    a, c = buffer\n",
+            "    d = buffer[1]\n",
+            "    use array arr\n",
+            "    use flags arr\n",
+            "    array arr[4]\n",
+            "    h = bind script on 1 2 with items prompt 3\n",
+            "    bind script on 1 2\n",
+            "    unbind\n",
+            "    kill script\n",
+            "    jump script\n",
+            "    priority 1\n",
+            "    timescale 2.0\n",
+            "    group 3\n",
+            "    suspend group 1\n",
+            "    resume others 2\n",
+            "    suspend script\n",
+            "    a = exists script\n",
+            "    thread {\n",
+            "        wait 1\n",
+            "    }\n",
+            "    child thread {\n",
+            "        wait 2\n",
+            "    }\n",
+            "}\n",
+            "data table: [int] = [1, 2]\n",
+            "enum Kind {\n",
+            "    A = 0\n",
+            "    B = 1\n",
+            "}\n",
+            "struct Point {\n",
+            "    x: int\n",
+            "    y: float\n",
+            "}\n",
+        );
+
+        let unparse = |source: &str| -> String {
+            parse_script(source)
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|declaration| format!("{}\n", declaration.unparse(&Scope::new())))
+                .collect()
+        };
+
+        let unparsed = unparse(source);
+        assert_eq!(unparsed, source);
+        assert_eq!(unparse(&unparsed), source);
     }
 }
-/*
-#[derive(Debug, PartialEq, Eq)]
-pub struct Variable {
-    pub offset: Option<u32>,
-    pub ty:     Type,
-}
-*/
-*/
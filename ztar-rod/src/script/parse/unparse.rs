@@ -1,7 +1,7 @@
-use std::cell::RefCell;
-use itertools::Itertools;
-use super::ast::*;
 use super::super::Scope;
+use super::ast::*;
+use itertools::Itertools;
+use std::cell::RefCell;
 
 /// Trait for structs that can produce a script sourcecode equivalent of
 /// themselves, given a Scope to look-up pointers.
@@ -18,97 +18,179 @@ fn indent(string: String) -> String {
 }
 
 impl Unparse for Declaration {
+    fn unparse(self, scope: &Scope) -> String {
+        self.kind.unparse(scope)
+    }
+}
+
+impl Unparse for DeclarationKind {
     fn unparse(self, scope: &Scope) -> String {
         match self {
-            Declaration::Fun { name, arguments, block } =>
-                format!("fun {}({}) {{\n{}\n}}",
-                    name.unparse(scope),
-                    arguments
+            DeclarationKind::Fun {
+                name,
+                arguments,
+                block,
+            } => format!(
+                "fun {}({}) {{\n{}\n}}",
+                name.unparse(scope),
+                arguments
+                    .into_iter()
+                    .map(|(id, ty)| format!("{}: {}", id.unparse(scope), ty.unparse(scope)))
+                    .join(", "),
+                indent(block.unparse(scope)),
+            ),
+
+            DeclarationKind::Data {
+                name,
+                datatype,
+                items,
+            } => format!(
+                "data {}: [{}] = [{}]",
+                name.unparse(scope),
+                datatype.unparse(scope),
+                items.into_iter().map(|item| item.unparse(scope)).join(", "),
+            ),
+
+            DeclarationKind::Enum { name, variants } => format!(
+                "enum {} {{\n{}\n}}",
+                name.unparse(scope),
+                indent(
+                    variants
+                        .into_iter()
+                        .map(|(id, value)| format!("{} = {}", id.unparse(scope), value as i32))
+                        .join("\n")
+                ),
+            ),
+
+            DeclarationKind::Struct { name, fields } => format!(
+                "struct {} {{\n{}\n}}",
+                name.unparse(scope),
+                indent(
+                    fields
                         .into_iter()
                         .map(|(id, ty)| format!("{}: {}", id.unparse(scope), ty.unparse(scope)))
-                        .join(", "),
-                    indent(block.unparse(scope)),
+                        .join("\n")
                 ),
+            ),
         }
     }
 }
 
 impl Unparse for Statement {
+    fn unparse(self, scope: &Scope) -> String {
+        self.kind.unparse(scope)
+    }
+}
+
+impl Unparse for StatementKind {
     fn unparse(self, scope: &Scope) -> String {
         match self {
-            Statement::Return => "return".to_string(),
+            StatementKind::Return => "return".to_string(),
 
-            Statement::Label { name } => format!("label .{}", name),
-            Statement::Goto { label_name } => format!("goto .{}", label_name),
+            StatementKind::Label { name } => format!("label .{}", name),
+            StatementKind::Goto { label_name } => format!("goto .{}", label_name),
 
-            Statement::VarAssign { identifier, expression } =>
-                format!("{} = {}",
-                    identifier.unparse(scope),
-                    expression.into_inner().unparse(scope),
-                ),
+            StatementKind::VarAssign {
+                identifier,
+                expression,
+            } => format!(
+                "{} = {}",
+                identifier.unparse(scope),
+                expression.into_inner().unparse(scope),
+            ),
+
+            StatementKind::VarOpAssign {
+                identifier,
+                op,
+                expression,
+                ..
+            } => format!(
+                "{} {}= {}",
+                identifier.unparse(scope),
+                op.unparse(scope),
+                expression.into_inner().unparse(scope),
+            ),
 
-            Statement::VarDeclare { identifier, datatype, expression } => match datatype.into_inner() {
+            StatementKind::VarDeclare {
+                identifier,
+                datatype,
+                expression,
+            } => match datatype.into_inner() {
                 DataType::Any => match expression {
-                    Some(expression) => format!("var {} = {}",
+                    Some(expression) => format!(
+                        "var {} = {}",
                         identifier.unparse(scope),
                         expression.into_inner().unparse(scope),
                     ),
-                    None => format!("var {}",
-                        identifier.unparse(scope),
-                    ),
+                    None => format!("var {}", identifier.unparse(scope),),
                 },
                 datatype => match expression {
-                    Some(expression) => format!("var {}: {} = {}",
+                    Some(expression) => format!(
+                        "var {}: {} = {}",
                         identifier.unparse(scope),
                         datatype.unparse(scope),
                         expression.into_inner().unparse(scope),
                     ),
-                    None => format!("var {}: {}",
+                    None => format!(
+                        "var {}: {}",
                         identifier.unparse(scope),
                         datatype.unparse(scope),
                     ),
                 },
             },
 
-            Statement::MethodCall { method, arguments, threading } => match threading {
-                MethodThreading::Assign(ident) => format!("{} = thread {}({})",
+            StatementKind::MethodCall {
+                method,
+                arguments,
+                threading,
+            } => match threading {
+                MethodThreading::Assign(ident) => format!(
+                    "{} = thread {}({})",
                     ident.unparse(scope),
                     method.unparse(scope),
                     arguments.unparse(scope),
                 ),
-                MethodThreading::Yes => format!("thread {}({})",
-                    method.unparse(scope),
-                    arguments.unparse(scope),
-                ),
-                MethodThreading::No => format!("{}({})",
+                MethodThreading::Yes => format!(
+                    "thread {}({})",
                     method.unparse(scope),
                     arguments.unparse(scope),
                 ),
+                MethodThreading::No => {
+                    format!("{}({})", method.unparse(scope), arguments.unparse(scope),)
+                }
             },
 
-            Statement::Wait { time, unit } => match unit {
-                TimeUnit::Frames  => format!("wait {}",     time.unparse(scope)),
+            StatementKind::Wait { time, unit } => match unit {
+                TimeUnit::Frames => format!("wait {}", time.unparse(scope)),
                 TimeUnit::Seconds => format!("waitsecs {}", time.unparse(scope)),
             },
 
-            Statement::If { condition, block_true, mut block_false } => match block_false.len() {
+            StatementKind::If {
+                condition,
+                block_true,
+                mut block_false,
+            } => match block_false.len() {
                 // No else block
-                0 => format!("if {} {{\n{}\n}}",
+                0 => format!(
+                    "if {} {{\n{}\n}}",
                     condition.unparse(scope),
                     indent(block_true.unparse(scope)),
                 ),
 
                 // Only one stmt in else block
-                1 => match block_false[0] {
+                1 => match block_false[0].kind {
                     // 'else if' contraction
-                    Statement::If { .. } => format!("if {} {{\n{}\n}} else {}",
+                    StatementKind::If { .. } => format!(
+                        "if {} {{\n{}\n}} else {}",
                         condition.unparse(scope),
                         indent(block_true.unparse(scope)),
-                        block_false.pop().unwrap().unparse(scope), // pop because we require ownership
+                        // pop because we require ownership
+                        block_false.pop().unwrap().unparse(scope),
                     ),
 
                     // Treat else block as normal
-                    _ => format!("if {} {{\n{}\n}} else {{\n{}\n}}",
+                    _ => format!(
+                        "if {} {{\n{}\n}} else {{\n{}\n}}",
                         condition.unparse(scope),
                         indent(block_true.unparse(scope)),
                         indent(block_false.unparse(scope)),
@@ -116,36 +198,172 @@ impl Unparse for Statement {
                 },
 
                 // Has else block
-                _ => format!("if {} {{\n{}\n}} else {{\n{}\n}}",
+                _ => format!(
+                    "if {} {{\n{}\n}} else {{\n{}\n}}",
                     condition.unparse(scope),
                     indent(block_true.unparse(scope)),
                     indent(block_false.unparse(scope)),
                 ),
             },
 
-            Statement::Switch { expression, cases } => format!("switch {} {{\n{}\n}}",
+            StatementKind::Switch { expression, cases } => format!(
+                "switch {} {{\n{}\n}}",
                 expression.unparse(scope),
-                indent(cases
-                    .into_iter()
-                    .map(|(case, block)| match case {
-                        Case::Default => format!("default {{\n{}\n}}", indent(block.unparse(scope))),
-                        Case::Test { operator, against } => format!("case {} {} {{\n{}\n}}",
-                            operator.unparse(scope),
-                            against.unparse(scope),
-                            indent(block.unparse(scope)),
-                        ),
-                    })
-                    .join("\n")
+                indent(
+                    cases
+                        .into_iter()
+                        .map(|(case, block)| match case {
+                            Case::Default =>
+                                format!("default {{\n{}\n}}", indent(block.unparse(scope))),
+                            Case::Test { operator, against } => format!(
+                                "case {} {} {{\n{}\n}}",
+                                operator.unparse(scope),
+                                against.unparse(scope),
+                                indent(block.unparse(scope)),
+                            ),
+                            Case::Range { low, high } => format!(
+                                "case {}..{} {{\n{}\n}}",
+                                low.unparse(scope),
+                                high.unparse(scope),
+                                indent(block.unparse(scope)),
+                            ),
+                            Case::AnyOf(values) => format!(
+                                "case any == {} {{\n{}\n}}",
+                                values
+                                    .into_iter()
+                                    .map(|value| value.unparse(scope))
+                                    .join(", "),
+                                indent(block.unparse(scope)),
+                            ),
+                            Case::AllOf(values) => format!(
+                                "case all == {} {{\n{}\n}}",
+                                values
+                                    .into_iter()
+                                    .map(|value| value.unparse(scope))
+                                    .join(", "),
+                                indent(block.unparse(scope)),
+                            ),
+                        })
+                        .join("\n")
                 ),
             ),
+
+            StatementKind::Loop { count, block } => match count {
+                Some(count) => format!(
+                    "loop {} {{\n{}\n}}",
+                    count.unparse(scope),
+                    indent(block.unparse(scope)),
+                ),
+                None => format!("loop {{\n{}\n}}", indent(block.unparse(scope))),
+            },
+            StatementKind::BreakLoop => "break".to_string(),
+            StatementKind::BreakCase => "break case".to_string(),
+
+            StatementKind::UseBuffer { datatype, buffer } => format!(
+                "use {} buffer {}",
+                datatype.unparse(scope),
+                buffer.unparse(scope),
+            ),
+            StatementKind::BufferRead { identifiers, .. } => format!(
+                "{} = buffer",
+                identifiers
+                    .into_iter()
+                    .map(|identifier| identifier.unparse(scope))
+                    .join(", "),
+            ),
+            StatementKind::BufferReadIndex {
+                identifier, index, ..
+            } => format!(
+                "{} = buffer[{}]",
+                identifier.unparse(scope),
+                index.unparse(scope),
+            ),
+
+            StatementKind::UseArray { kind, array } => match kind {
+                ArrayKind::Words => format!("use array {}", array.unparse(scope)),
+                ArrayKind::Flags => format!("use flags {}", array.unparse(scope)),
+            },
+            StatementKind::ArrayDeclare { identifier, size } => format!(
+                "array {}[{}]",
+                identifier.unparse(scope),
+                size.unparse(scope),
+            ),
+
+            StatementKind::Bind {
+                script,
+                trigger,
+                target,
+                items,
+                prompt,
+                handle,
+            } => {
+                let mut out = String::new();
+
+                if let Some(handle) = handle {
+                    out.push_str(&format!("{} = ", handle.unparse(scope)));
+                }
+
+                out.push_str(&format!(
+                    "bind {} on {} {}",
+                    script.unparse(scope),
+                    trigger.unparse(scope),
+                    target.unparse(scope),
+                ));
+
+                if let Some(items) = items {
+                    out.push_str(&format!(" with {}", items.unparse(scope)));
+                }
+
+                match prompt {
+                    Expression {
+                        kind: ExpressionKind::LiteralInt(0),
+                        ..
+                    } => (),
+                    prompt => out.push_str(&format!(" prompt {}", prompt.unparse(scope))),
+                }
+
+                out
+            }
+            StatementKind::Unbind => "unbind".to_string(),
+
+            StatementKind::Kill { script } => format!("kill {}", script.unparse(scope)),
+            StatementKind::Jump { script } => format!("jump {}", script.unparse(scope)),
+
+            StatementKind::Priority { priority } => format!("priority {}", priority.unparse(scope)),
+            StatementKind::Timescale { timescale } => {
+                format!("timescale {}", timescale.unparse(scope))
+            }
+            StatementKind::Group { group } => format!("group {}", group.unparse(scope)),
+
+            StatementKind::Suspend { target } => format!("suspend {}", target.unparse(scope)),
+            StatementKind::Resume { target } => format!("resume {}", target.unparse(scope)),
+
+            StatementKind::ScriptExists { identifier, script } => format!(
+                "{} = exists {}",
+                identifier.unparse(scope),
+                script.unparse(scope),
+            ),
+
+            StatementKind::Thread { kind, block } => match kind {
+                ThreadKind::Detached => format!("thread {{\n{}\n}}", indent(block.unparse(scope))),
+                ThreadKind::Child => {
+                    format!("child thread {{\n{}\n}}", indent(block.unparse(scope)))
+                }
+            },
         }
     }
 }
 
 impl Unparse for Expression {
+    fn unparse(self, scope: &Scope) -> String {
+        self.kind.unparse(scope)
+    }
+}
+
+impl Unparse for ExpressionKind {
     fn unparse(self, scope: &Scope) -> String {
         match self {
-            Expression::LiteralInt(maybe_ptr) => {
+            ExpressionKind::LiteralInt(maybe_ptr) => {
                 if maybe_ptr & 0xFFFF_0000 == 0x8024_0000 {
                     // It's probably a script pointer; try to give it its name
                     if let Some(name) = scope.lookup_ptr(maybe_ptr) {
@@ -155,18 +373,60 @@ impl Unparse for Expression {
 
                 // It's an int. Format it as signed.
                 format!("{}", maybe_ptr as i32)
-            },
-            Expression::LiteralFloat(f) => format!("{}", f),
-            Expression::LiteralBool(b)  => format!("{}", b),
+            }
+            ExpressionKind::LiteralFloat(f) => format!("{:?}", f), // Always has a decimal point
+            ExpressionKind::LiteralBool(b) => format!("{}", b),
 
-            Expression::Identifier(id)      => id.unparse(scope),
-            Expression::ArrayIndex(id, idx) => format!("{}[{}]", id.unparse(scope), idx),
+            ExpressionKind::Identifier(id) => id.unparse(scope),
+            ExpressionKind::ArrayIndex(id, idx) => format!("{}[{}]", id.unparse(scope), idx),
 
-            Expression::Operation { lhs, op, rhs } => format!("{} {} {}",
-                lhs.unparse(scope),
-                op.unparse(scope),
-                rhs.unparse(scope),
+            ExpressionKind::Variant { enum_name, variant } => {
+                format!("{}::{}", enum_name.unparse(scope), variant.unparse(scope))
+            }
+
+            ExpressionKind::StructLiteral { name, fields } => format!(
+                "{} {{ {} }}",
+                name.unparse(scope),
+                fields
+                    .into_iter()
+                    .map(|(id, value)| format!("{}: {}", id.unparse(scope), value.unparse(scope)))
+                    .join(", "),
             ),
+
+            ExpressionKind::Operation { lhs, op, rhs } => {
+                // Operations are left-associative, so an operand on the right
+                // needs brackets even if it binds as tightly.
+                let lhs = match &lhs.kind {
+                    ExpressionKind::Operation { op: lhs_op, .. }
+                        if precedence(lhs_op) < precedence(&op) =>
+                    {
+                        format!("({})", lhs.unparse(scope))
+                    }
+                    _ => lhs.unparse(scope),
+                };
+                let rhs = match &rhs.kind {
+                    ExpressionKind::Operation { op: rhs_op, .. }
+                        if precedence(rhs_op) <= precedence(&op) =>
+                    {
+                        format!("({})", rhs.unparse(scope))
+                    }
+                    _ => rhs.unparse(scope),
+                };
+
+                format!("{} {} {}", lhs, op.unparse(scope), rhs)
+            }
+
+            ExpressionKind::Const(expression) => format!("const {}", expression.unparse(scope)),
+        }
+    }
+}
+
+impl Unparse for ScriptTarget {
+    fn unparse(self, scope: &Scope) -> String {
+        match self {
+            ScriptTarget::Script(script) => script.unparse(scope),
+            ScriptTarget::Group(group) => format!("group {}", group.unparse(scope)),
+            ScriptTarget::Others(group) => format!("others {}", group.unparse(scope)),
         }
     }
 }
@@ -176,10 +436,10 @@ impl Unparse for IdentifierOrPointer {
         match self {
             IdentifierOrPointer::Identifier(ident) => ident.unparse(scope),
 
-             // Look-up the pointer - if it has a name, use the name instead
+            // Look-up the pointer - if it has a name, use the name instead
             IdentifierOrPointer::Pointer(ptr) => match scope.lookup_ptr(ptr) {
                 Some(name) => name.to_string(),
-                None       => format!("0x{:X}", ptr),
+                None => format!("0x{:X}", ptr),
             },
         }
     }
@@ -193,24 +453,50 @@ impl Unparse for Operator {
             Operator::Mul => "*".to_string(),
             Operator::Div => "/".to_string(),
             Operator::Mod => "%".to_string(),
+            Operator::BitAnd => "&".to_string(),
+            Operator::BitOr => "|".to_string(),
 
-            Operator::Eq  => "==".to_string(),
-            Operator::Ne  => "!=".to_string(),
-            Operator::Lt  => "<".to_string(),
-            Operator::Gt  => ">".to_string(),
-            Operator::Lte => ">=".to_string(),
-            Operator::Gte => "<=".to_string(),
+            Operator::Eq => "==".to_string(),
+            Operator::Ne => "!=".to_string(),
+            Operator::Lt => "<".to_string(),
+            Operator::Gt => ">".to_string(),
+            Operator::Lte => "<=".to_string(),
+            Operator::Gte => ">=".to_string(),
 
-            Operator::BitAndZ  => "&".to_string(),
+            Operator::BitAndZ => "&".to_string(),
             Operator::BitAndNz => "!&".to_string(),
 
             Operator::And => "and".to_string(),
-            Operator::Or  => "or".to_string(),
+            Operator::Or => "or".to_string(),
             Operator::Not => "not".to_string(),
         }
     }
 }
 
+/// How tightly an operator binds, as the parser has it; higher is tighter.
+fn precedence(op: &Operator) -> u8 {
+    match op {
+        Operator::Or => 0,
+        Operator::And => 1,
+
+        Operator::Eq
+        | Operator::Ne
+        | Operator::Lt
+        | Operator::Gt
+        | Operator::Lte
+        | Operator::Gte
+        | Operator::BitAndZ
+        | Operator::BitAndNz
+        | Operator::BitAnd
+        | Operator::BitOr
+        | Operator::Not => 2,
+
+        Operator::Add | Operator::Sub => 3,
+
+        Operator::Mul | Operator::Div | Operator::Mod => 4,
+    }
+}
+
 impl Unparse for Identifier {
     fn unparse(self, _: &Scope) -> String {
         self.0
@@ -226,18 +512,14 @@ impl Unparse for DataType {
 // Block
 impl Unparse for Vec<Statement> {
     fn unparse(self, scope: &Scope) -> String {
-        self
-            .into_iter()
-            .map(|stmt| stmt.unparse(scope))
-            .join("\n")
+        self.into_iter().map(|stmt| stmt.unparse(scope)).join("\n")
     }
 }
 
 // Argument list
 impl Unparse for Vec<RefCell<Expression>> {
     fn unparse(self, scope: &Scope) -> String {
-        self
-            .into_iter()
+        self.into_iter()
             .map(|arg| arg.into_inner().unparse(scope))
             .join(", ")
     }